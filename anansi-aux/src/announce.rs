@@ -0,0 +1,143 @@
+use std::cell::Cell;
+
+use wasm_bindgen::JsCast;
+use wasm_bindgen::closure::Closure;
+use web_sys::Element;
+
+use crate::{DOCUMENT, WINDOW};
+
+/// Hides the region visually while keeping it in the accessibility tree.
+const HIDDEN: &str = "position: absolute; width: 1px; height: 1px; margin: -1px; padding: 0; overflow: hidden; clip: rect(0, 0, 0, 0); white-space: nowrap; border: 0";
+
+/// How long the region stays empty before a message is written, long enough
+/// for screen readers to notice the change when the same message repeats.
+const CLEAR_MS: i32 = 100;
+
+thread_local! {
+    /// The latest announcement of each region, polite then assertive.
+    static GENERATION: [Cell<u32>; 2] = const { [Cell::new(0), Cell::new(0)] };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Politeness {
+    /// Read once the user is idle.
+    Polite,
+    /// Interrupts whatever is being read.
+    Assertive,
+}
+
+impl Politeness {
+    fn live(self) -> &'static str {
+        match self {
+            Self::Polite => "polite",
+            Self::Assertive => "assertive",
+        }
+    }
+    fn role(self) -> &'static str {
+        match self {
+            Self::Polite => "status",
+            Self::Assertive => "alert",
+        }
+    }
+    fn slot(self) -> usize {
+        match self {
+            Self::Polite => 0,
+            Self::Assertive => 1,
+        }
+    }
+    fn id(self) -> String {
+        format!("anansi-announcer-{}", self.live())
+    }
+}
+
+fn region(politeness: Politeness) -> Element {
+    DOCUMENT.with(|document| {
+        let id = politeness.id();
+        if let Some(el) = document.get_element_by_id(&id) {
+            return el;
+        }
+        let el = document.create_element("div").unwrap();
+        el.set_id(&id);
+        el.set_attribute("aria-live", politeness.live()).unwrap();
+        el.set_attribute("role", politeness.role()).unwrap();
+        el.set_attribute("aria-atomic", "true").unwrap();
+        el.set_attribute("style", HIDDEN).unwrap();
+        document.body().expect("expected body").append_child(&el).unwrap();
+        el
+    })
+}
+
+/// Clears the live region of `politeness` and writes `message` to it shortly
+/// after. When announcements to one region come in faster than that, only the
+/// latest one is written.
+pub fn announce(message: &str, politeness: Politeness) {
+    let el = region(politeness);
+    el.set_text_content(Some(""));
+    let slot = politeness.slot();
+    let generation = GENERATION.with(|g| {
+        g[slot].set(g[slot].get().wrapping_add(1));
+        g[slot].get()
+    });
+    let message = message.to_string();
+    let cb = Closure::once_into_js(move || {
+        if GENERATION.with(|g| g[slot].get()) == generation {
+            el.set_text_content(Some(&message));
+        }
+    });
+    WINDOW.with(|w| w.set_timeout_with_callback_and_timeout_and_arguments_0(cb.unchecked_ref(), CLEAR_MS).unwrap());
+}
+
+/// Creates both live regions if the page doesn't have them yet and returns
+/// the announcer. Screen readers only pick up changes to regions that were
+/// already in the page, so call it before the first announcement is due.
+pub fn use_announcer() -> impl Fn(&str, Politeness) {
+    region(Politeness::Polite);
+    region(Politeness::Assertive);
+    announce
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn region_attrs() {
+        assert_eq!(Politeness::Polite.id(), "anansi-announcer-polite");
+        assert_eq!((Politeness::Assertive.live(), Politeness::Assertive.role()), ("assertive", "alert"));
+    }
+}
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod dom_tests {
+    use wasm_bindgen_test::*;
+    use super::*;
+    use crate::fixtures::sleep;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn text(politeness: Politeness) -> String {
+        DOCUMENT.with(|document| document.get_element_by_id(&politeness.id()).unwrap().text_content().unwrap_or_default())
+    }
+
+    #[wasm_bindgen_test]
+    async fn announced() {
+        let announce = use_announcer();
+        let polite = DOCUMENT.with(|document| document.get_element_by_id(&Politeness::Polite.id())).unwrap();
+        assert_eq!(polite.get_attribute("aria-live").as_deref(), Some("polite"));
+        assert!(DOCUMENT.with(|document| document.get_element_by_id(&Politeness::Assertive.id())).is_some());
+        announce("Saved", Politeness::Polite);
+        assert_eq!(text(Politeness::Polite), "");
+        sleep(CLEAR_MS * 2).await;
+        assert_eq!(text(Politeness::Polite), "Saved");
+        announce("Saved", Politeness::Polite);
+        assert_eq!(text(Politeness::Polite), "");
+        sleep(CLEAR_MS * 2).await;
+        assert_eq!(text(Politeness::Polite), "Saved");
+        announce("1 result", Politeness::Polite);
+        announce("Connection lost", Politeness::Assertive);
+        announce("2 results", Politeness::Polite);
+        sleep(CLEAR_MS * 2).await;
+        assert_eq!(text(Politeness::Polite), "2 results");
+        assert_eq!(text(Politeness::Assertive), "Connection lost");
+    }
+}
@@ -0,0 +1,72 @@
+use std::any::Any;
+use std::cell::RefMut;
+use std::ops::{Deref, DerefMut};
+
+#[cfg(debug_assertions)]
+use std::cell::RefCell;
+#[cfg(debug_assertions)]
+use std::collections::HashMap;
+#[cfg(debug_assertions)]
+use std::panic::Location;
+
+use crate::ScopeVar;
+
+#[cfg(debug_assertions)]
+thread_local! {
+    static SITES: RefCell<HashMap<usize, &'static Location<'static>>> = RefCell::new(HashMap::new());
+}
+
+/// A mutable borrow of a scope variable. In debug builds it remembers where it
+/// was taken, so a conflicting borrow can say who is holding the object.
+pub struct ScopeMut<'a> {
+    inner: RefMut<'a, dyn Any>,
+    #[cfg(debug_assertions)]
+    key: usize,
+}
+
+impl Deref for ScopeMut<'_> {
+    type Target = dyn Any;
+    fn deref(&self) -> &Self::Target {
+        &*self.inner
+    }
+}
+
+impl DerefMut for ScopeMut<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut *self.inner
+    }
+}
+
+#[cfg(debug_assertions)]
+impl Drop for ScopeMut<'_> {
+    fn drop(&mut self) {
+        SITES.with(|s| s.borrow_mut().remove(&self.key));
+    }
+}
+
+#[cfg(debug_assertions)]
+#[track_caller]
+pub(crate) fn borrow_mut(var: &ScopeVar) -> ScopeMut<'_> {
+    let key = std::rc::Rc::as_ptr(&var.rf) as *const () as usize;
+    let caller = Location::caller();
+    match var.rf.try_borrow_mut() {
+        Ok(inner) => {
+            SITES.with(|s| s.borrow_mut().insert(key, caller));
+            ScopeMut {inner, key}
+        }
+        Err(_) => {
+            let site = SITES.with(|s| s.borrow().get(&key).map(|l| format!(", held since {}", l))).unwrap_or_default();
+            panic!("{}{}", conflict(&var.component, var.obj, var.index), site);
+        }
+    }
+}
+
+#[cfg(not(debug_assertions))]
+pub(crate) fn borrow_mut(var: &ScopeVar) -> ScopeMut<'_> {
+    ScopeMut {inner: var.rf.borrow_mut()}
+}
+
+pub(crate) fn conflict(component: &str, obj: usize, index: Option<usize>) -> String {
+    let item = index.map(|i| format!(" item {}", i)).unwrap_or_default();
+    format!("object {}{} of component {} is already borrowed", obj, item, if component.is_empty() { "?" } else { component })
+}
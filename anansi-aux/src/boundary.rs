@@ -0,0 +1,141 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::panic::{AssertUnwindSafe, Location, catch_unwind};
+
+use crate::{Rsx, Comp, ERROR_HANDLER, TRACKING, component_index, owner, panic_message, suspense};
+
+/// What a boundary has to put back when rendering its children fails.
+struct Active {
+    key: String,
+    tracking: usize,
+    suspended: usize,
+    owner: Option<owner::Owner>,
+}
+
+thread_local! {
+    static ACTIVE: RefCell<Vec<Active>> = const { RefCell::new(vec![]) };
+    static POISONED: RefCell<BTreeMap<String, String>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+/// Renders `children`, or `fallback` with the panic message if rendering them
+/// panicked. The boundary tries `children` again on every render, so once the
+/// signals behind a transient error change the subtree recovers by itself.
+///
+/// Catching the panic needs the app built with `panic = "unwind"`. On
+/// `wasm32-unknown-unknown` a panic aborts the render that raised it, so the
+/// panic hook `setup` installs marks the boundary instead, and the fallback
+/// shows the next time its component renders. A boundary is told apart from
+/// others by its component and its place in the source.
+#[track_caller]
+pub fn error_boundary(children: impl FnOnce() -> Vec<Rsx>, fallback: impl FnOnce(&str) -> Rsx) -> Rsx {
+    let key = format!("{}@{}", component_index().unwrap_or_default(), Location::caller());
+    if let Some(message) = POISONED.with(|p| p.borrow_mut().remove(&key)) {
+        return caught(&message, fallback);
+    }
+    let active = Active {key: key.clone(), tracking: TRACKING.with(|t| t.borrow().len()), suspended: suspense::depth(), owner: owner::current()};
+    let (tracking, suspended, current) = (active.tracking, active.suspended, active.owner.clone());
+    ACTIVE.with(|a| a.borrow_mut().push(active));
+    let result = catch_unwind(AssertUnwindSafe(children));
+    ACTIVE.with(|a| a.borrow_mut().pop());
+    match result {
+        Ok(children) => Rsx::Component(Comp {children}),
+        Err(payload) => {
+            POISONED.with(|p| p.borrow_mut().remove(&key));
+            restore(tracking, suspended, current);
+            caught(&panic_message(&*payload), fallback)
+        }
+    }
+}
+
+fn restore(tracking: usize, suspended: usize, current: Option<owner::Owner>) {
+    TRACKING.with(|t| t.borrow_mut().truncate(tracking));
+    suspense::unwind(suspended);
+    owner::set_current(current);
+}
+
+fn caught(message: &str, fallback: impl FnOnce(&str) -> Rsx) -> Rsx {
+    match ERROR_HANDLER.with(|h| h.borrow().clone()) {
+        Some(handler) => handler(message),
+        None => {
+            crate::log!("error boundary caught: {}", message);
+        }
+    }
+    Rsx::Component(Comp {children: vec![fallback(message)]})
+}
+
+/// Called from the panic hook. Where the panic unwinds the boundary catches
+/// it itself, so this only acts when panics abort.
+pub(crate) fn on_panic(message: &str) {
+    if cfg!(panic = "abort") {
+        poison(message);
+    }
+}
+
+/// Marks the innermost rendering boundary as failed and puts back what it
+/// saved, since the aborted render never returns to it.
+fn poison(message: &str) {
+    let Some(active) = ACTIVE.with(|a| a.try_borrow_mut().ok().and_then(|mut a| a.drain(..).next_back())) else {
+        return;
+    };
+    restore(active.tracking, active.suspended, active.owner);
+    POISONED.with(|p| p.borrow_mut().insert(active.key, message.to_string()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    fn text(rsx: &Rsx) -> String {
+        match rsx {
+            Rsx::Component(comp) => comp.children.iter().map(text).collect(),
+            Rsx::Text(txt) => txt.text.clone(),
+            _ => unreachable!(),
+        }
+    }
+
+    fn profile(user: Option<&str>) -> Vec<Rsx> {
+        let name = suspense::suspense(|| owner::Owner::new().run(|| vec![Rsx::new_text(user.expect("no user").to_string())]), Rsx::new_text(String::new()));
+        vec![Rsx::new_text("hi ".to_string()), name]
+    }
+
+    fn view(children: impl FnOnce() -> Vec<Rsx>) -> String {
+        text(&error_boundary(children, |e| Rsx::new_text(format!("error: {}", e))))
+    }
+
+    fn reported() -> Rc<RefCell<Vec<String>>> {
+        let reports = Rc::new(RefCell::new(vec![]));
+        let r = reports.clone();
+        crate::set_error_handler(move |message| r.borrow_mut().push(message.to_string()));
+        reports
+    }
+
+    #[test]
+    fn recovers() {
+        let reports = reported();
+        assert_eq!(view(|| profile(None)), "error: no user");
+        assert_eq!(suspense::depth(), 0);
+        assert!(owner::current().is_none());
+        assert_eq!(view(|| profile(Some("ada"))), "hi ada");
+        assert_eq!(*reports.borrow(), ["no user"]);
+    }
+
+    #[test]
+    fn poisoned_by_abort() {
+        let reports = reported();
+        // What the panic hook leaves behind when the panic aborts mid-render.
+        let aborted = view(|| {
+            TRACKING.with(|t| t.borrow_mut().push(vec![]));
+            owner::set_current(Some(owner::Owner::new()));
+            poison("no user");
+            vec![]
+        });
+        assert_eq!(aborted, "");
+        assert!(TRACKING.with(|t| t.borrow().is_empty()));
+        assert!(owner::current().is_none());
+        assert!(ACTIVE.with(|a| a.borrow().is_empty()));
+        assert_eq!(view(|| profile(Some("ada"))), "error: no user");
+        assert_eq!(view(|| profile(Some("ada"))), "hi ada");
+        assert_eq!(*reports.borrow(), ["no user"]);
+    }
+}
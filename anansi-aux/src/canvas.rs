@@ -0,0 +1,74 @@
+use std::rc::Rc;
+
+use wasm_bindgen::JsCast;
+use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement};
+
+use crate::{WINDOW, node_ref};
+use crate::effect::Effect;
+
+/// The `<canvas>` rendered with a `ref` and its 2D context.
+#[derive(Clone)]
+pub struct CanvasContext {
+    canvas: HtmlCanvasElement,
+    context: CanvasRenderingContext2d,
+}
+
+/// The backing store size for a canvas laid out at `width` by `height` CSS pixels.
+fn backing_size(width: f64, height: f64, ratio: f64) -> (u32, u32) {
+    ((width * ratio).round().max(0.0) as u32, (height * ratio).round().max(0.0) as u32)
+}
+
+impl CanvasContext {
+    pub fn canvas(&self) -> &HtmlCanvasElement {
+        &self.canvas
+    }
+    pub fn context(&self) -> &CanvasRenderingContext2d {
+        &self.context
+    }
+    /// The canvas's laid out size in CSS pixels, which drawing code should use.
+    pub fn size(&self) -> (f64, f64) {
+        let rect = self.canvas.get_bounding_client_rect();
+        (rect.width(), rect.height())
+    }
+    /// Matches the backing store to the laid out size times `devicePixelRatio`
+    /// and scales the context so one unit is one CSS pixel. Resizing clears
+    /// the canvas, so it is skipped when the size is unchanged.
+    pub fn fit(&self) {
+        let (width, height) = self.size();
+        let ratio = WINDOW.with(|w| w.device_pixel_ratio());
+        let (w, h) = backing_size(width, height, ratio);
+        if self.canvas.width() != w || self.canvas.height() != h {
+            self.canvas.set_width(w);
+            self.canvas.set_height(h);
+        }
+        self.context.set_transform(ratio, 0.0, 0.0, ratio, 0.0, 0.0).unwrap();
+    }
+}
+
+/// Gets the 2D context of the canvas rendered with `ref="name"` by the current component.
+pub fn use_canvas(name: &str) -> Option<CanvasContext> {
+    let canvas = node_ref(name)?.dyn_into::<HtmlCanvasElement>().ok()?;
+    let context = canvas.get_context("2d").ok()??.dyn_into::<CanvasRenderingContext2d>().ok()?;
+    Some(CanvasContext {canvas, context})
+}
+
+/// Draws now and again whenever a signal read by `draw` changes, fitting the
+/// canvas to its size first.
+pub fn draw_effect(canvas: CanvasContext, mut draw: impl FnMut(&CanvasContext) + 'static) -> Rc<Effect> {
+    Effect::new(move || {
+        canvas.fit();
+        draw(&canvas);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn high_dpi_backing() {
+        assert_eq!(backing_size(300.0, 150.0, 1.0), (300, 150));
+        assert_eq!(backing_size(300.0, 150.0, 2.0), (600, 300));
+        assert_eq!(backing_size(100.5, 40.25, 1.5), (151, 60));
+    }
+}
@@ -0,0 +1,150 @@
+use std::cell::RefCell;
+
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen::closure::Closure;
+
+use crate::{WINDOW, NODE_ID, Signal, schedule_rerender};
+
+thread_local! {
+    static CLIPBOARD: RefCell<ClipboardState> = const { RefCell::new(ClipboardState::Idle) };
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClipboardState {
+    Idle,
+    Pending,
+    Resolved(String),
+    /// The clipboard could not be accessed, e.g. the permission was denied.
+    Rejected(String),
+}
+
+impl ClipboardState {
+    pub fn text(&self) -> Option<&str> {
+        if let Self::Resolved(s) = self {
+            Some(s)
+        } else {
+            None
+        }
+    }
+}
+
+/// Records the outcome of a clipboard operation and rerenders the component
+/// at `node_id` that started it.
+fn settle(node_id: &str, result: Result<String, JsValue>) {
+    let state = match result {
+        Ok(text) => ClipboardState::Resolved(text),
+        Err(e) => ClipboardState::Rejected(e.as_string().unwrap_or_else(|| format!("{:?}", e))),
+    };
+    CLIPBOARD.with(|c| *c.borrow_mut() = state);
+    schedule_rerender(node_id);
+}
+
+fn track(promise: js_sys::Promise, text: Option<String>) {
+    CLIPBOARD.with(|c| *c.borrow_mut() = ClipboardState::Pending);
+    let node_id = NODE_ID.with(|n| n.borrow().clone());
+    let n = node_id.clone();
+    let ok = Closure::once_into_js(move |v: JsValue| settle(&n, Ok(text.or_else(|| v.as_string()).unwrap_or_default())));
+    let err = Closure::once_into_js(move |e: JsValue| settle(&node_id, Err(e)));
+    let then: js_sys::Function = js_sys::Reflect::get(&promise, &"then".into()).unwrap().unchecked_into();
+    let _ = then.call2(&promise, &ok, &err);
+}
+
+pub fn clipboard_state() -> ClipboardState {
+    CLIPBOARD.with(|c| c.borrow().clone())
+}
+
+/// Writes `text` to the system clipboard.
+pub fn copy(text: &str) {
+    let promise = WINDOW.with(|w| w.navigator().clipboard().write_text(text));
+    track(promise, Some(text.to_string()));
+}
+
+/// Reads the system clipboard, which may prompt for permission.
+pub fn paste() {
+    let promise = WINDOW.with(|w| w.navigator().clipboard().read_text());
+    track(promise, None);
+}
+
+/// The last copied or read value and a setter that writes to the clipboard.
+/// The component rerenders once an operation it started settles. Failures are
+/// reported by `clipboard_state` as `Rejected`.
+pub fn use_clipboard() -> (Signal<Option<String>>, impl Fn(&str)) {
+    (Signal::new(clipboard_state().text().map(|s| s.to_string())), copy)
+}
+
+/// Copies the latest clipboard value into `value` once an operation has
+/// settled.
+pub fn sync_clipboard(value: &mut Signal<Option<String>>) {
+    let state = clipboard_state();
+    if let ClipboardState::Resolved(text) = state {
+        if value.value().as_deref() != Some(text.as_str()) {
+            *value.value_mut() = Some(text);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{counted_component, renders};
+
+    #[test]
+    fn copied_and_denied() {
+        counted_component("clip");
+        let mut value = Signal::new(None);
+        CLIPBOARD.with(|c| *c.borrow_mut() = ClipboardState::Pending);
+        settle("clip", Ok("hello".to_string()));
+        assert_eq!(renders(), 1);
+        assert_eq!(use_clipboard().0.value().as_deref(), Some("hello"));
+        sync_clipboard(&mut value);
+        assert_eq!(value.value().as_deref(), Some("hello"));
+        CLIPBOARD.with(|c| *c.borrow_mut() = ClipboardState::Rejected("NotAllowedError".to_string()));
+        sync_clipboard(&mut value);
+        assert_eq!(clipboard_state(), ClipboardState::Rejected("NotAllowedError".to_string()));
+        assert_eq!(value.value().as_deref(), Some("hello"));
+    }
+}
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod dom_tests {
+    use wasm_bindgen_test::*;
+    use super::*;
+    use crate::fixtures::{counted_component, renders, sleep};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    /// Replaces `navigator.clipboard` with one whose writes succeed and whose
+    /// reads are denied.
+    fn mock_clipboard() {
+        let clipboard = js_sys::Object::new();
+        let write = js_sys::Function::new_no_args("return Promise.resolve()");
+        let read = js_sys::Function::new_no_args("return Promise.reject('NotAllowedError')");
+        js_sys::Reflect::set(&clipboard, &"writeText".into(), &write).unwrap();
+        js_sys::Reflect::set(&clipboard, &"readText".into(), &read).unwrap();
+        let descriptor = js_sys::Object::new();
+        js_sys::Reflect::set(&descriptor, &"value".into(), &clipboard).unwrap();
+        js_sys::Reflect::set(&descriptor, &"configurable".into(), &true.into()).unwrap();
+        WINDOW.with(|w| js_sys::Object::define_property(&w.navigator(), &"clipboard".into(), &descriptor));
+    }
+
+    #[wasm_bindgen_test]
+    async fn mocked() {
+        mock_clipboard();
+        counted_component("clip-dom");
+        let (mut value, set) = use_clipboard();
+        set("hello");
+        assert_eq!(clipboard_state(), ClipboardState::Pending);
+        sleep(0).await;
+        assert_eq!(renders(), 1);
+        assert_eq!(clipboard_state(), ClipboardState::Resolved("hello".to_string()));
+        sync_clipboard(&mut value);
+        assert_eq!(value.value().as_deref(), Some("hello"));
+        paste();
+        sleep(0).await;
+        assert_eq!(renders(), 2);
+        assert_eq!(clipboard_state(), ClipboardState::Rejected("NotAllowedError".to_string()));
+        sync_clipboard(&mut value);
+        assert_eq!(value.value().as_deref(), Some("hello"));
+        crate::unmount("clip-dom");
+    }
+}
@@ -0,0 +1,87 @@
+use wasm_bindgen::JsCast;
+use wasm_bindgen::closure::Closure;
+use web_sys::HtmlElement;
+
+use crate::{WINDOW, Rsx, Elem, Attribute, Signal, attributes, element, node_ref};
+
+const CLOSED: &str = "overflow: hidden; height: 0px";
+
+/// An accordion body that animates its height between 0 and the natural height
+/// of its content. Once open the inline height is removed again, so content
+/// that resizes while open is not clipped.
+pub struct Collapse {
+    name: String,
+    duration: u32,
+}
+
+impl Collapse {
+    /// `name` is the `ref` given to the wrapper element.
+    pub fn new(name: &str, duration: u32) -> Self {
+        Self {name: name.to_string(), duration}
+    }
+    pub fn render(&self, open: &mut Signal<bool>, children: Vec<Rsx>) -> Rsx {
+        let style = if *open.value() { "" } else { CLOSED };
+        element!("DIV", attributes![("ref".to_string(), self.name.clone()), ("style".to_string(), style.to_string())], children)
+    }
+    /// Flips `open` and animates the change once the rerender it causes has been painted.
+    pub fn toggle(&self, open: &mut Signal<bool>) {
+        let opening = !*open.value();
+        *open.value_mut() = opening;
+        let Some(el) = node_ref(&self.name) else {
+            return;
+        };
+        let html = el.dyn_into::<HtmlElement>().unwrap();
+        let current = html.get_bounding_client_rect().height();
+        let duration = self.duration;
+        let cb = Closure::once_into_js(move || animate(html, opening, current, duration));
+        WINDOW.with(|w| w.request_animation_frame(cb.unchecked_ref()).unwrap());
+    }
+}
+
+/// Start and end heights of a toggle. Starting from the current height lets a
+/// toggle in the middle of an animation reverse it without jumping.
+fn heights(opening: bool, current: f64, natural: f64) -> (f64, f64) {
+    if opening {
+        (current, natural)
+    } else {
+        (current, 0.0)
+    }
+}
+
+fn animate(html: HtmlElement, opening: bool, current: f64, duration: u32) {
+    let style = html.style();
+    let (from, to) = heights(opening, current, html.scroll_height() as f64);
+    style.set_property("transition", "none").unwrap();
+    style.set_property("overflow", "hidden").unwrap();
+    style.set_property("height", &format!("{}px", from)).unwrap();
+    html.offset_height();
+    style.set_property("transition", &format!("height {}ms", duration)).unwrap();
+    style.set_property("height", &format!("{}px", to)).unwrap();
+    // A timeout rather than `transitionend`, which never fires when the height did not change.
+    let cb = Closure::once_into_js(move || {
+        let style = html.style();
+        style.remove_property("transition").unwrap();
+        if opening {
+            style.remove_property("height").unwrap();
+            style.remove_property("overflow").unwrap();
+        }
+    });
+    WINDOW.with(|w| w.set_timeout_with_callback_and_timeout_and_arguments_0(cb.unchecked_ref(), duration as i32).unwrap());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toggle_heights() {
+        let collapse = Collapse::new("body", 200);
+        let mut open = Signal::new(false);
+        assert_eq!(collapse.render(&mut open, vec![]).as_elem().attrs[1].value, CLOSED);
+        assert_eq!(heights(true, 0.0, 120.0), (0.0, 120.0));
+        assert_eq!(heights(false, 120.0, 120.0), (120.0, 0.0));
+        assert_eq!(heights(true, 45.0, 120.0), (45.0, 120.0));
+        *open.value_mut() = true;
+        assert_eq!(collapse.render(&mut open, vec![]).as_elem().attrs[1].value, "");
+    }
+}
@@ -1,5 +1,9 @@
+use std::fmt;
+
 use serde::{Serialize, Deserialize};
 
+use crate::attr_escape;
+
 pub trait Component<'de> {
     type Properties: Serialize + Deserialize<'de>;
     
@@ -12,11 +16,37 @@ pub struct Pauser {
     ctx: Vec<String>,
     objs: Vec<String>,
     subs: Vec<Vec<String>>,
+    script_type: String,
+    nonce: Option<String>,
+    packed: bool,
+    island: Option<String>,
+}
+
+impl Default for Pauser {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Pauser {
     pub fn new() -> Self {
-        Self {r: vec![], n: 0, ctx: vec![], objs: vec![], subs: vec![]}
+        Self {r: vec![], n: 0, ctx: vec![], objs: vec![], subs: vec![], script_type: "app/json".to_string(), nonce: None, packed: false, island: None}
+    }
+    /// Sets the `type` and optional CSP `nonce` of the state script. The client
+    /// must be pointed at the same script with `anansi_aux::set_state_selector`.
+    pub fn set_script(&mut self, script_type: &str, nonce: Option<&str>) {
+        self.script_type = script_type.to_string();
+        self.nonce = nonce.map(|n| n.to_string());
+    }
+    /// Writes the state in the compact `packed` encoding instead of JSON,
+    /// which `get_state` recognizes by the script's `data-format` attribute.
+    pub fn set_packed(&mut self, packed: bool) {
+        self.packed = packed;
+    }
+    /// Marks the state script with `data-island`, so that on a page with several
+    /// independently hydrated islands each finds its own through `get_island_state`.
+    pub fn set_island(&mut self, island: &str) {
+        self.island = Some(island.to_string());
     }
     pub fn id(&self) -> u32 {
         self.n
@@ -40,8 +70,8 @@ impl Pauser {
     pub fn push_subs(&mut self, v: Vec<String>) {
         self.subs.push(v);
     }
-    pub fn to_string(&self) -> String {
-		let mut s = String::from("<script type=\"module\" src=\"/static/main.js\"></script><script type=\"app/json\">");
+    fn state(&self) -> String {
+        let mut s = String::new();
         s.push_str("{\"ctx\":{");
         let mut b = false;
         for c in &self.ctx {
@@ -49,7 +79,7 @@ impl Pauser {
                 s.push_str(&format!(",{}", c));
             } else {
                 b = true;
-                s.push_str(&c);
+                s.push_str(c);
             }
         }
         b = false;
@@ -59,7 +89,7 @@ impl Pauser {
                 s.push_str(&format!(",{}", o));
             } else {
                 b = true;
-                s.push_str(&o);
+                s.push_str(o);
             }
         }
         s.push_str("],\"subs\":[");
@@ -83,11 +113,31 @@ impl Pauser {
                 }
             }
         }
-        s.push_str("]}</script>");
+        s.push_str("]}");
         s
     }
 }
 
+impl fmt::Display for Pauser {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<script type=\"module\" src=\"/static/main.js\"></script>")?;
+        write!(f, "<script type=\"{}\"", attr_escape(&self.script_type))?;
+        if let Some(nonce) = &self.nonce {
+            write!(f, " nonce=\"{}\"", attr_escape(nonce))?;
+        }
+        if let Some(island) = &self.island {
+            write!(f, " data-island=\"{}\"", attr_escape(island))?;
+        }
+        let state = self.state();
+        // A state that doesn't parse is written as it is, for the client to report.
+        let packed = if self.packed { serde_json::from_str(&state).ok() } else { None };
+        match packed {
+            Some(value) => write!(f, " data-format=\"packed\">{}</script>", crate::packed::pack(&value)),
+            None => write!(f, ">{}</script>", state),
+        }
+    }
+}
+
 #[macro_export]
 macro_rules! components {
     ($($name:ident,)*) => {
@@ -96,3 +146,60 @@ macro_rules! components {
         })*
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_script() {
+        let mut pauser = Pauser::new();
+        pauser.set_script("app/x-state", Some("abc"));
+        assert!(pauser.to_string().contains("<script type=\"app/x-state\" nonce=\"abc\">{\"ctx\":{}"));
+    }
+
+    #[test]
+    fn island_script() {
+        let mut pauser = Pauser::new();
+        pauser.set_island("b");
+        assert!(pauser.to_string().contains("<script type=\"app/json\" data-island=\"b\">"));
+    }
+
+    #[test]
+    fn escaped_attributes() {
+        let mut pauser = Pauser::new();
+        pauser.set_script("app/json\" onload=\"x()", Some("n\"><b>"));
+        pauser.set_island("a'b&c");
+        let html = pauser.to_string();
+        assert!(html.contains("<script type=\"app/json&quot; onload=&quot;x()\" nonce=\"n&quot;>&lt;b>\" data-island=\"a&#x27;b&amp;c\">"));
+    }
+
+    #[test]
+    fn packed_script() {
+        let mut pauser = Pauser::new();
+        pauser.comp();
+        pauser.push_obj("[{\"id\":1}]".to_string());
+        pauser.push_subs(vec!["0 0".to_string()]);
+        let json = pauser.state();
+        pauser.set_packed(true);
+        let html = pauser.to_string();
+        let (_, script) = html.split_once(" data-format=\"packed\">").unwrap();
+        let packed = script.strip_suffix("</script>").unwrap();
+        let (from_json, json_ctx) = crate::parse_state(&json).unwrap();
+        let (from_packed, packed_ctx) = crate::parse_packed_state(packed).unwrap();
+        assert_eq!(from_json.subs(), from_packed.subs());
+        assert_eq!(json_ctx.len(), packed_ctx.len());
+        assert!(matches!((from_json.obj(0), from_packed.obj(0)), (Some(crate::Obj::Js(a)), Some(crate::Obj::Js(b))) if a == b));
+    }
+
+    #[test]
+    fn unparsable_state_unpacked() {
+        let mut pauser = Pauser::new();
+        pauser.comp();
+        pauser.push_obj("{\"id\":".to_string());
+        pauser.set_packed(true);
+        let html = pauser.to_string();
+        assert!(!html.contains("data-format"));
+        assert!(html.contains(">{\"ctx\":{\"0\":{\"R\":\"0\"}},\"objs\":[{\"id\":],\"subs\":[]}</script>"));
+    }
+}
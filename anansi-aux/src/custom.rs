@@ -0,0 +1,278 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::thread::LocalKey;
+
+use serde::de::DeserializeOwned;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen::closure::Closure;
+use web_sys::{CustomEvent, Element, Event, EventTarget, HtmlInputElement, HtmlSelectElement, HtmlTextAreaElement, KeyboardEvent};
+
+use crate::{DOCUMENT, recall};
+
+type Listener = Closure<dyn FnMut(Event)>;
+
+thread_local! {
+    static LISTENERS: RefCell<HashMap<String, Listener>> = RefCell::new(HashMap::new());
+    static EVENT: RefCell<Option<Event>> = const { RefCell::new(None) };
+    static DELEGATED: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+}
+
+/// Custom elements are the ones whose tag name contains a hyphen.
+pub fn is_custom(tag: &str) -> bool {
+    tag.contains('-')
+}
+
+/// The event an `on:` attribute needs a listener for, e.g. the custom events
+/// of a custom element or `change` on an input. Clicks are already delegated
+/// at the document.
+fn listened_event(key: &str) -> Option<&str> {
+    match key.strip_prefix("on:") {
+        Some(event) if event != "click" => Some(event),
+        _ => None,
+    }
+}
+
+fn is_json(value: &str) -> bool {
+    let value = value.trim_start();
+    value.starts_with('{') || value.starts_with('[')
+}
+
+/// Object and array `prop:` values on custom elements are passed as parsed
+/// JSON rather than strings.
+pub(crate) fn prop_value(node: &Element, value: &str) -> Option<JsValue> {
+    if is_custom(&node.tag_name()) && is_json(value) {
+        js_sys::JSON::parse(value).ok()
+    } else {
+        None
+    }
+}
+
+fn is_delegated(event: &str) -> bool {
+    DELEGATED.with(|d| d.borrow().contains(event))
+}
+
+/// Handles `event` with one listener on the document instead of one per
+/// element, which keeps the listener count flat for long lists. Elements
+/// rendered from then on aren't bound directly; the listener recalls the
+/// nearest element on the event's path with an `on:` attribute for it. Only
+/// the listeners are saved: each such element still has its `RECALLS` entry.
+pub fn delegate(event: &str) {
+    if !DELEGATED.with(|d| d.borrow_mut().insert(event.to_string())) {
+        return;
+    }
+    let key = format!("on:{}", event);
+    let cb = Closure::<dyn FnMut(Event)>::new(move |event: Event| {
+        let path = event.composed_path();
+        let handlers: Vec<Option<String>> = path.iter().map(|t| t.dyn_into::<Element>().ok().filter(|el| el.has_attribute(&key)).and_then(|el| el.get_attribute("rid"))).collect();
+        if let Some(rid) = nearest_handler(&handlers) {
+            let rid = rid.to_string();
+            with_event(event, || recall(&rid));
+        }
+    });
+    DOCUMENT.with(|document| document.add_event_listener_with_callback(event, cb.as_ref().unchecked_ref()).unwrap());
+    LISTENERS.with(|l| l.borrow_mut().insert(format!("document:{}", event), cb));
+}
+
+/// The `rid` of the first element along an event path, innermost first, that handles it.
+fn nearest_handler(path: &[Option<String>]) -> Option<&str> {
+    path.iter().find_map(|rid| rid.as_deref())
+}
+
+/// Attaches the shared listener that recalls the element's `rid` when `key`
+/// names an event that is neither delegated at the document by default, like
+/// clicks, nor through `delegate`.
+pub(crate) fn listen(node: &Element, key: &str) {
+    let Some(event) = listened_event(key).filter(|event| !is_delegated(event)) else {
+        return;
+    };
+    LISTENERS.with(|l| {
+        let mut listeners = l.borrow_mut();
+        let cb = listeners.entry(event.to_string()).or_insert_with(|| Closure::new(|event: Event| {
+            let Some(target) = event.current_target().and_then(|t| t.dyn_into::<Element>().ok()) else {
+                return;
+            };
+            if let Some(rid) = target.get_attribute("rid") {
+                with_event(event, || recall(&rid));
+            }
+        }));
+        node.add_event_listener_with_callback(event, cb.as_ref().unchecked_ref()).unwrap();
+    });
+}
+
+/// Fills `slot` for the duration of `f`, putting back what an outer call left.
+fn scoped<T: 'static, R>(slot: &'static LocalKey<RefCell<Option<T>>>, value: T, f: impl FnOnce() -> R) -> R {
+    let outer = slot.with(|s| s.borrow_mut().replace(value));
+    let r = f();
+    slot.with(|s| *s.borrow_mut() = outer);
+    r
+}
+
+/// Runs a callback with `event` as the current event.
+pub(crate) fn with_event<R>(event: Event, f: impl FnOnce() -> R) -> R {
+    scoped(&EVENT, event, f)
+}
+
+/// The event whose callback is currently running.
+pub fn current_event() -> Option<Event> {
+    EVENT.with(|e| e.borrow().clone())
+}
+
+/// Stops the browser's default action for the current event, e.g. so an
+/// `on:submit` handler keeps its form from reloading the page or an
+/// `on:click` one keeps a link from navigating.
+pub fn prevent_default() {
+    if let Some(event) = current_event() {
+        event.prevent_default();
+    }
+}
+
+/// Keeps the current event from reaching listeners further up. Clicks are
+/// delegated at the document, so this only holds back other listeners there.
+pub fn stop_propagation() {
+    if let Some(event) = current_event() {
+        event.stop_propagation();
+    }
+}
+
+fn target_value(target: EventTarget) -> Option<String> {
+    if let Some(input) = target.dyn_ref::<HtmlInputElement>() {
+        Some(input.value())
+    } else if let Some(textarea) = target.dyn_ref::<HtmlTextAreaElement>() {
+        Some(textarea.value())
+    } else {
+        target.dyn_ref::<HtmlSelectElement>().map(|select| select.value())
+    }
+}
+
+/// The `value` of the input, textarea or select the current event was
+/// dispatched on. A select gives its first selected option's value.
+pub fn current_target_value() -> Option<String> {
+    let event = current_event()?;
+    event.current_target().and_then(target_value).or_else(|| event.target().and_then(target_value))
+}
+
+fn parse_number(value: &str) -> Option<f64> {
+    value.trim().parse().ok().filter(|n: &f64| n.is_finite())
+}
+
+/// `current_target_value` as a number, e.g. for `<input type="range">`.
+/// Empty and non-numeric values give `None`.
+pub fn current_target_number() -> Option<f64> {
+    parse_number(&current_target_value()?)
+}
+
+/// The modifier keys held during a keyboard event.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Modifiers {
+    pub ctrl: bool,
+    pub shift: bool,
+    pub alt: bool,
+    pub meta: bool,
+}
+
+fn keyboard_event() -> Option<KeyboardEvent> {
+    current_event()?.dyn_into::<KeyboardEvent>().ok()
+}
+
+/// The `key` of the keyboard event whose callback is currently running, e.g.
+/// `"Enter"`, `"a"` or `"ArrowUp"`.
+pub fn current_key() -> Option<String> {
+    keyboard_event().map(|e| e.key())
+}
+
+pub fn current_key_modifiers() -> Option<Modifiers> {
+    keyboard_event().map(|e| Modifiers {ctrl: e.ctrl_key(), shift: e.shift_key(), alt: e.alt_key(), meta: e.meta_key()})
+}
+
+/// Whether `pressed` is `wanted`. Keys typed while an input method is still
+/// composing, such as the Enter that confirms a candidate, never match.
+fn key_matches(pressed: &str, composing: bool, wanted: &str) -> bool {
+    !composing && pressed == wanted
+}
+
+/// Whether the current keyboard event is for `key`, so an `on:keydown`
+/// handler can return early on every other key.
+pub fn key_pressed(key: &str) -> bool {
+    keyboard_event().is_some_and(|e| key_matches(&e.key(), e.is_composing(), key))
+}
+
+/// `key_pressed("Enter")`.
+pub fn enter_pressed() -> bool {
+    key_pressed("Enter")
+}
+
+/// The `detail` of the custom event whose callback is currently running.
+pub fn event_detail<T: DeserializeOwned>() -> Option<T> {
+    let detail = current_event()?.dyn_ref::<CustomEvent>()?.detail();
+    let json = js_sys::JSON::stringify(&detail).ok()?.as_string()?;
+    serde_json::from_str(&json).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_events() {
+        assert_eq!(listened_event("on:change"), Some("change"));
+        assert_eq!(listened_event("on:click"), None);
+        assert_eq!(listened_event("prop:options"), None);
+        assert!(is_custom("UI-SELECT"));
+        assert!(!is_custom("SELECT"));
+        assert!(is_json(" {\"a\": 1}"));
+        assert!(is_json("[1, 2]"));
+        assert!(!is_json("plain"));
+    }
+
+    #[test]
+    fn range_values() {
+        assert_eq!(parse_number("42"), Some(42.0));
+        assert_eq!(parse_number(" 0.5 "), Some(0.5));
+        assert_eq!(parse_number(""), None);
+        assert_eq!(parse_number("NaN"), None);
+    }
+
+    #[test]
+    fn nested_events() {
+        thread_local! {
+            static SLOT: RefCell<Option<&'static str>> = const { RefCell::new(None) };
+        }
+        let current = || SLOT.with(|s| *s.borrow());
+        let inner = scoped(&SLOT, "submit", || {
+            let inner = scoped(&SLOT, "click", current);
+            assert_eq!(current(), Some("submit"));
+            inner
+        });
+        assert_eq!(inner, Some("click"));
+        assert_eq!(current(), None);
+    }
+
+    #[test]
+    fn search_on_enter() {
+        let mut submitted = vec![];
+        let mut keydown = |query: &str, key: &str, composing: bool| {
+            if !key_matches(key, composing, "Enter") {
+                return;
+            }
+            submitted.push(query.to_string());
+        };
+        keydown("ru", "u", false);
+        keydown("rust", "Enter", true);
+        keydown("rust", "Enter", false);
+        keydown("rust", "enter", false);
+        assert_eq!(submitted, ["rust"]);
+    }
+
+    #[test]
+    fn delegated_rows() {
+        let rows: Vec<String> = (0..1000).map(|n| if n % 2 == 0 { "on:input".to_string() } else { "on:change".to_string() }).collect();
+        let bound = || rows.iter().filter_map(|key| listened_event(key)).filter(|event| !is_delegated(event)).count();
+        assert_eq!(bound(), 1000);
+        DELEGATED.with(|d| d.borrow_mut().insert("input".to_string()));
+        assert_eq!(bound(), 500);
+        // Delegation drops listeners, not the `RECALLS` entry each row needs to be recalled by.
+        let path = [None, Some("12".to_string()), Some("3".to_string())];
+        assert_eq!(nearest_handler(&path), Some("12"));
+        assert_eq!(nearest_handler(&[None, None]), None);
+    }
+}
@@ -0,0 +1,104 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+
+use crate::{NODE_ID, Rsx, Elem, Attribute, attributes, element, call, component_index, on_unmount};
+use crate::intersect::{IntersectionOptions, on_intersection, use_intersection};
+
+thread_local! {
+    static REVEALED: RefCell<HashSet<(String, String)>> = RefCell::new(HashSet::new());
+    static WATCHED: RefCell<HashSet<(String, String)>> = RefCell::new(HashSet::new());
+}
+
+/// Renders a placeholder until the element with `ref="name"` comes within
+/// `root_margin` of the viewport, then mounts the real content for good. The
+/// first intersection is enough, so scrolling quickly past still mounts it.
+pub struct Defer {
+    key: (String, String),
+}
+
+fn reveal(revealed: &mut HashSet<(String, String)>, key: &(String, String), hit: bool) -> bool {
+    hit && revealed.insert(key.clone())
+}
+
+/// Whether `key` has no observer yet, which it then has until the component unmounts.
+fn watch(key: &(String, String)) -> bool {
+    if !WATCHED.with(|w| w.borrow_mut().insert(key.clone())) {
+        return false;
+    }
+    let key = key.clone();
+    on_unmount(move || {
+        WATCHED.with(|w| w.borrow_mut().remove(&key));
+    });
+    true
+}
+
+impl Defer {
+    /// `callback` names the component callback run, e.g. `"page_reveal"`, to
+    /// rerender it once the element has been seen.
+    pub fn new(name: &str, root_margin: &str, callback: &'static str) -> Self {
+        let key = (component_index().unwrap_or_default(), name.to_string());
+        let defer = Self {key: key.clone()};
+        if defer.is_revealed() || !watch(&key) {
+            return defer;
+        }
+        let options = IntersectionOptions {root_margin: Some(root_margin.to_string()), threshold: None};
+        let mut visible = use_intersection(name, options);
+        if REVEALED.with(|r| reveal(&mut r.borrow_mut(), &key, *visible.value())) {
+            return defer;
+        }
+        let node_id = NODE_ID.with(|n| n.borrow().clone());
+        on_intersection(name, move |hit| {
+            if REVEALED.with(|r| reveal(&mut r.borrow_mut(), &key, hit)) {
+                let _ = call(&format!("{}[]", callback), &node_id);
+            }
+        });
+        defer
+    }
+    pub fn is_revealed(&self) -> bool {
+        REVEALED.with(|r| r.borrow().contains(&self.key))
+    }
+    pub fn render(&self, placeholder: impl FnOnce() -> Vec<Rsx>, content: impl FnOnce() -> Vec<Rsx>) -> Rsx {
+        let children = if self.is_revealed() { content() } else { placeholder() };
+        element!("DIV", attributes![("ref".to_string(), self.key.1.clone())], children)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(defer: &Defer) -> String {
+        let rsx = defer.render(|| vec![Rsx::new_text("...".to_string())], || vec![Rsx::new_text("chart".to_string())]);
+        match &rsx.as_elem().children[0] {
+            Rsx::Text(txt) => txt.text.clone(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn placeholder_until_seen() {
+        let key = ("0".to_string(), "chart".to_string());
+        let defer = Defer {key: key.clone()};
+        assert_eq!(text(&defer), "...");
+        REVEALED.with(|r| {
+            let mut r = r.borrow_mut();
+            assert!(!reveal(&mut r, &key, false));
+            assert!(reveal(&mut r, &key, true));
+            assert!(!reveal(&mut r, &key, false));
+            assert!(!reveal(&mut r, &key, true));
+        });
+        assert_eq!(text(&defer), "chart");
+    }
+
+    #[test]
+    fn unwatched_on_unmount() {
+        crate::CTX.with(|c| c.borrow_mut().insert("feed".to_string(), crate::Ctx::R("feed".to_string())));
+        NODE_ID.with(|n| *n.borrow_mut() = "feed".to_string());
+        let key = ("feed".to_string(), "chart".to_string());
+        assert!(watch(&key));
+        assert!(!watch(&key));
+        crate::unmount("feed");
+        assert!(!WATCHED.with(|w| w.borrow().contains(&key)));
+        assert!(watch(&key));
+    }
+}
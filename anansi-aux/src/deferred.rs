@@ -0,0 +1,101 @@
+use std::any::Any;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::panic::Location;
+
+use crate::{NODE_ID, Signal, component_index, on_unmount, schedule_rerender};
+
+/// Where a deferred value stands between renders.
+struct Trail<T> {
+    shown: T,
+    latest: T,
+    scheduled: bool,
+}
+
+thread_local! {
+    static TRAILS: RefCell<HashMap<String, Rc<dyn Any>>> = RefCell::new(HashMap::new());
+}
+
+/// A copy of `source` that trails it, catching up only once the main thread
+/// is idle, so urgent updates such as typing aren't held up by the expensive
+/// renders that depend on the deferred value. Updates before the next idle
+/// period are coalesced into one rerender, which shows the latest value.
+/// Each call site in a component keeps its own copy.
+#[track_caller]
+pub fn deferred<T: Clone + PartialEq + 'static>(source: &mut Signal<T>) -> Signal<T> {
+    let key = format!("{}@{}", component_index().unwrap_or_default(), Location::caller());
+    let node_id = NODE_ID.with(|n| n.borrow().clone());
+    trail(key, node_id, source.value().clone(), idle)
+}
+
+fn idle(f: Box<dyn FnOnce()>) {
+    use wasm_bindgen::JsCast;
+    let cb = wasm_bindgen::closure::Closure::once_into_js(f);
+    crate::WINDOW.with(|w| w.request_idle_callback(cb.unchecked_ref()).unwrap());
+}
+
+/// `deferred` for the copy under `key`, with `schedule` running a callback
+/// once the main thread is idle.
+fn trail<T: Clone + PartialEq + 'static>(key: String, node_id: String, latest: T, schedule: fn(Box<dyn FnOnce()>)) -> Signal<T> {
+    let existing = TRAILS.with(|t| t.borrow().get(&key).cloned()).and_then(|t| t.downcast::<RefCell<Trail<T>>>().ok());
+    let Some(trail) = existing else {
+        let trail = Rc::new(RefCell::new(Trail {shown: latest.clone(), latest: latest.clone(), scheduled: false}));
+        TRAILS.with(|t| t.borrow_mut().insert(key.clone(), trail));
+        on_unmount(move || {
+            TRAILS.with(|t| t.borrow_mut().remove(&key));
+        });
+        return Signal::new(latest);
+    };
+    let mut t = trail.borrow_mut();
+    t.latest = latest;
+    if t.shown != t.latest && !t.scheduled {
+        t.scheduled = true;
+        let trail = trail.clone();
+        schedule(Box::new(move || {
+            let caught_up = {
+                let mut t = trail.borrow_mut();
+                t.scheduled = false;
+                let changed = t.shown != t.latest;
+                t.shown = t.latest.clone();
+                changed
+            };
+            if caught_up {
+                schedule_rerender(&node_id);
+            }
+        }));
+    }
+    Signal::new(t.shown.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{counted_component, renders};
+
+    thread_local! {
+        static IDLE: RefCell<Vec<Box<dyn FnOnce()>>> = RefCell::new(vec![]);
+    }
+
+    fn later(f: Box<dyn FnOnce()>) {
+        IDLE.with(|i| i.borrow_mut().push(f));
+    }
+
+    #[test]
+    fn trails_source() {
+        counted_component("search");
+        let query = |s: &str| trail("q".to_string(), "search".to_string(), s.to_string(), later);
+        assert_eq!(query("").value(), "");
+        for s in ["r", "ru", "rus", "rust"] {
+            assert_eq!(query(s).value(), "");
+        }
+        let idle: Vec<_> = IDLE.with(|i| i.borrow_mut().drain(..).collect());
+        assert_eq!(idle.len(), 1);
+        for f in idle {
+            f();
+        }
+        assert_eq!(renders(), 1);
+        assert_eq!(query("rust").value(), "rust");
+        assert!(IDLE.with(|i| i.borrow().is_empty()));
+    }
+}
@@ -0,0 +1,112 @@
+use std::rc::Rc;
+use std::cell::{Cell, RefCell};
+
+use crate::{TRACKING, component_index, on_unmount, owner};
+
+type Cleanups = Vec<Box<dyn FnOnce()>>;
+
+thread_local! {
+    pub static EFFECTS: RefCell<Vec<Rc<Effect>>> = const { RefCell::new(vec![]) };
+    static CLEANUP: RefCell<Option<Cleanups>> = const { RefCell::new(None) };
+}
+
+/// Runs a side effect now and again after any signal it read has been changed
+/// with `value_mut`. The signals read are recorded on every run, so branches
+/// that read different signals are followed. Stale effects are rerun at the
+/// end of `call` and `recall`, or by `run_effects`.
+pub struct Effect {
+    f: RefCell<Box<dyn FnMut()>>,
+    deps: RefCell<Vec<(Rc<Cell<u64>>, u64)>>,
+    cleanups: RefCell<Cleanups>,
+    active: Cell<bool>,
+}
+
+impl Effect {
+    pub fn new(f: impl FnMut() + 'static) -> Rc<Self> {
+        let effect = Rc::new(Self {f: RefCell::new(Box::new(f)), deps: RefCell::new(vec![]), cleanups: RefCell::new(vec![]), active: Cell::new(true)});
+        effect.run();
+        EFFECTS.with(|e| e.borrow_mut().push(effect.clone()));
+        if component_index().is_some() || owner::current().is_some() {
+            let e = effect.clone();
+            on_unmount(move || e.dispose());
+        }
+        effect
+    }
+    fn stale(&self) -> bool {
+        self.active.get() && self.deps.borrow().iter().any(|(v, seen)| v.get() != *seen)
+    }
+    fn teardown(&self) {
+        let cleanups = std::mem::take(&mut *self.cleanups.borrow_mut());
+        for cleanup in cleanups {
+            cleanup();
+        }
+    }
+    fn run(&self) {
+        self.teardown();
+        TRACKING.with(|t| t.borrow_mut().push(vec![]));
+        let outer = CLEANUP.with(|c| c.borrow_mut().replace(vec![]));
+        (self.f.borrow_mut())();
+        let cleanups = CLEANUP.with(|c| std::mem::replace(&mut *c.borrow_mut(), outer)).unwrap_or_default();
+        let reads = TRACKING.with(|t| t.borrow_mut().pop().unwrap());
+        *self.cleanups.borrow_mut() = cleanups;
+        *self.deps.borrow_mut() = reads.into_iter().map(|v| {
+            let seen = v.get();
+            (v, seen)
+        }).collect();
+    }
+    /// Tears the effect down for good, running its cleanups.
+    pub fn dispose(self: &Rc<Self>) {
+        if self.active.replace(false) {
+            self.teardown();
+            EFFECTS.with(|e| e.borrow_mut().retain(|other| !Rc::ptr_eq(other, self)));
+        }
+    }
+}
+
+/// Registers `f` to run before the current effect reruns or is disposed, to
+/// undo what this run did, e.g. remove an event listener.
+pub fn effect_cleanup(f: impl FnOnce() + 'static) {
+    CLEANUP.with(|c| c.borrow_mut().as_mut().expect("effect_cleanup called outside an effect").push(Box::new(f)));
+}
+
+/// Reruns every effect whose signals changed, until none are left stale.
+/// An effect that keeps invalidating itself is given up on after a few passes.
+pub fn run_effects() {
+    for _ in 0..16 {
+        let stale: Vec<Rc<Effect>> = EFFECTS.with(|e| e.borrow().iter().filter(|e| e.stale()).cloned().collect());
+        if stale.is_empty() {
+            return;
+        }
+        for effect in stale {
+            if effect.stale() {
+                effect.run();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Signal;
+
+    #[test]
+    fn reruns_on_change() {
+        let count = Rc::new(RefCell::new(Signal::new(1)));
+        let log = Rc::new(RefCell::new(vec![]));
+        let (c, l) = (count.clone(), log.clone());
+        let effect = Effect::new(move || {
+            let n = *c.borrow_mut().value();
+            l.borrow_mut().push(format!("run {}", n));
+            let l = l.clone();
+            effect_cleanup(move || l.borrow_mut().push(format!("clean {}", n)));
+        });
+        run_effects();
+        *count.borrow_mut().value_mut() = 2;
+        run_effects();
+        effect.dispose();
+        *count.borrow_mut().value_mut() = 3;
+        run_effects();
+        assert_eq!(*log.borrow(), ["run 1", "clean 1", "run 2", "clean 2"]);
+    }
+}
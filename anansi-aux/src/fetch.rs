@@ -0,0 +1,153 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use wasm_bindgen::JsValue;
+use web_sys::RequestInit;
+
+use crate::Resource;
+use crate::rpc::{RpcError, Settled, decode, network, rejected, send};
+
+#[derive(Default)]
+struct Shared {
+    settled: Option<Settled>,
+    waker: Option<Waker>,
+}
+
+/// A JSON request started by `fetch_json`. Awaiting it sends the request and
+/// resolves to the decoded body, or to `Rejected` with an `RpcError` when the
+/// request fails, the status is outside 200-299 or the body doesn't decode.
+pub struct JsonRequest<T> {
+    url: String,
+    method: String,
+    headers: Vec<(String, String)>,
+    body: Option<Result<String, RpcError>>,
+    shared: Option<Rc<RefCell<Shared>>>,
+    ty: PhantomData<T>,
+}
+
+/// A GET of `url`, which `method`, `header` and `json` can change before it's awaited.
+pub fn fetch_json<T: DeserializeOwned>(url: &str) -> JsonRequest<T> {
+    JsonRequest {url: url.to_string(), method: "GET".to_string(), headers: vec![], body: None, shared: None, ty: PhantomData}
+}
+
+impl<T> JsonRequest<T> {
+    pub fn method(mut self, method: &str) -> Self {
+        self.method = method.to_string();
+        self
+    }
+    pub fn header(mut self, key: &str, value: &str) -> Self {
+        self.headers.push((key.to_string(), value.to_string()));
+        self
+    }
+    /// Sends `body` serialized as JSON, with a JSON `Content-Type` unless one is set.
+    pub fn json<B: Serialize>(mut self, body: &B) -> Self {
+        self.body = Some(serde_json::to_string(body).map_err(|e| RpcError::Encode(e.to_string())));
+        self
+    }
+    fn content_typed(&self) -> bool {
+        self.headers.iter().any(|(key, _)| key.eq_ignore_ascii_case("content-type"))
+    }
+    fn start(&self, body: Option<String>) -> Rc<RefCell<Shared>> {
+        let init = RequestInit::new();
+        init.set_method(&self.method);
+        let headers = js_sys::Object::new();
+        for (key, value) in &self.headers {
+            js_sys::Reflect::set(&headers, &key.into(), &value.into()).unwrap();
+        }
+        if let Some(body) = body {
+            if !self.content_typed() {
+                js_sys::Reflect::set(&headers, &"Content-Type".into(), &"application/json".into()).unwrap();
+            }
+            init.set_body(&JsValue::from_str(&body));
+        }
+        init.set_headers(&headers);
+        let shared = Rc::new(RefCell::new(Shared::default()));
+        let s = shared.clone();
+        send(&self.url, &init, move |result| {
+            let waker = {
+                let mut s = s.borrow_mut();
+                s.settled = Some(network(result));
+                s.waker.take()
+            };
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        });
+        shared
+    }
+}
+
+impl<T> Unpin for JsonRequest<T> {}
+
+impl<T: DeserializeOwned> Future for JsonRequest<T> {
+    type Output = Resource<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.shared.is_none() {
+            let body = match self.body.take().transpose() {
+                Ok(body) => body,
+                Err(e) => return Poll::Ready(rejected(e)),
+            };
+            self.shared = Some(self.start(body));
+        }
+        let mut shared = self.shared.as_ref().unwrap().borrow_mut();
+        match shared.settled.take() {
+            Some(settled) => Poll::Ready(decode(settled)),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn poll<T: DeserializeOwned>(request: &mut JsonRequest<T>) -> Poll<Resource<T>> {
+        Pin::new(request).poll(&mut Context::from_waker(Waker::noop()))
+    }
+
+    fn in_flight<T: DeserializeOwned>(url: &str) -> (JsonRequest<T>, Rc<RefCell<Shared>>) {
+        let mut request = fetch_json(url);
+        let shared = Rc::new(RefCell::new(Shared::default()));
+        request.shared = Some(shared.clone());
+        (request, shared)
+    }
+
+    #[test]
+    fn status_rejects() {
+        let (mut user, shared) = in_flight::<HashMap<String, String>>("/users/1");
+        assert!(poll(&mut user).is_pending());
+        assert!(shared.borrow().waker.is_some());
+        shared.borrow_mut().settled = Some(Ok((404, "no such user".to_string())));
+        match poll(&mut user) {
+            Poll::Ready(Resource::Rejected(e)) => assert_eq!(e.to_string(), "server responded with 404: no such user"),
+            _ => panic!("expected rejected"),
+        }
+        let (mut user, shared) = in_flight::<HashMap<String, String>>("/users/2");
+        shared.borrow_mut().settled = Some(Ok((200, r#"{"name":"bea"}"#.to_string())));
+        assert!(matches!(poll(&mut user), Poll::Ready(Resource::Resolved(u)) if u["name"] == "bea"));
+    }
+
+    #[test]
+    fn builder() {
+        let request = fetch_json::<()>("/users").method("POST").header("content-type", "text/plain").json(&[1, 2]);
+        assert_eq!(request.method, "POST");
+        assert!(request.content_typed());
+        assert!(matches!(request.body, Some(Ok(ref body)) if body == "[1,2]"));
+        let unkeyable = HashMap::from([((1, 2), 3)]);
+        match poll(&mut fetch_json::<()>("/users").json(&unkeyable)) {
+            Poll::Ready(Resource::Rejected(e)) => assert!(e.to_string().starts_with("could not encode request")),
+            _ => panic!("expected rejected"),
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen::closure::Closure;
+use web_sys::{File, HtmlInputElement};
+
+use crate::{Resource, Signal, component_index, custom::current_event};
+
+thread_local! {
+    static FILES: RefCell<HashMap<String, Vec<File>>> = RefCell::new(HashMap::new());
+    static READS: RefCell<HashMap<(String, usize), Result<Contents, String>>> = RefCell::new(HashMap::new());
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileInfo {
+    pub name: String,
+    pub size: f64,
+    pub mime: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Contents {
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileReadError(pub String);
+
+impl fmt::Display for FileReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "could not read file: {}", self.0)
+    }
+}
+
+impl Error for FileReadError {}
+
+fn info(file: &File) -> FileInfo {
+    FileInfo {name: file.name(), size: file.size(), mime: file.type_()}
+}
+
+fn key() -> String {
+    component_index().unwrap_or_default()
+}
+
+/// The files last read by `read_files` in this component.
+pub fn use_file_input() -> Signal<Vec<FileInfo>> {
+    Signal::new(FILES.with(|f| f.borrow().get(&key()).map(|files| files.iter().map(info).collect()).unwrap_or_default()))
+}
+
+/// Copies the selection of the `<input type="file">` whose `on:change`
+/// callback is running into `files`. An emptied selection clears it.
+pub fn read_files(files: &mut Signal<Vec<FileInfo>>) {
+    let Some(input) = current_event().and_then(|e| e.target()).and_then(|t| t.dyn_into::<HtmlInputElement>().ok()) else {
+        return;
+    };
+    let mut selected = vec![];
+    if let Some(list) = input.files() {
+        for i in 0..list.length() {
+            selected.extend(list.item(i));
+        }
+    }
+    let infos: Vec<FileInfo> = selected.iter().map(info).collect();
+    let k = key();
+    READS.with(|r| r.borrow_mut().retain(|(c, _), _| *c != k));
+    FILES.with(|f| f.borrow_mut().insert(k, selected));
+    if *files.value() != infos {
+        *files.value_mut() = infos;
+    }
+}
+
+fn start(n: usize, text: bool) -> Result<(), FileReadError> {
+    let k = key();
+    let Some(file) = FILES.with(|f| f.borrow().get(&k).and_then(|files| files.get(n).cloned())) else {
+        return Err(FileReadError(format!("no file at index {}", n)));
+    };
+    let read = (k, n);
+    READS.with(|r| r.borrow_mut().remove(&read));
+    let promise = if text { file.text() } else { file.array_buffer() };
+    let r = read.clone();
+    let ok = Closure::once(move |v: JsValue| {
+        let contents = if text {
+            Contents::Text(v.as_string().unwrap_or_default())
+        } else {
+            Contents::Bytes(js_sys::Uint8Array::new(&v).to_vec())
+        };
+        READS.with(|reads| reads.borrow_mut().insert(r, Ok(contents)));
+    });
+    let err = Closure::once(move |e: JsValue| {
+        let e = e.as_string().unwrap_or_else(|| format!("{:?}", e));
+        READS.with(|reads| reads.borrow_mut().insert(read, Err(e)));
+    });
+    let _ = promise.then2(&ok, &err);
+    ok.forget();
+    err.forget();
+    Ok(())
+}
+
+fn pending<D>(started: Result<(), FileReadError>) -> Resource<D> {
+    match started {
+        Ok(()) => Resource::Pending,
+        Err(e) => Resource::Rejected(Box::new(e)),
+    }
+}
+
+fn settle<D>(n: usize, resource: &mut Resource<D>, get: impl FnOnce(Contents) -> D) {
+    if let Some(result) = READS.with(|r| r.borrow_mut().remove(&(key(), n))) {
+        *resource = match result {
+            Ok(contents) => Resource::Resolved(get(contents)),
+            Err(e) => Resource::Rejected(Box::new(FileReadError(e))),
+        };
+    }
+}
+
+/// Starts reading the `n`th selected file as text. `sync_text` picks up the result.
+pub fn read_text(n: usize) -> Resource<String> {
+    pending(start(n, true))
+}
+
+/// Starts reading the `n`th selected file as bytes. `sync_bytes` picks up the result.
+pub fn read_bytes(n: usize) -> Resource<Vec<u8>> {
+    pending(start(n, false))
+}
+
+pub fn sync_text(n: usize, text: &mut Resource<String>) {
+    settle(n, text, |c| match c {
+        Contents::Text(s) => s,
+        Contents::Bytes(b) => String::from_utf8_lossy(&b).into_owned(),
+    });
+}
+
+pub fn sync_bytes(n: usize, bytes: &mut Resource<Vec<u8>>) {
+    settle(n, bytes, |c| match c {
+        Contents::Text(s) => s.into_bytes(),
+        Contents::Bytes(b) => b,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settled_reads() {
+        READS.with(|r| {
+            let mut r = r.borrow_mut();
+            r.insert((String::new(), 0), Ok(Contents::Text("a,b".to_string())));
+            r.insert((String::new(), 1), Ok(Contents::Bytes(vec![1, 2])));
+            r.insert((String::new(), 2), Err("NotReadableError".to_string()));
+        });
+        let mut text = Resource::Pending;
+        sync_text(0, &mut text);
+        assert!(matches!(text, Resource::Resolved(ref s) if s == "a,b"));
+        let mut bytes = Resource::Pending;
+        sync_bytes(1, &mut bytes);
+        assert!(matches!(bytes, Resource::Resolved(ref b) if b == &[1, 2]));
+        let mut failed: Resource<String> = Resource::Pending;
+        sync_text(2, &mut failed);
+        match failed {
+            Resource::Rejected(e) => assert_eq!(e.to_string(), "could not read file: NotReadableError"),
+            _ => panic!("expected rejected"),
+        }
+        let mut untouched: Resource<String> = Resource::Pending;
+        sync_text(0, &mut untouched);
+        assert!(matches!(untouched, Resource::Pending));
+        assert!(matches!(read_text(5), Resource::Rejected(_)));
+    }
+}
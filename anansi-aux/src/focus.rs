@@ -0,0 +1,89 @@
+use std::cell::RefCell;
+
+use wasm_bindgen::JsCast;
+use web_sys::{Element, HtmlElement, HtmlInputElement, HtmlTextAreaElement};
+
+use crate::DOCUMENT;
+
+thread_local! {
+    static FOCUSED: RefCell<Option<Focused>> = const { RefCell::new(None) };
+}
+
+struct Focused {
+    el: Element,
+    selector: String,
+    selection: Option<(u32, u32)>,
+}
+
+/// Builds a selector that finds the same logical element after it has been
+/// re-created, from its `id`, `data-focus-key` or `name`, most stable first.
+pub fn identity(tag: &str, attr: impl Fn(&str) -> Option<String>) -> Option<String> {
+    for key in ["id", "data-focus-key", "name"] {
+        if let Some(value) = attr(key) {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+            return Some(format!("{}[{}=\"{}\"]", tag.to_lowercase(), key, value));
+        }
+    }
+    None
+}
+
+fn selection(el: &Element) -> Option<(u32, u32)> {
+    if let Some(input) = el.dyn_ref::<HtmlInputElement>() {
+        Some((input.selection_start().ok()??, input.selection_end().ok()??))
+    } else if let Some(area) = el.dyn_ref::<HtmlTextAreaElement>() {
+        Some((area.selection_start().ok()??, area.selection_end().ok()??))
+    } else {
+        None
+    }
+}
+
+fn set_selection(el: &Element, (start, end): (u32, u32)) {
+    if let Some(input) = el.dyn_ref::<HtmlInputElement>() {
+        input.set_selection_range(start, end).ok();
+    } else if let Some(area) = el.dyn_ref::<HtmlTextAreaElement>() {
+        area.set_selection_range(start, end).ok();
+    }
+}
+
+/// Remembers the focused element and its caret before a diff pass.
+pub fn capture() {
+    let focused = DOCUMENT.with(|document| document.active_element()).and_then(|el| {
+        let selector = identity(&el.tag_name(), |k| el.get_attribute(k))?;
+        let selection = selection(&el);
+        Some(Focused {el, selector, selection})
+    });
+    FOCUSED.with(|f| *f.borrow_mut() = focused);
+}
+
+/// Refocuses the element captured by `capture` when the diff replaced it,
+/// restoring its caret.
+pub fn restore() {
+    let Some(focused) = FOCUSED.with(|f| f.borrow_mut().take()) else {
+        return;
+    };
+    if focused.el.is_connected() {
+        return;
+    }
+    let el = DOCUMENT.with(|document| document.query_selector(&focused.selector).ok().flatten());
+    if let Some(el) = el {
+        if let Some(html) = el.dyn_ref::<HtmlElement>() {
+            html.focus().ok();
+        }
+        if let Some(selection) = focused.selection {
+            set_selection(&el, selection);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identities() {
+        let attrs = |pairs: &'static [(&'static str, &'static str)]| move |k: &str| pairs.iter().find(|(a, _)| *a == k).map(|(_, v)| v.to_string());
+        assert_eq!(identity("INPUT", attrs(&[("name", "q"), ("id", "search")])), Some("input[id=\"search\"]".to_string()));
+        assert_eq!(identity("TEXTAREA", attrs(&[("name", "say \"hi\"")])), Some("textarea[name=\"say \\\"hi\\\"\"]".to_string()));
+        assert_eq!(identity("INPUT", attrs(&[])), None);
+    }
+}
@@ -0,0 +1,84 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+
+use crate::{DOCUMENT, Signal, component_index, on_unmount};
+
+thread_local! {
+    static OWNED_META: RefCell<HashSet<(String, String)>> = RefCell::new(HashSet::new());
+}
+
+/// Sets `document.title` from `title`. Called while rendering, it follows the
+/// signal on every rerender.
+pub fn use_title(title: &mut Signal<String>) {
+    DOCUMENT.with(|document| {
+        if document.title() != *title.value() {
+            document.set_title(title.value());
+        }
+    });
+}
+
+/// Sets the content of `<meta name="{name}">`, creating the tag in `<head>` when
+/// missing. A tag created here is removed when the component unmounts, and a
+/// pre-existing tag gets its original content back, or loses the content
+/// attribute if it had none.
+pub fn use_meta(name: &str, content: &mut Signal<String>) {
+    DOCUMENT.with(|document| {
+        let selector = format!("meta[name=\"{}\"]", name.replace('"', "\\\""));
+        let (meta, created) = match document.query_selector(&selector).unwrap() {
+            Some(meta) => (meta, false),
+            None => {
+                let meta = document.create_element("meta").unwrap();
+                meta.set_attribute("name", name).unwrap();
+                document.head().expect("expected head").append_child(&meta).unwrap();
+                (meta, true)
+            }
+        };
+        let key = (component_index().unwrap_or_default(), name.to_string());
+        if OWNED_META.with(|o| o.borrow_mut().insert(key.clone())) {
+            let original = meta.get_attribute("content");
+            let m = meta.clone();
+            on_unmount(move || {
+                OWNED_META.with(|o| o.borrow_mut().remove(&key));
+                match original {
+                    _ if created => m.remove(),
+                    Some(content) => m.set_attribute("content", &content).unwrap(),
+                    None => m.remove_attribute("content").unwrap(),
+                }
+            });
+        }
+        if meta.get_attribute("content").as_deref() != Some(content.value().as_str()) {
+            meta.set_attribute("content", content.value()).unwrap();
+        }
+    });
+}
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod dom_tests {
+    use wasm_bindgen_test::*;
+    use super::*;
+    use crate::{CTX, Ctx, NODE_ID};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn in_head(index: &str) {
+        CTX.with(|c| c.borrow_mut().insert(index.to_string(), Ctx::R(index.to_string())));
+        NODE_ID.with(|n| *n.borrow_mut() = index.to_string());
+    }
+
+    #[wasm_bindgen_test]
+    fn meta_restored() {
+        let head = DOCUMENT.with(|document| document.head().unwrap());
+        head.insert_adjacent_html("beforeend", r#"<meta name="robots"><meta name="author" content="page">"#).unwrap();
+        in_head("article");
+        use_meta("robots", &mut Signal::new("noindex".to_string()));
+        use_meta("author", &mut Signal::new("bea".to_string()));
+        use_meta("theme-color", &mut Signal::new("#000".to_string()));
+        let meta = |name: &str| head.query_selector(&format!("meta[name=\"{}\"]", name)).unwrap();
+        assert_eq!(meta("robots").unwrap().get_attribute("content").as_deref(), Some("noindex"));
+        crate::unmount("article");
+        let robots = meta("robots").expect("expected the page's own tag to stay");
+        assert!(!robots.has_attribute("content"));
+        assert_eq!(meta("author").unwrap().get_attribute("content").as_deref(), Some("page"));
+        assert!(meta("theme-color").is_none());
+    }
+}
@@ -0,0 +1,419 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use wasm_bindgen::JsCast;
+use wasm_bindgen::closure::Closure;
+use web_sys::{Element, IntersectionObserver, IntersectionObserverEntry};
+
+use crate::{Rsx, AppState, Ctx, DOCUMENT, WINDOW, MOUNTS, NODE_ID, APP_STATE, CTX, RECALLS, mount, load_island_state, clear_recalls, flush_unmounted};
+
+thread_local! {
+    static PROPS: RefCell<HashMap<String, Value>> = RefCell::new(HashMap::new());
+}
+
+/// Attributes longer than this are still read, but debug builds warn that the
+/// props would be better off in the state script.
+const PROPS_WARN_LEN: usize = 8 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HydrationError {
+    MissingState,
+    Malformed(String),
+    MissingContext(String),
+    /// The island has no `data-component` attribute.
+    MissingComponent,
+    /// No component was registered under the island's `data-component`.
+    UnknownComponent(String),
+}
+
+impl fmt::Display for HydrationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MissingState => write!(f, "missing state script"),
+            Self::Malformed(e) => write!(f, "malformed state: {}", e),
+            Self::MissingContext(id) => write!(f, "no context for node {}", id),
+            Self::MissingComponent => write!(f, "island has no data-component"),
+            Self::UnknownComponent(name) => write!(f, "no mount for component {}", name),
+        }
+    }
+}
+
+impl std::error::Error for HydrationError {}
+
+/// Why the state script couldn't be loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    /// The page has no state script.
+    NoScript,
+    /// The script isn't valid JSON, e.g. because it was truncated.
+    InvalidJson(String),
+    /// The state has no `ctx`, `objs` or `subs`.
+    MissingField(&'static str),
+    /// A field holds the wrong kind of value.
+    Malformed(String),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NoScript => write!(f, "no state script present"),
+            Self::InvalidJson(e) => write!(f, "state is not valid JSON: {}", e),
+            Self::MissingField(field) => write!(f, "state is missing field {}", field),
+            Self::Malformed(e) => write!(f, "malformed state: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+impl From<StateError> for HydrationError {
+    fn from(e: StateError) -> Self {
+        match e {
+            StateError::NoScript => Self::MissingState,
+            e => Self::Malformed(e.to_string()),
+        }
+    }
+}
+
+fn ensure_state(node_id: &str) -> Result<(), HydrationError> {
+    if load_island_state(node_id)? {
+        Ok(())
+    } else {
+        Err(HydrationError::MissingState)
+    }
+}
+
+fn try_mount(node_id: &str, new: fn(String)) -> Result<(), HydrationError> {
+    ensure_state(node_id)?;
+    if !CTX.with(|c| c.borrow().contains_key(node_id)) {
+        return Err(HydrationError::MissingContext(node_id.to_string()));
+    }
+    NODE_ID.with(|n| *n.borrow_mut() = node_id.to_string());
+    mount(node_id, new);
+    Ok(())
+}
+
+fn attach(rsx: &mut Rsx, el: &Element) {
+    match rsx {
+        Rsx::Component(comp) => {
+            for child in &mut comp.children {
+                attach(child, el);
+            }
+        }
+        _ => DOCUMENT.with(|document| rsx.attach_to_element(el, document)),
+    }
+}
+
+/// Mounts `new` over the server-rendered `root`. When hydration fails the error
+/// is logged and `root`'s server HTML is replaced by a client-rendered `fallback`,
+/// which may be a fresh render of the component or an error view.
+pub fn hydrate_or_fallback(root: &Element, node_id: &str, new: fn(String), fallback: impl FnOnce() -> Rsx) -> Result<(), HydrationError> {
+    let result = try_mount(node_id, new);
+    if let Err(e) = &result {
+        web_sys::console::error_1(&format!("hydration failed: {}", e).into());
+        RECALLS.with(|r| {
+            let mut recalls = r.borrow_mut();
+            let children = root.child_nodes();
+            for i in 0..children.length() {
+                clear_recalls(&mut recalls, &children.get(i).unwrap());
+            }
+        });
+        root.set_inner_html("");
+        flush_unmounted();
+        attach(&mut fallback(), root);
+    }
+    result
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    Eager,
+    Visible,
+    Idle,
+    Interaction,
+}
+
+impl Priority {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "eager" => Some(Self::Eager),
+            "visible" => Some(Self::Visible),
+            "idle" => Some(Self::Idle),
+            "interaction" => Some(Self::Interaction),
+            _ => None,
+        }
+    }
+}
+
+/// Parses a `data-props` value, which is either JSON or base64-encoded JSON.
+pub fn decode_props(attr: &str) -> Result<Value, HydrationError> {
+    let attr = attr.trim();
+    let json = if attr.starts_with('{') || attr.starts_with('[') {
+        attr.to_string()
+    } else {
+        WINDOW.with(|w| w.atob(attr)).map_err(|_| HydrationError::Malformed("props are not base64".to_string()))?
+    };
+    serde_json::from_str(&json).map_err(crate::malformed)
+}
+
+/// Records `props` for the island `node_id`, creating the page state if there
+/// is no state script so the island can mount without one.
+pub(crate) fn seed_props(node_id: &str, props: Value) {
+    if !matches!(load_island_state(node_id), Ok(true)) {
+        APP_STATE.with(|a| {
+            a.borrow_mut().get_or_insert_with(|| AppState {objs: vec![], subs: vec![]});
+        });
+    }
+    CTX.with(|c| {
+        c.borrow_mut().entry(node_id.to_string()).or_insert_with(|| Ctx::R(node_id.to_string()));
+    });
+    PROPS.with(|p| p.borrow_mut().insert(node_id.to_string(), props));
+}
+
+/// The props the current island was hydrated with from its `data-props` attribute.
+pub fn island_props<T: DeserializeOwned>() -> Option<T> {
+    let node_id = NODE_ID.with(|n| n.borrow().clone());
+    let value = PROPS.with(|p| p.borrow().get(&node_id).cloned())?;
+    serde_json::from_value(value).ok()
+}
+
+fn read_props(island: &Element, node_id: &str) -> Result<(), HydrationError> {
+    if let Some(attr) = island.get_attribute("data-props") {
+        if cfg!(debug_assertions) && attr.len() > PROPS_WARN_LEN {
+            web_sys::console::warn_1(&format!("data-props of island {} is {} bytes", node_id, attr.len()).into());
+        }
+        seed_props(node_id, decode_props(&attr)?);
+    }
+    Ok(())
+}
+
+pub(crate) fn island_id(island: &Element) -> Option<String> {
+    if let Some(id) = island.get_attribute("a:id") {
+        return Some(id);
+    }
+    island.query_selector("[a\\:id]").ok().flatten().and_then(|el| el.get_attribute("a:id"))
+}
+
+/// Mounts the island's component ahead of its first callback. An island that
+/// fails to hydrate keeps its server HTML without affecting the others. Props
+/// in a `data-props` attribute are read first and don't need the state script.
+pub fn hydrate_island(island: &Element) {
+    if let Err(e) = try_hydrate_island(island) {
+        let component = island.get_attribute("data-component").unwrap_or_default();
+        web_sys::console::error_1(&format!("problem hydrating {}: {}", component, e).into());
+    }
+}
+
+fn try_hydrate_island(island: &Element) -> Result<(), HydrationError> {
+    let component = island.get_attribute("data-component").ok_or(HydrationError::MissingComponent)?;
+    let new = MOUNTS.with(|m| m.borrow().get(&component).copied()).ok_or(HydrationError::UnknownComponent(component))?;
+    match island_id(island) {
+        Some(node_id) => read_props(island, &node_id).and_then(|_| try_mount(&node_id, new)),
+        None => Ok(()),
+    }
+}
+
+/// Schedules every `[data-hydrate]` island according to its priority hint:
+/// `eager` mounts now, `idle` when the main thread is idle, `visible` once it
+/// scrolls into view and `interaction` on the first pointer or key event inside it.
+pub fn hydrate_islands() {
+    for island in islands() {
+        schedule(island);
+    }
+}
+
+/// Every `[data-hydrate]` island in the document.
+pub(crate) fn islands() -> Vec<Element> {
+    let islands = DOCUMENT.with(|document| document.query_selector_all("[data-hydrate]").unwrap());
+    (0..islands.length()).map(|i| islands.get(i).unwrap().dyn_into::<Element>().unwrap()).collect()
+}
+
+/// The closure a deferred island waits on, until it hydrates.
+type Waiting<F> = Rc<RefCell<Option<Closure<F>>>>;
+
+/// The events that hydrate an `interaction` island.
+const INTERACTIONS: [&str; 3] = ["pointerdown", "keydown", "focusin"];
+
+/// Drops the closure in `waiting` once the current callback has returned, as
+/// a closure can't be freed while it runs.
+fn release<F: ?Sized + 'static>(waiting: Waiting<F>) {
+    let cb = Closure::once_into_js(move || drop(waiting.borrow_mut().take()));
+    WINDOW.with(|w| w.set_timeout_with_callback(cb.unchecked_ref()).unwrap());
+}
+
+/// Hydrates `island` now or later, as its `data-hydrate` hint asks. A deferred
+/// island stops observing or listening once it has hydrated.
+pub(crate) fn schedule(island: Element) {
+    let hint = island.get_attribute("data-hydrate").unwrap_or_default();
+    match Priority::parse(&hint).unwrap_or(Priority::Interaction) {
+        Priority::Eager => hydrate_island(&island),
+        Priority::Idle => {
+            let cb = Closure::once_into_js(move || hydrate_island(&island));
+            WINDOW.with(|w| w.request_idle_callback(cb.unchecked_ref()).unwrap());
+        }
+        Priority::Visible => {
+            let waiting: Waiting<dyn FnMut(Vec<IntersectionObserverEntry>, IntersectionObserver)> = Rc::default();
+            let (w, target) = (waiting.clone(), island.clone());
+            let cb = Closure::<dyn FnMut(Vec<IntersectionObserverEntry>, IntersectionObserver)>::new(move |entries: Vec<IntersectionObserverEntry>, observer: IntersectionObserver| {
+                if entries.iter().any(|entry| entry.is_intersecting()) {
+                    observer.disconnect();
+                    hydrate_island(&target);
+                    release(w.clone());
+                }
+            });
+            let observer = IntersectionObserver::new(cb.as_ref().unchecked_ref()).unwrap();
+            observer.observe(&island);
+            *waiting.borrow_mut() = Some(cb);
+        }
+        Priority::Interaction => {
+            let waiting: Waiting<dyn FnMut()> = Rc::default();
+            let (w, target) = (waiting.clone(), island.clone());
+            let cb = Closure::<dyn FnMut()>::new(move || {
+                if let Some(cb) = w.borrow().as_ref() {
+                    for event in INTERACTIONS {
+                        target.remove_event_listener_with_callback(event, cb.as_ref().unchecked_ref()).unwrap();
+                    }
+                }
+                target.set_attribute("data-hydrated", "").unwrap();
+                hydrate_island(&target);
+                release(w.clone());
+            });
+            for event in INTERACTIONS {
+                island.add_event_listener_with_callback(event, cb.as_ref().unchecked_ref()).unwrap();
+            }
+            *waiting.borrow_mut() = Some(cb);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn malformed_state() {
+        let ok = r#"{"ctx":{"0":{"R":"0"}},"objs":[1],"subs":[["0 1"]]}"#;
+        let (state, ctx) = crate::parse_state(ok).unwrap();
+        assert_eq!(state.subs(), &vec![vec![(0, 1)]]);
+        assert!(ctx.contains_key("0"));
+        assert_eq!(crate::parse_state("{\"ctx\":{}}").err(), Some(StateError::MissingField("objs")));
+        assert_eq!(crate::parse_state(r#"{"ctx":{},"objs":[]}"#).err(), Some(StateError::MissingField("subs")));
+        assert!(matches!(crate::parse_state(r#"{"ctx":{},"objs":[],"subs":[["x"]]}"#), Err(StateError::Malformed(_))));
+        assert!(matches!(crate::parse_state(&ok[..ok.len() / 2]), Err(StateError::InvalidJson(_))));
+        assert!(matches!(crate::parse_state(""), Err(StateError::InvalidJson(_))));
+        assert_eq!(HydrationError::from(StateError::NoScript), HydrationError::MissingState);
+    }
+
+    #[test]
+    fn props_from_attribute() {
+        #[derive(serde::Deserialize)]
+        struct Props {
+            title: String,
+            count: u32,
+        }
+        let props = decode_props(r#" {"title":"Cart","count":3} "#).unwrap();
+        crate::register_island("7", AppState {objs: vec![], subs: vec![]}, HashMap::new());
+        seed_props("7", props);
+        assert!(CTX.with(|c| c.borrow().contains_key("7")));
+        NODE_ID.with(|n| *n.borrow_mut() = "7".to_string());
+        let props: Props = island_props().unwrap();
+        assert_eq!((props.title.as_str(), props.count), ("Cart", 3));
+        NODE_ID.with(|n| *n.borrow_mut() = "8".to_string());
+        assert!(island_props::<Props>().is_none());
+        assert!(matches!(decode_props("[1,"), Err(HydrationError::Malformed(_))));
+    }
+
+    #[test]
+    fn parse_hints() {
+        assert_eq!(Priority::parse("visible"), Some(Priority::Visible));
+        assert_eq!(Priority::parse("idle"), Some(Priority::Idle));
+        assert_eq!(Priority::parse("soon"), None);
+    }
+}
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod dom_tests {
+    use wasm_bindgen_test::*;
+    use super::*;
+    use crate::fixtures::sleep;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    thread_local! {
+        static MOUNTED: RefCell<Vec<String>> = const { RefCell::new(vec![]) };
+    }
+
+    fn mounted(node_id: String) {
+        MOUNTED.with(|m| m.borrow_mut().push(node_id));
+    }
+
+    /// Appends an island of the `Hydrated` component with its own state script.
+    fn island(id: &str, hint: &str) -> Element {
+        MOUNTS.with(|m| m.borrow_mut().insert("Hydrated".to_string(), mounted));
+        DOCUMENT.with(|document| {
+            let page = document.create_element("div").unwrap();
+            page.set_inner_html(&format!(r#"<script type="app/json" data-island="{id}">{{"ctx":{{"{id}":{{"R":"{id}"}}}},"objs":[],"subs":[]}}</script><div data-hydrate="{hint}" data-component="Hydrated" a:id="{id}"><button>+</button></div>"#));
+            document.body().unwrap().append_child(&page).unwrap();
+            page.query_selector("[data-hydrate]").unwrap().unwrap()
+        })
+    }
+
+    fn hydrated(id: &str) -> usize {
+        MOUNTED.with(|m| m.borrow().iter().filter(|n| *n == id).count())
+    }
+
+    #[wasm_bindgen_test]
+    fn bad_islands_skipped() {
+        let good = island("h1", "eager");
+        let unnamed = DOCUMENT.with(|document| document.create_element("div").unwrap());
+        unnamed.set_attribute("a:id", "h0").unwrap();
+        let unknown = good.clone_node().unwrap().dyn_into::<Element>().unwrap();
+        unknown.set_attribute("data-component", "Missing").unwrap();
+        assert_eq!(try_hydrate_island(&unnamed), Err(HydrationError::MissingComponent));
+        assert_eq!(try_hydrate_island(&unknown), Err(HydrationError::UnknownComponent("Missing".to_string())));
+        for el in [unnamed, unknown, good] {
+            hydrate_island(&el);
+        }
+        assert_eq!(hydrated("h1"), 1);
+    }
+
+    #[wasm_bindgen_test]
+    async fn visible_after_intersection() {
+        let el = island("h2", "visible");
+        let page = el.parent_element().unwrap();
+        page.set_attribute("style", "margin-top: 300vh").unwrap();
+        schedule(el.clone());
+        sleep(100).await;
+        assert_eq!(hydrated("h2"), 0);
+        el.scroll_into_view();
+        for _ in 0..20 {
+            if hydrated("h2") > 0 {
+                break;
+            }
+            sleep(50).await;
+        }
+        assert_eq!(hydrated("h2"), 1);
+        WINDOW.with(|w| w.scroll_to_with_x_and_y(0.0, 0.0));
+        sleep(100).await;
+        el.scroll_into_view();
+        sleep(100).await;
+        assert_eq!(hydrated("h2"), 1);
+        page.remove();
+    }
+
+    #[wasm_bindgen_test]
+    fn interaction_once() {
+        let el = island("h3", "interaction");
+        schedule(el.clone());
+        assert_eq!(hydrated("h3"), 0);
+        for event in ["pointerdown", "keydown", "pointerdown"] {
+            el.dispatch_event(&web_sys::Event::new(event).unwrap()).unwrap();
+        }
+        assert_eq!(hydrated("h3"), 1);
+        assert!(el.has_attribute("data-hydrated"));
+    }
+}
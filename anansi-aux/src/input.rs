@@ -0,0 +1,94 @@
+use wasm_bindgen::JsCast;
+use web_sys::{HtmlSelectElement, HtmlOptionElement};
+
+use crate::Signal;
+
+fn options(select: &HtmlSelectElement) -> Vec<HtmlOptionElement> {
+    let mut v = Vec::with_capacity(select.length() as usize);
+    for i in 0..select.length() {
+        if let Some(option) = select.item(i) {
+            v.push(option.dyn_into::<HtmlOptionElement>().expect("expected option element"));
+        }
+    }
+    v
+}
+
+pub fn selected_index(select: &HtmlSelectElement) -> Option<usize> {
+    let index = select.selected_index();
+    if index < 0 {
+        None
+    } else {
+        Some(index as usize)
+    }
+}
+
+pub fn set_selected_index(select: &HtmlSelectElement, index: Option<usize>) {
+    select.set_selected_index(index.map_or(-1, |i| i as i32));
+}
+
+pub fn selected_values(select: &HtmlSelectElement) -> Vec<String> {
+    options(select).iter().filter(|o| o.selected()).map(|o| o.value()).collect()
+}
+
+/// Copies the select's current selection into `values`, e.g. from a change handler.
+/// The comparison doesn't subscribe the caller to `values`.
+pub fn read_selected(select: &HtmlSelectElement, values: &mut Signal<Vec<String>>) {
+    let selected = selected_values(select);
+    if *values.peek() != selected {
+        *values.value_mut() = selected;
+    }
+}
+
+/// Marks each option selected when its value is in `values`. Values that no
+/// longer have a matching option are dropped from the signal.
+pub fn write_selected(select: &HtmlSelectElement, values: &mut Signal<Vec<String>>) {
+    let options = options(select);
+    for option in &options {
+        let selected = values.value().contains(&option.value());
+        if option.selected() != selected {
+            option.set_selected(selected);
+        }
+    }
+    let stale = values.value().iter().any(|v| !options.iter().any(|o| &o.value() == v));
+    if stale {
+        values.value_mut().retain(|v| options.iter().any(|o| &o.value() == v));
+    }
+}
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod dom_tests {
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+    use wasm_bindgen_test::*;
+    use super::*;
+    use crate::{DOCUMENT, Memo};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    fn read_untracked() {
+        let select = DOCUMENT.with(|document| {
+            let select = document.create_element("select").unwrap().dyn_into::<HtmlSelectElement>().unwrap();
+            select.set_multiple(true);
+            for value in ["a", "b", "c"] {
+                let option = document.create_element("option").unwrap();
+                option.set_attribute("value", value).unwrap();
+                select.append_child(&option).unwrap();
+            }
+            select
+        });
+        options(&select)[1].set_selected(true);
+        let values = Rc::new(RefCell::new(Signal::new(vec![])));
+        let runs = Rc::new(Cell::new(0));
+        let (v, n) = (values.clone(), runs.clone());
+        let mut reads = Memo::new(move || {
+            n.set(n.get() + 1);
+            read_selected(&select, &mut v.borrow_mut());
+        });
+        reads.value();
+        assert_eq!(*values.borrow_mut().peek(), ["b"]);
+        *values.borrow_mut().value_mut() = vec!["c".to_string()];
+        reads.value();
+        assert_eq!(runs.get(), 1);
+    }
+}
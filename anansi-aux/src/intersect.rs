@@ -0,0 +1,203 @@
+use std::rc::Rc;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+
+use wasm_bindgen::JsCast;
+use wasm_bindgen::closure::Closure;
+use web_sys::{IntersectionObserver, IntersectionObserverEntry, IntersectionObserverInit};
+
+use crate::{WINDOW, NODE_ID, Signal, component_index, node_ref, on_unmount, schedule_rerender};
+
+type Listener = Rc<dyn Fn(bool)>;
+
+thread_local! {
+    static INTERSECTING: RefCell<HashMap<(String, String), bool>> = RefCell::new(HashMap::new());
+    static LISTENERS: RefCell<HashMap<(String, String), Vec<Listener>>> = RefCell::new(HashMap::new());
+    static OBSERVED: RefCell<HashSet<(String, String)>> = RefCell::new(HashSet::new());
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct IntersectionOptions {
+    pub root_margin: Option<String>,
+    pub threshold: Option<f64>,
+}
+
+impl IntersectionOptions {
+    fn to_init(&self) -> IntersectionObserverInit {
+        let init = IntersectionObserverInit::new();
+        if let Some(margin) = &self.root_margin {
+            init.set_root_margin(margin);
+        }
+        if let Some(threshold) = self.threshold {
+            init.set_threshold_f64(threshold);
+        }
+        init
+    }
+}
+
+fn key(name: &str) -> (String, String) {
+    (component_index().unwrap_or_default(), name.to_string())
+}
+
+/// How many animation frames `use_intersection` waits for the element to be
+/// rendered before giving up.
+const MAX_FRAMES: u32 = 60;
+
+/// Observes the element rendered with `ref="name"` and records whether it is in
+/// the viewport, rerendering the component when that changes. When the element
+/// has not been rendered yet, observing is retried each animation frame for up
+/// to `MAX_FRAMES` frames. The first render starts the observer, later renders
+/// only read it, and it disconnects on unmount.
+pub fn use_intersection(name: &str, options: IntersectionOptions) -> Signal<bool> {
+    let key = key(name);
+    if OBSERVED.with(|o| o.borrow_mut().insert(key.clone())) {
+        let node_id = NODE_ID.with(|n| n.borrow().clone());
+        let observation = Rc::new(Observation::default());
+        let (o, k) = (observation.clone(), key.clone());
+        on_unmount(move || {
+            o.done.set(true);
+            if let Some((observer, _cb)) = o.observer.take() {
+                observer.disconnect();
+            }
+            OBSERVED.with(|o| o.borrow_mut().remove(&k));
+            INTERSECTING.with(|i| i.borrow_mut().remove(&k));
+        });
+        observe(key.clone(), node_id, options, observation, 0);
+    }
+    Signal::new(INTERSECTING.with(|i| i.borrow().get(&key).copied().unwrap_or(false)))
+}
+
+type Callback = Closure<dyn FnMut(Vec<IntersectionObserverEntry>)>;
+
+/// The observer behind a `use_intersection`, once its element was rendered.
+#[derive(Default)]
+struct Observation {
+    done: Cell<bool>,
+    observer: RefCell<Option<(IntersectionObserver, Callback)>>,
+}
+
+fn observe(key: (String, String), node_id: String, options: IntersectionOptions, observation: Rc<Observation>, frames: u32) {
+    if observation.done.get() {
+        return;
+    }
+    let previous = NODE_ID.with(|n| std::mem::replace(&mut *n.borrow_mut(), node_id.clone()));
+    let el = node_ref(&key.1);
+    NODE_ID.with(|n| *n.borrow_mut() = previous);
+    let Some(el) = el else {
+        if frames < MAX_FRAMES {
+            let cb = Closure::once_into_js(move || observe(key, node_id, options, observation, frames + 1));
+            WINDOW.with(|w| w.request_animation_frame(cb.unchecked_ref()).unwrap());
+        } else {
+            web_sys::console::warn_1(&format!("ref {} was never rendered, not observing it", key.1).into());
+        }
+        return;
+    };
+    let cb = Closure::<dyn FnMut(Vec<IntersectionObserverEntry>)>::new(move |entries: Vec<IntersectionObserverEntry>| {
+        if let Some(entry) = entries.last() {
+            record(&key, &node_id, entry.is_intersecting());
+        }
+    });
+    let observer = IntersectionObserver::new_with_options(cb.as_ref().unchecked_ref(), &options.to_init()).unwrap();
+    observer.observe(&el);
+    *observation.observer.borrow_mut() = Some((observer, cb));
+}
+
+/// Applies an observed change: the next render reads `hit`, the listeners are
+/// told and the component at `node_id` rerenders.
+fn record(key: &(String, String), node_id: &str, hit: bool) {
+    if INTERSECTING.with(|i| i.borrow_mut().insert(key.clone(), hit)) == Some(hit) {
+        return;
+    }
+    let listeners = LISTENERS.with(|l| l.borrow().get(key).cloned().unwrap_or_default());
+    for listener in listeners {
+        listener(hit);
+    }
+    schedule_rerender(node_id);
+}
+
+/// Calls `f` with each intersection change of `ref="name"` as it is observed,
+/// until the component unmounts.
+pub fn on_intersection(name: &str, f: impl Fn(bool) + 'static) {
+    let key = key(name);
+    LISTENERS.with(|l| l.borrow_mut().entry(key.clone()).or_default().push(Rc::new(f)));
+    on_unmount(move || {
+        LISTENERS.with(|l| l.borrow_mut().remove(&key));
+    });
+}
+
+/// Copies the latest intersection of `ref="name"` into `visible`.
+pub fn sync_intersection(name: &str, visible: &mut Signal<bool>) {
+    let current = INTERSECTING.with(|i| i.borrow().get(&key(name)).copied().unwrap_or(false));
+    if *visible.value() != current {
+        *visible.value_mut() = current;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{counted_component, renders};
+
+    #[test]
+    fn change_rerenders() {
+        counted_component("io");
+        let key = key("card");
+        let seen = Rc::new(RefCell::new(vec![]));
+        let s = seen.clone();
+        on_intersection("card", move |hit| s.borrow_mut().push(hit));
+        record(&key, "io", true);
+        record(&key, "io", true);
+        assert_eq!(renders(), 1);
+        assert_eq!(*seen.borrow(), [true]);
+        assert!(*Signal::new(INTERSECTING.with(|i| i.borrow()[&key])).value());
+        record(&key, "io", false);
+        assert_eq!(renders(), 2);
+        crate::unmount("io");
+        record(&key, "io", true);
+        assert_eq!(renders(), 2);
+    }
+}
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod dom_tests {
+    use wasm_bindgen_test::*;
+    use super::*;
+    use crate::{DOCUMENT, NODE_REFS};
+    use crate::fixtures::{counted_component, renders, sleep};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    async fn rendered(count: u32) {
+        for _ in 0..20 {
+            if renders() >= count {
+                return;
+            }
+            sleep(50).await;
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn observed() {
+        counted_component("io-dom");
+        let card = DOCUMENT.with(|document| {
+            let card = document.create_element("div").unwrap();
+            card.set_attribute("style", "height: 10px").unwrap();
+            document.body().unwrap().append_child(&card).unwrap();
+            card
+        });
+        NODE_REFS.with(|r| r.borrow_mut().entry("io-dom".to_string()).or_default().insert("card".to_string(), card.clone()));
+        let mut visible = use_intersection("card", IntersectionOptions::default());
+        assert!(!*visible.value());
+        rendered(1).await;
+        assert_eq!(renders(), 1);
+        sync_intersection("card", &mut visible);
+        assert!(*visible.value());
+        card.set_attribute("style", "height: 10px; position: absolute; top: 300vh").unwrap();
+        rendered(2).await;
+        assert_eq!(renders(), 2);
+        sync_intersection("card", &mut visible);
+        assert!(!*visible.value());
+        crate::unmount("io-dom");
+        card.remove();
+    }
+}
@@ -1,7 +1,17 @@
+//! Client-side runtime for anansi components.
+//!
+//! Browser hooks such as `use_online`, `use_intersection` and `use_clipboard`
+//! return a fresh `Signal` holding the current value on every render, so it is
+//! meant to be read, not kept. A component that keeps its own signal across
+//! renders, e.g. in its state, refreshes it with the matching `sync_*`
+//! function instead, or `StorageMap::sync` for local storage entries.
+
 use std::fmt;
 use std::any::Any;
+use std::borrow::Cow;
 use std::rc::Rc;
 use std::slice::{Iter, IterMut};
+use std::iter::Enumerate;
 use std::cell::{RefCell, Ref, RefMut};
 use std::collections::{HashMap, HashSet};
 use std::error::Error;
@@ -10,12 +20,14 @@ use std::marker::PhantomData;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use wasm_bindgen::closure::Closure;
-use web_sys::{Element, Node, NodeList, Document, Text, Window, Event};
+use web_sys::{Element, HtmlElement, HtmlTemplateElement, Node, NodeList, Document, Text, Comment, Window, Event};
 
 use serde_json::Value;
 use serde::{Serialize, Serializer, ser::SerializeSeq, Deserialize, de::DeserializeOwned};
 use serde::de::{Deserializer, Visitor, SeqAccess};
 
+use hydrate::{HydrationError, StateError};
+
 pub use anansi_macros::*;
 
 extern crate self as anansi_aux;
@@ -24,20 +36,65 @@ pub mod prelude {
     pub use serde_json::Value;
     pub use serde::{Serialize, Deserialize};
     pub use anansi_macros::{store, Properties, component, function_component, refchild, release};
-    pub use super::{attributes, element, document, Rsx, Sub, Proxy, Comp, Elem, Attribute, CbCmd, Resource, Rendered, RefVec, RefChild, Signal};
+    pub use super::{attributes, classes, styles, element, document, Rsx, Sub, Proxy, Comp, Elem, Attribute, CbCmd, Resource, Rendered, RefVec, RefChild, Signal, Memo};
+    pub use super::custom::{prevent_default, stop_propagation, current_target_value, current_key, key_pressed, enter_pressed};
 }
 
+pub mod announce;
+pub mod borrow;
+pub mod boundary;
+pub mod canvas;
+pub mod clipboard;
+pub mod collapse;
 pub mod components;
+pub mod custom;
+pub mod defer;
+pub mod deferred;
+pub mod effect;
+pub mod fetch;
+pub mod file;
+pub mod focus;
+pub mod head;
+pub mod hydrate;
+pub mod input;
+pub mod intersect;
+pub mod online;
+pub mod owner;
+pub mod packed;
+pub mod patch;
+pub mod preload;
+pub mod query;
+pub mod refmap;
+pub mod resource;
+pub mod router;
+pub mod rpc;
+pub mod scroll;
+pub mod shared;
+pub mod snapshot;
+pub mod storage;
+pub mod store;
+pub mod suspense;
+pub mod svg;
+pub mod switch;
+pub mod task;
+pub mod template;
+pub mod theme;
+pub mod timer;
+pub mod transition;
+pub mod virtual_list;
 
 pub type Mounts = &'static [(&'static str, fn(String), fn())];
 
 thread_local! {
     pub static WINDOW: Window = web_sys::window().expect("should have a window");
+    static RAW_HTML: js_sys::WeakMap = js_sys::WeakMap::new();
     pub static DOCUMENT: Document = {
         let window = web_sys::window().expect("should have a window");
         window.document().expect("window should have a document")
     };
     pub static CALLBACKS: RefCell<HashMap<String, CallbackData>> = RefCell::new(HashMap::new());
+    pub static STATE_SELECTOR: RefCell<String> = RefCell::new("script[type='app/json']".to_string());
+    pub static MOUNTS: RefCell<HashMap<String, fn(String)>> = RefCell::new(HashMap::new());
     pub static RECALLS: RefCell<HashMap<String, RecallData>> = RefCell::new(HashMap::new());
     pub static APP_STATE: RefCell<Option<AppState>> = RefCell::new(None);
     pub static NODE_ID: RefCell<String> = RefCell::new(String::new());
@@ -50,8 +107,28 @@ thread_local! {
     pub static MOUNTED: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
     pub static VIRT_NODES: RefCell<HashMap<String, Rsx>> = RefCell::new(HashMap::new());
     pub static EVENT_CB: RefCell<HashMap<&'static str, Closure<dyn Fn(Event)>>> = RefCell::new(HashMap::new());
+    pub static RENDER_SOURCE: RefCell<Option<RenderSource>> = const { RefCell::new(None) };
+    pub static ENTER_CLASS: RefCell<Option<String>> = const { RefCell::new(None) };
+    pub static NODE_REFS: RefCell<HashMap<String, HashMap<String, Element>>> = RefCell::new(HashMap::new());
+    pub static PENDING_RENDERS: RefCell<Vec<PendingRender>> = const { RefCell::new(vec![]) };
+    pub static SYNC: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+    pub static BATCH_DEPTH: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    pub static TRACKING: RefCell<Vec<Tracked>> = const { RefCell::new(vec![]) };
+    pub static ERROR_HANDLER: RefCell<Option<ErrorHandler>> = const { RefCell::new(None) };
+    pub static MOUNT_HOOKS: RefCell<Option<Vec<MountHook>>> = const { RefCell::new(None) };
+    pub static UNMOUNTED: RefCell<Vec<String>> = const { RefCell::new(vec![]) };
 }
 
+pub type ErrorHandler = Rc<dyn Fn(&str)>;
+/// The component being rendered and the index of its next `data-anansi-src` label.
+pub type RenderSource = (&'static str, usize);
+/// A queued rerender: the component's node id, its render source and its tree.
+pub type PendingRender = (String, Option<RenderSource>, Rsx);
+/// The version counters of the signals a `Memo` read while computing.
+pub type Tracked = Vec<Rc<std::cell::Cell<u64>>>;
+/// A hook run once the component being mounted is in the DOM.
+pub type MountHook = Box<dyn FnOnce()>;
+
 #[derive(PartialEq, Eq, Hash, Debug)]
 pub struct CompId {
     node_id: String,
@@ -80,12 +157,36 @@ macro_rules! document {
 #[macro_export]
 macro_rules! comp_statics {
     ($($name:expr,)*) => {
-        pub static STATICS: &[(&'static str, &'static [u8])] = &[
+        pub static STATICS: &[anansi_aux::Static] = &[
             $((concat!("/static/styles/", $name, ".css"), include_bytes!(concat!("static", anansi_aux::main_separator!(), "styles", anansi_aux::main_separator!(), $name, ".css"))),)*
         ];
     }
 }
 
+pub type Static = (&'static str, &'static [u8]);
+
+/// Combines several `comp_statics!` sets into one, keeping the first entry for
+/// any path declared by more than one component.
+pub fn merge_statics(sets: &[&'static [Static]]) -> Vec<Static> {
+    let mut seen = HashSet::new();
+    let mut merged = vec![];
+    for set in sets {
+        for s in *set {
+            if seen.insert(s.0) {
+                merged.push(*s);
+            }
+        }
+    }
+    merged
+}
+
+#[macro_export]
+macro_rules! merge_statics {
+    ($($statics:expr),* $(,)?) => {
+        anansi_aux::merge_statics(&[$($statics),*])
+    }
+}
+
 #[macro_export]
 #[cfg(not(target_os = "windows"))]
 macro_rules! main_separator {
@@ -237,6 +338,21 @@ impl<T: ?Sized> RefVec<T> {
     pub fn len(&self) -> usize {
         self.0.len()
     }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    pub fn get(&self, index: usize) -> Option<Ref<'_, T>> {
+        self.0.get(index).map(|c| c.borrow())
+    }
+    pub fn get_mut(&self, index: usize) -> Option<RefMut<'_, T>> {
+        self.0.get(index).map(|c| c.borrow_mut())
+    }
+    pub fn first(&self) -> Option<Ref<'_, T>> {
+        self.get(0)
+    }
+    pub fn last(&self) -> Option<Ref<'_, T>> {
+        self.0.last().map(|c| c.borrow())
+    }
 }
 
 pub trait GetOne {
@@ -264,6 +380,17 @@ impl<T: RefChild> RefVec<T> {
             n += 1;
         }
     }
+    /// Inserts `t` at `index`, shifting the items after it.
+    pub fn insert(&mut self, index: usize, t: <T as RefChild>::Item) {
+        let len = self.0.len();
+        if index > len {
+            panic!("insertion index (is {}) should be <= len (is {})", index, len);
+        }
+        self.0.insert(index, Rc::new(RefCell::new(T::new(index, t))));
+        for c in &self.0[index + 1..] {
+            *c.borrow_mut().pos_mut() += 1;
+        }
+    }
     pub fn clear(&mut self) {
         self.0.clear();
     }
@@ -286,11 +413,61 @@ impl<T: RefChild> RefVec<T> {
         self.0.append(&mut rest);
         removed
     }
+    /// Removes every index in `indices`, ignoring duplicates and out of range
+    /// entries, and reindexes the survivors once.
+    pub fn remove_indices(&mut self, indices: &[usize]) -> Vec<Rc<RefCell<T>>> {
+        let mut indices: Vec<usize> = indices.iter().copied().filter(|i| *i < self.0.len()).collect();
+        indices.sort_unstable();
+        indices.dedup();
+        let mut removed = Vec::with_capacity(indices.len());
+        let mut next = indices.iter().peekable();
+        let old = std::mem::take(&mut self.0);
+        for (n, c) in old.into_iter().enumerate() {
+            if next.peek() == Some(&&n) {
+                next.next();
+                removed.push(c);
+            } else {
+                self.0.push(c);
+            }
+        }
+        self.reindex(indices.first().copied().unwrap_or(0));
+        removed
+    }
+    /// Keeps the items `f` accepts and renumbers them from 0, since scope ids
+    /// refer to items by position.
+    pub fn retain(&mut self, f: impl Fn(&T) -> bool) {
+        self.0.retain(|c| f(&c.borrow()));
+        self.reindex(0);
+    }
+    /// Stably sorts the items by `f`, keeping each item's cell.
+    pub fn sort_by(&mut self, mut f: impl FnMut(&T, &T) -> std::cmp::Ordering) {
+        self.0.sort_by(|a, b| f(&a.borrow(), &b.borrow()));
+        self.reindex(0);
+    }
+    /// Stably sorts the items by the key `f` extracts, keeping each item's cell.
+    pub fn sort_by_key<K: Ord>(&mut self, mut f: impl FnMut(&T) -> K) {
+        self.0.sort_by_key(|c| f(&c.borrow()));
+        self.reindex(0);
+    }
+    /// Moves the item at `from` to `to`, shifting the items between them.
+    pub fn move_item(&mut self, from: usize, to: usize) {
+        if from == to {
+            return;
+        }
+        let item = self.0.remove(from);
+        self.0.insert(to, item);
+        self.reindex(from.min(to));
+    }
+    fn reindex(&mut self, start: usize) {
+        for (n, c) in self.0.iter().enumerate().skip(start) {
+            *c.borrow_mut().pos_mut() = n;
+        }
+    }
     pub fn iter(&self) -> RefIter<'_, T> {
-        RefIter {iter: self.0.iter()}
+        RefIter {iter: self.0.iter().enumerate()}
     }
     pub fn iter_mut(&mut self) -> RefIterMut<'_, T> {
-        RefIterMut {iter_mut: self.0.iter_mut()}
+        RefIterMut {iter_mut: self.0.iter_mut().enumerate()}
     }
     pub fn with_capacity(capacity: usize) -> Self {
         Self(Vec::with_capacity(capacity))
@@ -298,14 +475,18 @@ impl<T: RefChild> RefVec<T> {
 }
 
 pub struct RefIter<'a, T> {
-    iter: Iter<'a, Rc<RefCell<T>>>,
+    iter: Enumerate<Iter<'a, Rc<RefCell<T>>>>,
 }
 
 impl<'a, T> Iterator for RefIter<'a, T> {
     type Item = Ref<'a, T>;
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some(r) = self.iter.next() {
-            Some(r.borrow())
+        if let Some((n, r)) = self.iter.next() {
+            if cfg!(debug_assertions) {
+                Some(r.try_borrow().unwrap_or_else(|_| panic!("RefVec item {} is already mutably borrowed", n)))
+            } else {
+                Some(r.borrow())
+            }
         } else {
             None
         }
@@ -313,14 +494,18 @@ impl<'a, T> Iterator for RefIter<'a, T> {
 }
 
 pub struct RefIterMut<'a, T> {
-    iter_mut: IterMut<'a, Rc<RefCell<T>>>,
+    iter_mut: Enumerate<IterMut<'a, Rc<RefCell<T>>>>,
 }
 
 impl<'a, T> Iterator for RefIterMut<'a, T> {
     type Item = RefMut<'a, T>;
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some(r) = self.iter_mut.next() {
-            Some(r.borrow_mut())
+        if let Some((n, r)) = self.iter_mut.next() {
+            if cfg!(debug_assertions) {
+                Some(r.try_borrow_mut().unwrap_or_else(|_| panic!("RefVec item {} is already borrowed", n)))
+            } else {
+                Some(r.borrow_mut())
+            }
         } else {
             None
         }
@@ -329,7 +514,8 @@ impl<'a, T> Iterator for RefIterMut<'a, T> {
 
 pub struct Signal<T> {
     _proxy: SignalProxy,
-    value: T,
+    value: Option<T>,
+    init: Option<Box<dyn FnOnce() -> T>>,
 }
 
 impl<T> Parent for Signal<T> {
@@ -341,7 +527,7 @@ impl<T: Serialize + DeserializeOwned + 'static + std::fmt::Debug> Signal<T> {
         if let Obj::Js(v) = &store.objs[n] {
             let t: T = serde_json::from_value(v.clone()).unwrap();
             let subs = store.subs.pop().expect("problem getting subs");
-            Self {_proxy: SignalProxy::from(subs[0]), value: t}
+            Self {_proxy: SignalProxy::from(subs[0]), value: Some(t), init: None}
         } else {
             panic!("expected JavaScript value when resuming")
         }
@@ -350,21 +536,148 @@ impl<T: Serialize + DeserializeOwned + 'static + std::fmt::Debug> Signal<T> {
 
 impl<T> Signal<T> {
     pub fn new(t: T) -> Self {
-        Self {_proxy: SignalProxy::new(), value: t}
+        Self {_proxy: SignalProxy::new(), value: Some(t), init: None}
+    }
+    pub fn lazy(init: impl FnOnce() -> T + 'static) -> Self {
+        Self {_proxy: SignalProxy::new(), value: None, init: Some(Box::new(init))}
+    }
+    fn force(&mut self) -> &mut T {
+        let init = &mut self.init;
+        self.value.get_or_insert_with(|| (init.take().expect("expected lazy initializer"))())
     }
     pub fn value(&mut self) -> &T {
         self._proxy.set();
-        &self.value
+        self.force()
+    }
+    /// Reads the value without subscribing to it, so the render, `Memo` or
+    /// `Effect` doing the read is not rerun when it changes.
+    pub fn peek(&mut self) -> &T {
+        self.force()
+    }
+    /// Like `peek` through a shared reference. A lazy signal must have been
+    /// read before, as its initializer can't run here.
+    pub fn peek_untracked(&self) -> &T {
+        self.value.as_ref().expect("lazy signal peeked before it was initialized")
     }
     pub fn value_mut(&mut self) -> &mut T {
-        self._proxy._invalid = true;
-        &mut self.value
+        self._proxy.fire();
+        self.force()
+    }
+    /// Invalidates the signal after its value was changed in place without
+    /// `value_mut`, e.g. through a `Cell` inside it, and has the current
+    /// component rerender in the next animation frame.
+    pub fn notify(&mut self) {
+        self._proxy.fire();
+        let node_id = NODE_ID.with(|n| n.borrow().clone());
+        request_rerender(&node_id);
+    }
+    pub fn get_subs(&self) -> Vec<String> {
+        self._proxy.get_subs()
+    }
+    pub fn into_inner(mut self) -> T {
+        self.force();
+        self.value.unwrap()
+    }
+}
+
+impl<T: PartialEq> Signal<T> {
+    /// Replaces the value, invalidating only when `new` differs from it.
+    pub fn set(&mut self, new: T) {
+        if *self.force() != new {
+            self.value = Some(new);
+            self._proxy.fire();
+        }
+    }
+    /// Mutates the value in place. `f` returns whether it changed anything,
+    /// and the signal is only invalidated when it did.
+    pub fn update(&mut self, f: impl FnOnce(&mut T) -> bool) {
+        if f(self.force()) {
+            self._proxy.fire();
+        }
+    }
+}
+
+/// A derived value that can be written back: reads compute from the sources
+/// and writes go through the setter, which updates them.
+pub struct LinkedSignal<U> {
+    get: Box<dyn Fn() -> U>,
+    set: Box<dyn Fn(U)>,
+}
+
+impl<U> LinkedSignal<U> {
+    pub fn new(get: impl Fn() -> U + 'static, set: impl Fn(U) + 'static) -> Self {
+        Self {get: Box::new(get), set: Box::new(set)}
+    }
+    pub fn value(&self) -> U {
+        (self.get)()
+    }
+    pub fn set(&self, u: U) {
+        (self.set)(u)
+    }
+}
+
+/// A value derived from signals. The signals read while computing it are
+/// recorded, and it is only recomputed once one of them has changed since.
+/// Readers are only notified when the recomputed value differs.
+pub struct Memo<U> {
+    _proxy: SignalProxy,
+    f: Box<dyn Fn() -> U>,
+    value: Option<U>,
+    deps: Vec<(Rc<std::cell::Cell<u64>>, u64)>,
+}
+
+impl<U: PartialEq> Memo<U> {
+    pub fn new(f: impl Fn() -> U + 'static) -> Self {
+        let mut _proxy = SignalProxy::new();
+        _proxy._dirty = 0;
+        Self {_proxy, f: Box::new(f), value: None, deps: vec![]}
+    }
+    fn recompute(&mut self) {
+        TRACKING.with(|t| t.borrow_mut().push(vec![]));
+        let u = (self.f)();
+        let reads = TRACKING.with(|t| t.borrow_mut().pop().unwrap());
+        self.deps = reads.into_iter().map(|v| {
+            let seen = v.get();
+            (v, seen)
+        }).collect();
+        if self.value.as_ref() != Some(&u) {
+            self.value = Some(u);
+            self._proxy.fire();
+        }
+        self._proxy._dirty = -1;
+    }
+    pub fn value(&mut self) -> &U {
+        if self.deps.iter().any(|(v, seen)| v.get() != *seen) {
+            self._proxy._dirty = 0;
+        }
+        if self._proxy._dirty != -1 {
+            self.recompute();
+        }
+        self._proxy.track();
+        if self._proxy._learning {
+            self._proxy.track_once();
+        }
+        self.value.as_ref().unwrap()
     }
     pub fn get_subs(&self) -> Vec<String> {
         self._proxy.get_subs()
     }
-    pub fn into_inner(self) -> T {
-        self.value
+}
+
+impl<U: PartialEq + DeserializeOwned> Memo<U> {
+    /// Restores the value rendered on the server. The signals it depends on are
+    /// only known after computing it, so it reruns `f` on first read, notifying
+    /// readers only if the result differs from the restored value.
+    pub fn resume(store: &mut AppState, n: usize, f: impl Fn() -> U + 'static) -> Self {
+        if let Obj::Js(v) = &store.objs[n] {
+            let u: U = serde_json::from_value(v.clone()).unwrap();
+            let subs = store.subs.pop().expect("problem getting subs");
+            let mut _proxy = SignalProxy::from(subs[0]);
+            _proxy._dirty = 0;
+            Self {_proxy, f: Box::new(f), value: Some(u), deps: vec![]}
+        } else {
+            panic!("expected JavaScript value when resuming")
+        }
     }
 }
 
@@ -375,27 +688,57 @@ pub struct SignalProxy {
     pub _node: u32,
     pub _dirty: i64,
     pub _sub: Sub,
+    pub _version: Rc<std::cell::Cell<u64>>,
+    pub _tracked: bool,
 }
 
 impl SignalProxy {
     pub fn new() -> Self {
-        Self {_learning: false, _invalid: false, _node: 0, _dirty: -1, _sub: (0, 0)}
+        Self::from((0, 0))
     }
     pub fn from(_sub: (u32, i64)) -> Self {
-        Self {_learning: false, _invalid: false, _node: 0, _dirty: -1, _sub}
+        Self {_learning: false, _invalid: false, _node: 0, _dirty: -1, _sub, _version: Rc::default(), _tracked: false}
+    }
+    /// Marks the value as changed for the renderer and for any `Memo` that read it.
+    pub fn fire(&mut self) {
+        self._invalid = true;
+        self._version.set(self._version.get().wrapping_add(1));
     }
-    pub fn set(&mut self) {
+    fn track(&self) {
+        TRACKING.with(|t| {
+            if let Some(reads) = t.borrow_mut().last_mut() {
+                if !reads.iter().any(|r| Rc::ptr_eq(r, &self._version)) {
+                    reads.push(self._version.clone());
+                }
+            }
+        });
+    }
+    /// Records the dependency of the current learning pass unless it already
+    /// was, returning whether this read recorded it.
+    pub fn track_once(&mut self) -> bool {
+        if self._tracked {
+            return false;
+        }
+        self._tracked = true;
+        self._sub = (self._node, 0);
+        true
+    }
+    /// Returns whether the read was tracked as a new dependency.
+    pub fn set(&mut self) -> bool {
+        self.track();
         if self._learning {
-            self._sub = (self._node, 0);
+            self.track_once()
         } else {
             if self._dirty == -1 {
                 self._dirty = 0;
             }
             self._dirty |= 1;
+            false
         }
     }
     pub fn start_proxy(&mut self) -> Sub {
         self._learning = true;
+        self._tracked = false;
         self._invalid = false;
         self._dirty = -1;
         self._sub
@@ -421,14 +764,27 @@ impl Proxy {
     pub fn new(subs: Vec<Sub>) -> Self {
         Self {_learning: false, _invalid: false, _node: 0, _dirty: -1, _subs: subs}
     }
-    pub fn set(&mut self, n: i64) {
+    /// Records the dependency on `n` unless this learning pass already did,
+    /// returning whether this read recorded it.
+    pub fn track_once(&mut self, n: i64) -> bool {
+        let sub = (self._node, n);
+        if self._subs.contains(&sub) {
+            false
+        } else {
+            self._subs.push(sub);
+            true
+        }
+    }
+    /// Returns whether the read was tracked as a new dependency.
+    pub fn set(&mut self, n: i64) -> bool {
         if self._learning {
-            self._subs.push((self._node, n));
+            self.track_once(n)
         } else {
             if self._dirty == -1 {
                 self._dirty = 0;
             }
             self._dirty |= n;
+            false
         }
     }
     pub fn start_proxy(&mut self) -> Vec<Sub> {
@@ -462,6 +818,12 @@ pub struct Elem {
     pub name: &'static str,
     pub attrs: Vec<Attribute>,
     pub children: Vec<Rsx>,
+    pub key: Option<String>,
+    /// Opaque data for tooling, e.g. a source span, stamped on the element as
+    /// `data-anansi-meta` and ignored when diffing.
+    pub meta: Option<String>,
+    /// Which parts can change between renders, when the `component` macro knows.
+    pub holes: Option<template::Holes>,
     pub el: Option<Element>,
 }
 
@@ -478,14 +840,83 @@ macro_rules! attributes {
     }
 }
 
+/// Builds a `class` value from `(name, condition)` pairs, keeping each name
+/// whose condition holds once, in order. Diffing works token by token, so
+/// toggling one entry only touches that class on the element.
+#[macro_export]
+macro_rules! classes {
+    ($(($name:expr, $on:expr)),* $(,)?) => {
+        $crate::join_classes(&[$(($name, $on)),*])
+    }
+}
+
+pub fn join_classes(classes: &[(&str, bool)]) -> String {
+    let mut tokens: Vec<&str> = vec![];
+    for token in classes.iter().filter(|(_, on)| *on).flat_map(|(name, _)| name.split_whitespace()) {
+        if !tokens.contains(&token) {
+            tokens.push(token);
+        }
+    }
+    tokens.join(" ")
+}
+
+/// Builds a `style` value from `(property, value)` pairs, skipping empty
+/// values. Diffing works per declaration, so changing one property leaves the
+/// others, and any set from elsewhere, untouched.
+#[macro_export]
+macro_rules! styles {
+    ($(($prop:expr, $value:expr)),* $(,)?) => {
+        $crate::join_styles(&[$(($prop, $value)),*])
+    }
+}
+
+pub fn join_styles(styles: &[(&str, &str)]) -> String {
+    styles.iter().filter(|(_, value)| !value.trim().is_empty()).map(|(prop, value)| format!("{}: {}", prop, value.trim())).collect::<Vec<_>>().join("; ")
+}
+
+/// Labels `el` with the next `data-anansi-src` of the source being rendered,
+/// returning whether there is one.
+pub(crate) fn stamp_source(el: &Element) -> bool {
+    #[cfg(debug_assertions)]
+    return RENDER_SOURCE.with(|s| {
+        if let Some((name, n)) = s.borrow_mut().as_mut() {
+            el.set_attribute("data-anansi-src", &format!("{}:{}", name, n)).unwrap();
+            *n += 1;
+            true
+        } else {
+            false
+        }
+    });
+    #[cfg(not(debug_assertions))]
+    {
+        let _ = el;
+        false
+    }
+}
+
 impl Elem {
     fn node(&self) -> Node {
         self.el.clone().expect("expected element").dyn_into::<Node>().unwrap()
     }
     fn to_node(&mut self, document: &Document) -> Node {
-        let el = document.create_element(self.name).unwrap();
+        if let Some(node) = preload::adopt(self) {
+            return node;
+        }
+        let el = svg::create(document, self.name);
+        stamp_source(&el);
+        if let Some(meta) = &self.meta {
+            el.set_attribute("data-anansi-meta", meta).unwrap();
+        }
         for attr in &self.attrs {
-            el.set_attribute(&attr.key, &attr.value).unwrap();
+            if let Some(prop) = attr.key.strip_prefix("prop:") {
+                set_prop(&el, prop, &attr.value);
+                continue;
+            }
+            svg::set_attr(&el, &attr.key, &attr.value);
+            sync_idl(&el, &attr.key, Some(&attr.value));
+            if attr.key == "ref" {
+                register_ref(&attr.value, &el);
+            }
             if attr.key.starts_with("on:") {
                 CALLBACKS.with(|c| {
                     let c = c.borrow();
@@ -502,14 +933,32 @@ impl Elem {
                         *r += 1;
                     });
                 });
+                custom::listen(&el, &attr.key);
             }
         }
+        self.check_void();
         for child in &mut self.children {
             child.attach_to_element(&el, document);
         }
+        svg::leave();
         self.el = Some(el.clone());
         el.dyn_into::<Node>().unwrap()
     }
+    /// Drops the children of a void element such as `<img>`, which can't
+    /// have any. Returns whether there were some.
+    fn strip_void_children(&mut self) -> bool {
+        if is_void(self.name) && !self.children.is_empty() {
+            self.children.clear();
+            true
+        } else {
+            false
+        }
+    }
+    fn check_void(&mut self) {
+        if self.strip_void_children() {
+            web_sys::console::warn_1(&format!("<{}> is a void element, ignoring its children", self.name.to_lowercase()).into());
+        }
+    }
     fn diff(&mut self, node: &mut Node) {
         let mut name = node.node_name();
         if name == "#text" && node.node_value().unwrap() == "" {
@@ -519,43 +968,27 @@ impl Elem {
         if self.name == name {
             let el = node.dyn_ref::<Element>().unwrap();
             let attributes = el.attributes();
-            let l = self.attrs.len() as u32;
-            let mut same = true;
-            if l == attributes.length() {
-                for attr in &self.attrs {
-                    if let Some(attribute) = attributes.get_named_item(&attr.key) {
-                        let val = attribute.value();
-                        if val != attr.value {
-                            same = false;
-                            break;
-                        }
-                    } else {
-                        same = false;
-                        break;
-                    }
+            let live: Vec<String> = (0..attributes.length()).filter_map(|i| attributes.item(i)).map(|a| a.name()).collect();
+            for key in stale_attrs(&live, &self.attrs) {
+                drop_attr(el, key);
+                sync_idl(el, key, None);
+            }
+            for attr in changed_attrs(|k| el.get_attribute(k), &self.attrs) {
+                update_attr(el, attr, el.get_attribute(&attr.key).as_deref());
+                check_recall(el, attr);
+            }
+            for attr in &self.attrs {
+                if let Some(prop) = attr.key.strip_prefix("prop:") {
+                    set_prop(el, prop, &attr.value);
+                    continue;
                 }
-            } else if l + 1 == attributes.length() {
-                if attributes.get_named_item("rid").is_some() {
-                    for attr in &self.attrs {
-                        if let Some(attribute) = attributes.get_named_item(&attr.key) {
-                            if attribute.value() != attr.value {
-                                same = false;
-                                break;
-                            }
-                        } else {
-                            same = false;
-                            break;
-                        }
-                    }
-                } else {
-                    same = false;
+                sync_idl(el, &attr.key, Some(&attr.value));
+                if attr.key == "ref" {
+                    register_ref(&attr.value, el);
                 }
-            } else {
-                same = false;
-            }
-            if same {
-                return;
             }
+            self.el = Some(el.clone());
+            return;
         }
         let parent = node.parent_node().unwrap();
         DOCUMENT.with(|document| {
@@ -579,28 +1012,29 @@ impl Elem {
         let mut attrs = attrs.iter();
         let mut attrs2 = attrs2.iter();
         while let Some(attr) = attrs.next() {
+            sync_idl(node, &attr.key, Some(&attr.value));
             if let Some(attr2) = attrs2.next() {
                 if attr.key == attr2.key {
                     if attr.value == attr2.value {
                         continue;
                     }
+                    update_attr(node, attr, Some(&attr2.value));
                 } else {
-                    node.remove_attribute(&attr.key).unwrap();
-                }
-                if !check_recall(node, attr) {
-                    node.set_attribute(&attr.key, &attr.value).unwrap();
+                    drop_attr(node, &attr.key);
+                    put_attr(node, attr);
                 }
+                check_recall(node, attr);
             } else {
                 while let Some(attr) = attrs.next() {
-                    node.set_attribute(&attr.key, &attr.value).unwrap();
+                    put_attr(node, attr);
                 }
                 return;
             }
         }
         if let Some(attr2) = attrs2.next() {
-            node.remove_attribute(&attr2.key).unwrap();
+            drop_attr(node, &attr2.key);
             while let Some(attr) = attrs.next() {
-                node.remove_attribute(&attr.key).unwrap();
+                drop_attr(node, &attr.key);
             }
         }
     }
@@ -613,26 +1047,293 @@ impl Elem {
         if self.vcheck(old) {
             return;
         }
-        let parent = old.node().parent_node().unwrap();
+        let Some(old) = old.node() else {
+            return;
+        };
+        let parent = old.parent_node().unwrap();
         DOCUMENT.with(|document| {
             let new = self.to_node(&document);
 
-            parent.replace_child(&new, &old.node()).unwrap();
+            parent.replace_child(&new, &old).unwrap();
         });
     }
     fn vlast(&mut self, old: &Rsx) {
         if self.vcheck(old) {
             return;
         }
-        let parent = old.node().parent_node().unwrap();
+        let Some(old) = old.node() else {
+            return;
+        };
+        let parent = old.parent_node().unwrap();
         DOCUMENT.with(|document| {
             let new = self.to_node(&document);
 
-            parent.replace_child(&new, &old.node()).unwrap();
+            parent.replace_child(&new, &old).unwrap();
         });
     }
 }
 
+pub(crate) fn component_index() -> Option<String> {
+    NODE_ID.with(|n| {
+        let node_id = n.borrow();
+        CTX.with(|c| c.borrow().get(&*node_id).map(|ctx| match ctx {
+            Ctx::R(s) => s.to_string(),
+        }))
+    })
+}
+
+fn register_ref(name: &str, el: &Element) {
+    let index = component_index().unwrap_or_default();
+    NODE_REFS.with(|r| {
+        r.borrow_mut().entry(index).or_default().insert(name.to_string(), el.clone());
+    });
+}
+
+/// Gets the element rendered with `ref="name"` by the current component.
+pub fn node_ref(name: &str) -> Option<Element> {
+    let index = component_index().unwrap_or_default();
+    NODE_REFS.with(|r| r.borrow().get(&index).and_then(|refs| refs.get(name).cloned()))
+}
+
+pub fn clear_refs(index: &str) {
+    NODE_REFS.with(|r| r.borrow_mut().remove(index));
+}
+
+/// Runs `f` once the component being mounted is in the DOM, or right away
+/// when it is already mounted.
+pub fn on_mount(f: impl FnOnce() + 'static) {
+    let f = MOUNT_HOOKS.with(|h| match &mut *h.borrow_mut() {
+        Some(hooks) => {
+            hooks.push(Box::new(f));
+            None
+        }
+        None => Some(f),
+    });
+    if let Some(f) = f {
+        f();
+    }
+}
+
+/// The owner set by `Owner::run`, or else that of the current component.
+fn current_owner() -> owner::Owner {
+    owner::current().unwrap_or_else(|| owner::component(&component_index().unwrap_or_default()))
+}
+
+/// Runs `f` with the current component's owner current, so owners created and
+/// components mounted while it renders become its children.
+fn in_component<R>(f: impl FnOnce() -> R) -> R {
+    match component_index() {
+        Some(index) => owner::component(&index).run(f),
+        None => f(),
+    }
+}
+
+/// Runs `f` when the current owner is disposed, which by default is the
+/// owner of the current component, disposed when it unmounts.
+pub fn on_unmount(f: impl FnOnce() + 'static) {
+    current_owner().on_cleanup(f);
+}
+
+/// Provides `value` to the current owner's subtree, shadowing any context of
+/// the same type provided further out.
+pub fn provide_context<T: 'static>(value: T) {
+    current_owner().provide(value);
+}
+
+/// The nearest context of type `T`, looked up from the current owner outward.
+pub fn use_context<T: 'static>() -> Option<Rc<T>> {
+    current_owner().lookup()
+}
+
+/// Forgets the mounted component at `index`, cancelling its tasks and running
+/// its `on_unmount` callbacks.
+pub fn unmount(index: &str) {
+    MOUNTED.with(|m| m.borrow_mut().remove(index));
+    RENDERERS.with(|r| r.borrow_mut().remove(index));
+    VIRT_NODES.with(|v| v.borrow_mut().remove(index));
+    clear_refs(index);
+    task::cancel_component(index);
+    owner::dispose_component(index);
+}
+
+/// The component index of an `<!--av a:id=..-->` marker.
+fn marker_index(comment: &str) -> Option<&str> {
+    comment.strip_prefix("av ")?.split(' ').find_map(|attr| attr.strip_prefix("a:id="))
+}
+
+fn queue_unmount(index: &str) {
+    UNMOUNTED.with(|u| {
+        let mut unmounted = u.borrow_mut();
+        if !unmounted.iter().any(|i| i == index) {
+            unmounted.push(index.to_string());
+        }
+    });
+}
+
+/// Unmounts the components whose markers were removed from the DOM.
+fn flush_unmounted() {
+    let unmounted = UNMOUNTED.with(|u| std::mem::take(&mut *u.borrow_mut()));
+    for index in unmounted {
+        unmount(&index);
+    }
+}
+
+const VOID_ELEMENTS: [&str; 13] = ["area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track", "wbr"];
+
+/// Whether `name` is an element that never has children, in any case.
+pub(crate) fn is_void(name: &str) -> bool {
+    VOID_ELEMENTS.iter().any(|v| v.eq_ignore_ascii_case(name))
+}
+
+/// `value` as the type `current` has, so flags like `indeterminate` get a bool
+/// and `scrollTop` a number. Other properties, like an input's `value`, keep
+/// the string as written.
+fn prop_value(current: &JsValue, value: &str) -> JsValue {
+    let typed = if current.as_bool().is_some() {
+        value.parse::<bool>().ok().map(JsValue::from_bool)
+    } else if current.as_f64().is_some() {
+        value.parse::<f64>().ok().map(JsValue::from_f64)
+    } else {
+        None
+    };
+    typed.unwrap_or_else(|| JsValue::from_str(value))
+}
+
+fn set_prop(node: &Element, prop: &str, value: &str) {
+    let prop = JsValue::from_str(prop);
+    let value = custom::prop_value(node, value).unwrap_or_else(|| {
+        let current = js_sys::Reflect::get(node, &prop).unwrap_or(JsValue::UNDEFINED);
+        prop_value(&current, value)
+    });
+    js_sys::Reflect::set(node, &prop, &value).expect("problem setting property");
+}
+
+fn put_attr(node: &Element, attr: &Attribute) {
+    if let Some(prop) = attr.key.strip_prefix("prop:") {
+        set_prop(node, prop, &attr.value);
+    } else {
+        svg::set_attr(node, &attr.key, &attr.value);
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Idl {
+    Text(String),
+    Flag(bool),
+}
+
+/// The property value an input's `value`, `checked` or `selected` attribute
+/// should leave the live element with. `None` means the attribute is absent.
+fn idl_target(key: &str, value: Option<&str>) -> Option<Idl> {
+    match key {
+        "value" => Some(Idl::Text(value.unwrap_or_default().to_string())),
+        "checked" | "selected" => Some(Idl::Flag(value.is_some_and(|v| v != "false"))),
+        _ => None,
+    }
+}
+
+/// Sets form state the user can change, which the attribute stops reflecting
+/// once they do, as a property when it differs from the live one.
+fn sync_idl(node: &Element, key: &str, value: Option<&str>) {
+    let Some(target) = idl_target(key, value) else {
+        return;
+    };
+    let prop = JsValue::from_str(key);
+    if !js_sys::Reflect::has(node, &prop).unwrap_or(false) {
+        return;
+    }
+    let target = match target {
+        Idl::Text(s) => JsValue::from_str(&s),
+        Idl::Flag(b) => JsValue::from_bool(b),
+    };
+    if js_sys::Reflect::get(node, &prop).ok().as_ref() != Some(&target) {
+        js_sys::Reflect::set(node, &prop, &target).expect("problem setting property");
+    }
+}
+
+/// Splits two class lists into the tokens to remove and the tokens to add.
+fn token_diff<'a>(old: &'a str, new: &'a str) -> (Vec<&'a str>, Vec<&'a str>) {
+    let old: Vec<&str> = old.split_whitespace().collect();
+    let new: Vec<&str> = new.split_whitespace().collect();
+    let removed = old.iter().filter(|t| !new.contains(t)).copied().collect();
+    let added = new.iter().filter(|t| !old.contains(t)).copied().collect();
+    (removed, added)
+}
+
+fn declarations(style: &str) -> Vec<(&str, &str)> {
+    style.split(';').filter_map(|d| d.split_once(':')).map(|(p, v)| (p.trim(), v.trim())).filter(|(p, _)| !p.is_empty()).collect()
+}
+
+/// Splits two inline styles into the properties to remove and the declarations to set.
+fn style_diff<'a>(old: &'a str, new: &'a str) -> (Vec<&'a str>, Vec<(&'a str, &'a str)>) {
+    let old = declarations(old);
+    let new = declarations(new);
+    let removed = old.iter().filter(|(p, _)| !new.iter().any(|(q, _)| q == p)).map(|(p, _)| *p).collect();
+    let set = new.into_iter().filter(|d| !old.contains(d)).collect();
+    (removed, set)
+}
+
+/// Writes a changed attribute. `class` and `style` only touch the tokens and
+/// declarations that differ from `old`, leaving the rest of the list alone.
+fn update_attr(node: &Element, attr: &Attribute, old: Option<&str>) {
+    let html = node.dyn_ref::<HtmlElement>();
+    match (attr.key.as_str(), old, html) {
+        ("class", Some(old), _) => {
+            let (removed, added) = token_diff(old, &attr.value);
+            let list = node.class_list();
+            for token in removed {
+                list.remove_1(token).unwrap();
+            }
+            for token in added {
+                list.add_1(token).unwrap();
+            }
+        }
+        ("style", Some(old), Some(html)) => {
+            let (removed, set) = style_diff(old, &attr.value);
+            let style = html.style();
+            for prop in removed {
+                style.remove_property(prop).unwrap();
+            }
+            for (prop, value) in set {
+                match value.strip_suffix("!important") {
+                    Some(value) => style.set_property_with_priority(prop, value.trim_end(), "important").unwrap(),
+                    None => style.set_property(prop, value).unwrap(),
+                }
+            }
+        }
+        _ => put_attr(node, attr),
+    }
+}
+
+fn drop_attr(node: &Element, key: &str) {
+    if !key.starts_with("prop:") {
+        svg::remove_attr(node, key);
+    }
+}
+
+/// Bookkeeping attributes the runtime adds to rendered elements.
+pub(crate) const FRAMEWORK_ATTRS: [&str; 4] = ["rid", "a:id", "data-anansi-src", "data-anansi-meta"];
+
+/// The live attributes missing from `attrs`, leaving the runtime's own alone.
+fn stale_attrs<'a>(live: &'a [String], attrs: &[Attribute]) -> Vec<&'a str> {
+    live.iter().map(|k| k.as_str()).filter(|k| !FRAMEWORK_ATTRS.contains(k) && !attrs.iter().any(|a| a.key == *k)).collect()
+}
+
+/// The attributes whose live value differs. Unchanged `on:` bindings are left
+/// out so they keep their `rid`.
+fn changed_attrs(live: impl Fn(&str) -> Option<String>, attrs: &[Attribute]) -> Vec<&Attribute> {
+    attrs.iter().filter(|a| !a.key.starts_with("prop:") && live(&a.key).as_ref() != Some(&a.value)).collect()
+}
+
+/// Points `node` at a fresh recall entry for its `on:` binding, dropping the
+/// entry of its previous `rid` so rebinding doesn't leak.
+fn rebind_recall(recalls: &mut HashMap<String, RecallData>, old: Option<&str>, rid: String, data: RecallData) {
+    if let Some(old) = old {
+        recalls.remove(old);
+    }
+    recalls.insert(rid, data);
+}
+
 fn check_recall(node: &Element, attr: &Attribute) -> bool {
     let mut b = false;
     if attr.key.starts_with("on:") {
@@ -644,14 +1345,16 @@ fn check_recall(node: &Element, attr: &Attribute) -> bool {
             RID.with(|r| {
                 let mut r = r.borrow_mut();
                 let rs = r.to_string();
+                let old = node.get_attribute("rid");
                 node.set_attribute("rid", &rs).unwrap();
                 RECALLS.with(|rc| {
-                    rc.borrow_mut().insert(rs, RecallData {call: cb.call, ids: ids.to_string()});
+                    rebind_recall(&mut rc.borrow_mut(), old.as_deref(), rs, RecallData {call: cb.call, ids: ids.to_string()});
                 });
                 *r += 1;
                 b = true;
             });
         });
+        custom::listen(node, &attr.key);
     }
     b
 }
@@ -659,7 +1362,11 @@ fn check_recall(node: &Element, attr: &Attribute) -> bool {
 #[macro_export]
 macro_rules! element {
     ($n:literal, $a:expr, $c: expr) => {
-        Rsx::Element(Elem {name: $n, attrs: $a, children: $c, el: None})
+        {
+            let attrs: Vec<Attribute> = $a;
+            let key = attrs.iter().find(|a| a.key == "key").map(|a| a.value.clone());
+            Rsx::Element(Elem {name: $n, attrs, children: $c, key, meta: None, holes: None, el: None})
+        }
     }
 }
 
@@ -677,33 +1384,225 @@ impl Txt {
     }
 }
 
+/// Pre-rendered markup, inserted as the nodes it parses to between an
+/// `<!--av-->` and a `<!--/av-->` marker. It is neither escaped nor
+/// sanitized: the caller must make sure it is safe.
 #[derive(Debug, Clone)]
-pub struct Keys {
-    parent: Option<Node>,
-    children: Vec<Rsx>,
+pub struct Html {
+    html: String,
+    start: Option<Node>,
+    end: Option<Node>,
 }
 
-impl Keys {
-    fn kcheck(&mut self, k: &Self) {
-        self.parent = k.parent.clone();
-        let parent = self.parent.clone().expect("expected parent");
-        let mut children = self.children.iter_mut().map(|c| if let Rsx::Element(e) = c {e} else {unimplemented!()});
-        let mut children2 = k.children.iter().map(|c| if let Rsx::Element(e) = c {e} else {unimplemented!()});
-        let mut c1 = if let Some(c) = children.next() {
-            c
+impl Html {
+    /// Parses the markup into a fragment delimited by run markers. The start
+    /// marker remembers the markup so an unchanged rerender can keep the nodes.
+    fn build_node(&mut self, document: &Document) -> Node {
+        let template = document.create_element("template").unwrap().dyn_into::<HtmlTemplateElement>().unwrap();
+        template.set_inner_html(&self.html);
+        let content = template.content();
+        let start = document.create_comment("av").dyn_into::<Node>().unwrap();
+        let end = document.create_comment("/av").dyn_into::<Node>().unwrap();
+        content.insert_before(&start, content.first_child().as_ref()).unwrap();
+        content.append_child(&end).unwrap();
+        RAW_HTML.with(|m| m.set(start.unchecked_ref(), &JsValue::from_str(&self.html)));
+        self.start = Some(start);
+        self.end = Some(end);
+        content.dyn_into::<Node>().unwrap()
+    }
+    /// Whether the run opened at `node` was rendered from the same markup.
+    fn rendered_at(&self, node: &Node) -> bool {
+        is_run_start(node) && RAW_HTML.with(|m| m.get(node.unchecked_ref()).as_string()).as_deref() == Some(&self.html)
+    }
+    /// Diffs the run opened at `node`, leaving `node` at its closing marker.
+    /// Unchanged markup keeps its nodes; otherwise the whole run is replaced.
+    fn update(&mut self, node: &mut Node) {
+        if self.rendered_at(node) {
+            self.start = Some(node.clone());
+            self.end = Some(run_end(node));
         } else {
-            while let Some(child) = children2.next() {
-                parent.remove_child(&child.node()).unwrap();
+            let new = DOCUMENT.with(|document| self.build_node(document));
+            Html::replace(&new, &run_nodes(node));
+        }
+        *node = self.end.clone().unwrap();
+    }
+    fn vupdate(&mut self, old: &Rsx) {
+        if let Rsx::Html(h) = old {
+            if h.html == self.html {
+                self.start = h.start.clone();
+                self.end = h.end.clone();
+                return;
             }
-            return;
-        };
-        DOCUMENT.with(|doc| {
-            let mut c2 = if let Some(c) = children2.next() {
+        }
+        let new = DOCUMENT.with(|document| self.build_node(document));
+        Html::replace(&new, &rendered_nodes(old));
+    }
+    /// Puts `new`, as returned by `build_node`, in place of `old`, the live
+    /// nodes of what was rendered here before.
+    fn replace(new: &Node, old: &[Node]) {
+        let parent = old[0].parent_node().unwrap();
+        parent.insert_before(new, Some(&old[0])).unwrap();
+        RECALLS.with(|r| {
+            let mut recall = r.borrow_mut();
+            for node in old {
+                remove_recall(&mut recall, &parent, node);
+            }
+        });
+    }
+}
+
+/// Children rendered inline between an `<!--av-->` and a `<!--/av-->`
+/// marker, so their run is diffed on its own however its length changes.
+#[derive(Debug, Clone)]
+pub struct Fragment {
+    children: Vec<Rsx>,
+    start: Option<Node>,
+    end: Option<Node>,
+}
+
+impl Fragment {
+    fn build_node(&mut self, document: &Document) -> Node {
+        let fragment = document.create_document_fragment();
+        let start = document.create_comment("av").dyn_into::<Node>().unwrap();
+        let end = document.create_comment("/av").dyn_into::<Node>().unwrap();
+        fragment.append_child(&start).unwrap();
+        for child in &mut self.children {
+            fragment.append_child(&child.to_node(document)).unwrap();
+        }
+        fragment.append_child(&end).unwrap();
+        self.start = Some(start);
+        self.end = Some(end);
+        fragment.dyn_into::<Node>().unwrap()
+    }
+    /// Diffs the run opened at `node` against the children, leaving `node` at
+    /// the closing marker. A node that doesn't open a run is replaced.
+    fn update(&mut self, node: &mut Node) {
+        if !is_run_start(node) {
+            let new = DOCUMENT.with(|document| self.build_node(document));
+            let parent = node.parent_node().unwrap();
+            parent.insert_before(&new, Some(node)).unwrap();
+            RECALLS.with(|r| remove_recall(&mut r.borrow_mut(), &parent, node));
+        } else {
+            self.start = Some(node.clone());
+            let mut first = node.next_sibling().unwrap();
+            if avcheck(&first) {
+                self.fill(node);
+            } else if self.children.is_empty() {
+                let parent = node.parent_node().unwrap();
+                RECALLS.with(|r| {
+                    let mut recall = r.borrow_mut();
+                    while !avcheck(&first) {
+                        let next = run_end(&first).next_sibling().unwrap();
+                        for n in run_nodes(&first) {
+                            remove_recall(&mut recall, &parent, &n);
+                        }
+                        first = next;
+                    }
+                });
+            } else {
+                check_siblings(&mut self.children, &mut first);
+            }
+            self.end = Some(run_end(node));
+        }
+        *node = self.end.clone().unwrap();
+    }
+    /// Inserts every child after `start`, in order.
+    fn fill(&mut self, start: &Node) {
+        let mut cursor = start.clone();
+        for child in &mut self.children {
+            child.edit(&cursor);
+            cursor = run_end(&cursor.next_sibling().unwrap());
+        }
+    }
+    fn vupdate(&mut self, old: &Fragment) {
+        self.start = old.start.clone();
+        self.end = old.end.clone();
+        if old.children.is_empty() {
+            let start = old.start.clone().unwrap();
+            self.fill(&start);
+        } else if self.children.is_empty() {
+            let parent = self.start.as_ref().unwrap().parent_node().unwrap();
+            RECALLS.with(|r| {
+                let mut recall = r.borrow_mut();
+                for child in &old.children {
+                    for n in rendered_nodes(child) {
+                        remove_recall(&mut recall, &parent, &n);
+                    }
+                }
+            });
+        } else {
+            vcheck_both(&mut self.children, &old.children);
+        }
+    }
+}
+
+fn is_run_start(node: &Node) -> bool {
+    node.node_type() == Node::COMMENT_NODE && node.text_content().is_some_and(|c| c == "av" || c.starts_with("av "))
+}
+
+/// How many markers into `markers` the run opened by the first one closes.
+fn run_length<'a>(markers: impl IntoIterator<Item = Option<&'a str>>) -> Option<usize> {
+    let mut depth = 0;
+    for (n, marker) in markers.into_iter().enumerate() {
+        match marker {
+            Some(m) if m == "av" || m.starts_with("av ") => depth += 1,
+            Some("/av") => depth -= 1,
+            _ => {}
+        }
+        if depth == 0 {
+            return Some(n);
+        }
+    }
+    None
+}
+
+/// The nodes from `node` through the end of the marked run it opens, or just
+/// `node` when it doesn't open one.
+fn run_nodes(node: &Node) -> Vec<Node> {
+    if !is_run_start(node) {
+        return vec![node.clone()];
+    }
+    let mut nodes = vec![node.clone()];
+    while let Some(next) = nodes.last().unwrap().next_sibling() {
+        nodes.push(next);
+    }
+    let markers = nodes.iter().map(|n| if n.node_type() == Node::COMMENT_NODE { n.text_content() } else { None }).collect::<Vec<_>>();
+    let len = run_length(markers.iter().map(|m| m.as_deref())).expect("unclosed av run");
+    nodes.truncate(len + 1);
+    nodes
+}
+
+fn run_end(node: &Node) -> Node {
+    run_nodes(node).pop().unwrap()
+}
+
+#[derive(Debug, Clone)]
+pub struct Keys {
+    parent: Option<Node>,
+    children: Vec<Rsx>,
+}
+
+impl Keys {
+    fn kcheck(&mut self, k: &Self) {
+        self.parent = k.parent.clone();
+        let parent = self.parent.clone().expect("expected parent");
+        let mut children = self.children.iter_mut().map(|c| if let Rsx::Element(e) = c {e} else {unimplemented!()});
+        let mut children2 = k.children.iter().map(|c| if let Rsx::Element(e) = c {e} else {unimplemented!()});
+        let mut c1 = if let Some(c) = children.next() {
+            c
+        } else {
+            while let Some(child) = children2.next() {
+                switch::detach(&parent, child);
+            }
+            return;
+        };
+        DOCUMENT.with(|doc| {
+            let mut c2 = if let Some(c) = children2.next() {
                 c
             } else {
-                parent.append_child(&c1.to_node(&doc)).unwrap();
+                parent.append_child(&keyed_node(c1, doc)).unwrap();
                 while let Some(child) = children.next() {
-                    parent.append_child(&child.to_node(&doc)).unwrap();
+                    parent.append_child(&keyed_node(child, doc)).unwrap();
                 }
                 return;
             };
@@ -719,18 +1618,18 @@ impl Keys {
                             c2 = c;
                             continue;
                         } else {
-                            parent.append_child(&c1.to_node(&doc)).unwrap();
+                            parent.append_child(&keyed_node(c1, doc)).unwrap();
                             while let Some(child) = children.next() {
-                                parent.append_child(&child.to_node(&doc)).unwrap();
+                                parent.append_child(&keyed_node(child, doc)).unwrap();
                             }
                             return;
                         }
                     } else {
                         while let Some(child) = children2.next() {
-                            parent.remove_child(&child.node()).unwrap();
+                            switch::detach(&parent, child);
                         }
                         for (c, _) in old.values() {
-                            parent.remove_child(&c.node()).unwrap();
+                            switch::detach(&parent, c);
                         }
                         return;
                     }
@@ -755,7 +1654,8 @@ impl Keys {
                             n += 1;
                         }
                         if n == k.children.len() as u32 {
-                            parent.replace_child(&c1.to_node(doc), &c2.node()).unwrap();
+                            switch::park(c2);
+                            parent.replace_child(&enter(template::instantiate(c1, doc)), &c2.node()).unwrap();
                         }
                     }
                     if let Some(c) = children.next() {
@@ -764,16 +1664,16 @@ impl Keys {
                             c2 = c;
                             b = true;
                         } else {
-                            parent.append_child(&c1.to_node(&doc)).unwrap();
+                            parent.append_child(&keyed_node(c1, doc)).unwrap();
                             while let Some(child) = children.next() {
-                                parent.append_child(&child.to_node(&doc)).unwrap();
+                                parent.append_child(&keyed_node(child, doc)).unwrap();
                             }
                             return;
                         }
                     } else {
                         while let Some(child) = children2.next() {
                             if !old.contains_key(&child.attrs[0].value) {
-                                parent.remove_child(&child.node()).unwrap();
+                                switch::detach(&parent, child);
                             }
                         }
                         return;
@@ -781,13 +1681,13 @@ impl Keys {
                     if b {
                         continue;
                     }
-                    parent.insert_before(&c1.to_node(&doc), Some(&c2.node())).unwrap();
+                    parent.insert_before(&enter(template::instantiate(c1, &doc)), Some(&c2.node())).unwrap();
                     if let Some(c) = children.next() {
                         c1 = c;
                         continue;
                     }
                     while let Some(child) = children2.next() {
-                        parent.remove_child(&child.node()).unwrap();
+                        switch::detach(&parent, child);
                     }
                     
                 }
@@ -802,12 +1702,37 @@ pub enum Rsx {
     Element(Elem),
     Text(Txt),
     Keyed(Keys),
+    Html(Html),
+    Fragment(Fragment),
 }
 
 impl Rsx {
     pub fn component() -> Self {
         Rsx::Component(Comp {children: vec![]})
     }
+    pub fn key(&self) -> Option<&str> {
+        if let Rsx::Element(e) = self {
+            e.key.as_deref()
+        } else {
+            None
+        }
+    }
+    /// Marks which attributes and text children of an element, by index, are
+    /// dynamic at the macro call site `site`, all others being the same on every
+    /// render. Other nodes are returned unchanged.
+    pub fn with_holes(mut self, site: &'static str, attrs: &'static [usize], texts: &'static [usize]) -> Self {
+        if let Rsx::Element(e) = &mut self {
+            e.holes = Some(template::Holes {site, attrs, texts});
+        }
+        self
+    }
+    /// Attaches tooling metadata to an element. Other nodes are returned unchanged.
+    pub fn with_meta(mut self, meta: impl Into<String>) -> Self {
+        if let Rsx::Element(e) = &mut self {
+            e.meta = Some(meta.into());
+        }
+        self
+    }
     pub fn as_elem(&self) -> &Elem {
         if let Rsx::Element(e) = self {
             e
@@ -821,6 +1746,15 @@ impl Rsx {
     pub fn new_keyed(children: Vec<Rsx>) -> Self {
         Rsx::Keyed(Keys {parent: None, children})
     }
+    /// Several roots rendered inline, without a wrapper element.
+    pub fn new_fragment(children: Vec<Rsx>) -> Self {
+        Rsx::Fragment(Fragment {children, start: None, end: None})
+    }
+    /// Raw markup, e.g. rendered markdown. It is inserted unescaped, so only
+    /// pass HTML that has already been sanitized.
+    pub fn new_html(html: impl Into<String>) -> Self {
+        Rsx::Html(Html {html: html.into(), start: None, end: None})
+    }
     fn attach_to_element(&mut self, el: &Element, document: &Document) {
         match self {
             Self::Element(elem) => {
@@ -829,56 +1763,65 @@ impl Rsx {
             Self::Text(text) => {
                 el.append_child(&text.to_node(document)).unwrap();
             }
-            Self::Component(_) => unimplemented!(),
-            Self::Keyed(keyed) => {
-                for child in &mut keyed.children {
+            Self::Html(html) => {
+                el.append_child(&html.build_node(document)).unwrap();
+            }
+            Self::Fragment(fragment) => {
+                el.append_child(&fragment.build_node(document)).unwrap();
+            }
+            Self::Component(Comp {children}) | Self::Keyed(Keys {children, ..}) => {
+                for child in children {
                     el.append_child(&child.to_node(document)).unwrap();
                 }
             }
         }
     }
+    /// The first element or text node this renders, looking through components and keyed lists.
+    fn first(&self) -> Option<&Self> {
+        match self {
+            Self::Element(_) | Self::Text(_) | Self::Html(_) | Self::Fragment(_) => Some(self),
+            Self::Component(Comp {children}) | Self::Keyed(Keys {children, ..}) => children.iter().find_map(|c| c.first()),
+        }
+    }
     fn edit(&mut self, node: &Node) {
         DOCUMENT.with(|document| {
             match self {
                 Self::Element(elem) => {
-                    let new = elem.to_node(&document);
+                    let new = enter(elem.to_node(&document));
                     add_sibling(node, &new);
                 }
                 Self::Text(text) => {
                     let new = text.to_node(&document);
                     add_sibling(node, &new);
                 }
-                Self::Component(_) => unimplemented!(),
-                Self::Keyed(_) => unimplemented!(),
-            }
-        });
-    }
-    fn parent_node(&self) -> Option<Node> {
-        match self {
-            Self::Element(elem) => {
-                elem.el.as_ref().expect("expected element").parent_node()
-            }
-            Self::Text(text) => {
-                if let Some(node) = text.node.clone() {
-                    node.parent_node()
-                } else {
-                    panic!("expected node for text: {}", text.text)
+                Self::Html(html) => {
+                    let new = html.build_node(document);
+                    add_sibling(node, &new);
+                }
+                Self::Fragment(fragment) => {
+                    let new = fragment.build_node(document);
+                    add_sibling(node, &new);
+                }
+                Self::Component(_) | Self::Keyed(_) => {
+                    let new = self.to_node(&document);
+                    add_sibling(node, &new);
                 }
             }
-            Self::Component(_) => unimplemented!(),
-            Self::Keyed(_) => unimplemented!(),
-        }
+        });
     }
-    fn node(&self) -> Node {
+    /// The last node this was rendered to, or `None` for a component or keyed
+    /// list that rendered nothing and so has no place in the document.
+    fn node(&self) -> Option<Node> {
         match self {
             Self::Element(elem) => {
-                elem.node()
+                Some(elem.node())
             }
             Self::Text(text) => {
-                text.node.clone().expect("expected text node").dyn_into::<Node>().expect("expected node")
+                Some(text.node.clone().expect("expected text node").dyn_into::<Node>().expect("expected node"))
             }
-            Self::Component(_) => unimplemented!(),
-            Self::Keyed(_) => unimplemented!(),
+            Self::Html(html) => Some(html.end.clone().expect("expected html marker")),
+            Self::Fragment(fragment) => Some(fragment.end.clone().expect("expected fragment marker")),
+            Self::Component(_) | Self::Keyed(_) => self.first().and_then(Self::node),
         }
     }
     fn to_node(&mut self, document: &Document) -> Node {
@@ -889,8 +1832,19 @@ impl Rsx {
             Self::Text(text) => {
                 text.to_node(document)
             }
-            Self::Component(_) => unimplemented!(),
-            Self::Keyed(_) => unimplemented!(),
+            Self::Html(html) => {
+                html.build_node(document)
+            }
+            Self::Fragment(fragment) => {
+                fragment.build_node(document)
+            }
+            Self::Component(Comp {children}) | Self::Keyed(Keys {children, ..}) => {
+                let fragment = document.create_document_fragment();
+                for child in children {
+                    fragment.append_child(&child.to_node(document)).unwrap();
+                }
+                fragment.dyn_into::<Node>().unwrap()
+            }
         }
     }
     fn children(&self) -> Option<&Vec<Self>> {
@@ -905,6 +1859,10 @@ impl Rsx {
             Self::Text(_) => {
                 unimplemented!();
             }
+            Self::Html(_) => None,
+            Self::Fragment(fragment) => {
+                Some(&fragment.children)
+            }
             Self::Component(comp) => {
                 Some(&comp.children)
             }
@@ -940,6 +1898,41 @@ impl AppState {
     pub fn subs_mut(&mut self) -> &mut Vec<Vec<Sub>> {
         &mut self.subs
     }
+    pub fn obj(&self, n: usize) -> Option<&Obj> {
+        self.objs.get(n)
+    }
+    /// Describes slot `n`, or `ObjKind::Missing` when it is out of range.
+    pub fn obj_kind(&self, n: usize) -> ObjKind {
+        match self.objs.get(n) {
+            Some(Obj::Rs(_)) => ObjKind::Rs,
+            Some(Obj::Js(v)) => ObjKind::Js(match v {
+                Value::Null => JsonKind::Null,
+                Value::Bool(_) => JsonKind::Bool,
+                Value::Number(_) => JsonKind::Number,
+                Value::String(_) => JsonKind::String,
+                Value::Array(_) => JsonKind::Array,
+                Value::Object(_) => JsonKind::Object,
+            }),
+            None => ObjKind::Missing,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjKind {
+    Rs,
+    Js(JsonKind),
+    Missing,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JsonKind {
+    Null,
+    Bool,
+    Number,
+    String,
+    Array,
+    Object,
 }
 
 #[derive(Clone)]
@@ -948,25 +1941,60 @@ pub enum Obj {
     Js(Value),
 }
 
+const HTML_SPECIAL: [char; 5] = ['<', '>', '\'', '"', '&'];
+
 pub fn html_escape(s: &str) -> String {
-    let mut escaped = String::new();
-    for c in s.chars() {
-        let html = match c {
-            '<' => "&lt;",
-            '>' => "&gt;",
-            '\'' => "&#x27;",
-            '"' => "&quot;",
-            '&' => "&amp;",
-            _ => {
-                escaped.push(c);
-                continue;
-            }
-        };
-        escaped.push_str(html);
-    }
+    let mut escaped = String::with_capacity(s.len());
+    html_escape_to(s, &mut escaped);
     escaped
 }
 
+/// Appends `s` to `out`, escaped for text content.
+pub fn html_escape_to(s: &str, out: &mut String) {
+    let mut rest = s;
+    while let Some(n) = rest.find(HTML_SPECIAL) {
+        out.push_str(&rest[..n]);
+        out.push_str(match rest.as_bytes()[n] {
+            b'<' => "&lt;",
+            b'>' => "&gt;",
+            b'\'' => "&#x27;",
+            b'"' => "&quot;",
+            _ => "&amp;",
+        });
+        rest = &rest[n + 1..];
+    }
+    out.push_str(rest);
+}
+
+/// Like `html_escape`, but borrows `s` when nothing in it needs escaping.
+pub fn html_escape_cow(s: &str) -> Cow<'_, str> {
+    if s.contains(HTML_SPECIAL) {
+        Cow::Owned(html_escape(s))
+    } else {
+        Cow::Borrowed(s)
+    }
+}
+
+/// Escapes `s` for use inside a quoted attribute value. Only `&`, `<` and the
+/// quote characters are replaced, and `s` is borrowed when none appear.
+pub fn attr_escape(s: &str) -> Cow<'_, str> {
+    let Some(start) = s.find(['&', '<', '"', '\'']) else {
+        return Cow::Borrowed(s);
+    };
+    let mut escaped = String::with_capacity(s.len() + 8);
+    escaped.push_str(&s[..start]);
+    for c in s[start..].chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            _ => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
 pub struct RecallData {
     pub call: fn(),
     pub ids: String,
@@ -987,46 +2015,262 @@ macro_rules! log {
     };
 }
 
+/// Sets the class added to elements inserted by the diff and removed
+/// once the next frame has painted, so a CSS transition on that class animates them in.
+pub fn set_enter_class(class: Option<&str>) {
+    ENTER_CLASS.with(|e| *e.borrow_mut() = class.map(|c| c.to_string()));
+}
+
+fn enter(node: Node) -> Node {
+    if let Some(class) = ENTER_CLASS.with(|e| e.borrow().clone()) {
+        if let Some(el) = node.dyn_ref::<Element>() {
+            el.class_list().add_1(&class).unwrap();
+            let el = el.clone();
+            let cb = Closure::once_into_js(move || {
+                let cb = Closure::once_into_js(move || {
+                    el.class_list().remove_1(&class).unwrap();
+                });
+                WINDOW.with(|w| w.request_animation_frame(cb.unchecked_ref()).unwrap());
+            });
+            WINDOW.with(|w| w.request_animation_frame(cb.unchecked_ref()).unwrap());
+        }
+    }
+    node
+}
+
 fn add_sibling(node: &Node, new: &Node) {
     match node.node_type() {
         Node::ELEMENT_NODE => node.dyn_ref::<Element>().unwrap().after_with_node_1(new).unwrap(),
         Node::TEXT_NODE => node.dyn_ref::<Text>().unwrap().after_with_node_1(new).unwrap(),
+        Node::COMMENT_NODE => node.dyn_ref::<Comment>().unwrap().after_with_node_1(new).unwrap(),
         _ => unimplemented!(),
     }
 }
 
-pub fn get_state(document: &Document, ctx_map: &mut HashMap<String, Ctx>) -> Option<AppState> {
-    let script = document.query_selector_all("script[type='app/json']").unwrap().get(0).unwrap();
-    let text = script.text_content().unwrap();
-    let json: Value = serde_json::from_str(&text).unwrap();
-    let values = json.as_object().unwrap();
-    let ctx = values.get("ctx").unwrap();
-    let contexts = ctx.as_object().unwrap();
+/// Overrides the selector used to find the state script, e.g. `script[type='app/x-state']`
+/// when a strict CSP requires a nonced, uniquely-typed tag.
+pub fn set_state_selector(selector: &str) {
+    STATE_SELECTOR.with(|s| *s.borrow_mut() = selector.to_string());
+}
+
+/// Reads and removes the state script. Gives `Ok(None)` when the page has
+/// none, so the caller can fall back to rendering on the client alone.
+pub fn get_state(document: &Document, ctx_map: &mut HashMap<String, Ctx>) -> Result<Option<AppState>, StateError> {
+    get_island_state(document, None, ctx_map)
+}
+
+/// `get_state` for the island whose state script has `data-island="{island}"`,
+/// leaving the other islands' scripts in place. `None` takes the first script
+/// without `data-island`.
+pub fn get_island_state(document: &Document, island: Option<&str>, ctx_map: &mut HashMap<String, Ctx>) -> Result<Option<AppState>, StateError> {
+    match load_state(document, island, ctx_map) {
+        Ok(state) => Ok(Some(state)),
+        Err(StateError::NoScript) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Reads and removes the state script, failing instead of panicking when it is
+/// missing or malformed.
+pub fn try_get_state(document: &Document, ctx_map: &mut HashMap<String, Ctx>) -> Result<AppState, HydrationError> {
+    Ok(load_state(document, None, ctx_map)?)
+}
+
+/// The index of the state script for `island` among scripts with the given
+/// `data-island` attributes.
+fn island_script(islands: &[Option<String>], island: Option<&str>) -> Option<usize> {
+    match island {
+        Some(island) => islands.iter().position(|i| i.as_deref() == Some(island)),
+        None => islands.iter().position(Option::is_none),
+    }
+}
+
+thread_local! {
+    static ISLAND_STATES: RefCell<HashMap<String, AppState>> = RefCell::new(HashMap::new());
+    static ISLAND_OF: RefCell<HashMap<String, String>> = RefCell::new(HashMap::new());
+}
+
+/// Makes `state` the state of the island `island`, whose components are the
+/// node ids in `contexts`.
+fn register_island(island: &str, state: AppState, contexts: HashMap<String, Ctx>) {
+    ISLAND_OF.with(|i| {
+        let mut islands = i.borrow_mut();
+        islands.insert(island.to_string(), island.to_string());
+        for node_id in contexts.keys() {
+            islands.insert(node_id.clone(), island.to_string());
+        }
+    });
+    CTX.with(|c| c.borrow_mut().extend(contexts));
+    ISLAND_STATES.with(|s| s.borrow_mut().insert(island.to_string(), state));
+}
+
+/// Loads the state of the island whose root has the node id `island`: its own
+/// `data-island` script when the page has one, otherwise the page's shared
+/// script. Gives `Ok(false)` when there is neither, so the caller can fall
+/// back to rendering on the client alone.
+pub fn load_island_state(island: &str) -> Result<bool, StateError> {
+    if ISLAND_OF.with(|i| i.borrow().contains_key(island)) {
+        return Ok(true);
+    }
+    let mut contexts = HashMap::new();
+    match DOCUMENT.with(|document| load_state(document, Some(island), &mut contexts)) {
+        Ok(state) => {
+            register_island(island, state, contexts);
+            return Ok(true);
+        }
+        Err(StateError::NoScript) => {}
+        Err(e) => return Err(e),
+    }
+    if APP_STATE.with(|a| a.borrow().is_some()) {
+        return Ok(true);
+    }
+    match DOCUMENT.with(|document| get_island_state(document, None, &mut contexts))? {
+        Some(state) => {
+            APP_STATE.with(|a| *a.borrow_mut() = Some(state));
+            CTX.with(|c| c.borrow_mut().extend(contexts));
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// Loads the state a component is mounted with, as `load_island_state` does.
+/// When there is none or it can't be read, the problem is logged and the
+/// component starts from an empty state instead, rendering on the client alone.
+/// Returns whether the server's state was loaded.
+pub fn mount_state(node_id: &str) -> bool {
+    match load_island_state(node_id) {
+        Ok(true) => return true,
+        Ok(false) => web_sys::console::warn_1(&format!("no state for {}, rendering on the client", node_id).into()),
+        Err(e) => web_sys::console::error_1(&format!("problem getting state for {}: {}, rendering on the client", node_id, e).into()),
+    }
+    APP_STATE.with(|a| {
+        a.borrow_mut().get_or_insert_with(|| AppState {objs: vec![], subs: vec![]});
+    });
+    false
+}
+
+/// Unmounts the components of the island `island` and drops their contexts and
+/// its state, so that it's loaded afresh from the next script for it.
+fn forget_island(island: &str) {
+    let node_ids: Vec<String> = ISLAND_OF.with(|i| {
+        let mut islands = i.borrow_mut();
+        let node_ids = islands.iter().filter(|(_, of)| *of == island).map(|(node_id, _)| node_id.clone()).collect();
+        islands.retain(|_, of| of != island);
+        node_ids
+    });
+    let indices: Vec<String> = CTX.with(|c| {
+        let mut contexts = c.borrow_mut();
+        node_ids.iter().filter_map(|node_id| contexts.remove(node_id)).map(|Ctx::R(index)| index).collect()
+    });
+    for index in indices {
+        unmount(&index);
+    }
+    ISLAND_STATES.with(|s| s.borrow_mut().remove(island));
+}
+
+/// Runs `f` on the state of the current component's island, or on the page
+/// state when the island has no script of its own. `None` when that state
+/// isn't loaded.
+pub fn with_state<R>(f: impl FnOnce(&mut AppState) -> R) -> Option<R> {
+    let node_id = NODE_ID.with(|n| n.borrow().clone());
+    match ISLAND_OF.with(|i| i.borrow().get(&node_id).cloned()) {
+        Some(island) => ISLAND_STATES.with(|s| s.borrow_mut().get_mut(&island).map(f)),
+        None => APP_STATE.with(|a| a.borrow_mut().as_mut().map(f)),
+    }
+}
+
+fn load_state(document: &Document, island: Option<&str>, ctx_map: &mut HashMap<String, Ctx>) -> Result<AppState, StateError> {
+    let selector = STATE_SELECTOR.with(|s| s.borrow().clone());
+    let scripts: Vec<Element> = document.query_selector_all(&selector).map(|s| (0..s.length()).filter_map(|i| s.get(i)?.dyn_into().ok()).collect()).unwrap_or_default();
+    let islands: Vec<Option<String>> = scripts.iter().map(|s| s.get_attribute("data-island")).collect();
+    let script = island_script(&islands, island).map(|n| &scripts[n]).ok_or(StateError::NoScript)?;
+    let text = script.text_content().unwrap_or_default();
+    let (state, cmap) = match script.get_attribute("data-format").as_deref() {
+        Some("packed") => parse_packed_state(&text)?,
+        _ => parse_state(&text)?,
+    };
+    script.remove();
+    *ctx_map = cmap;
+    Ok(state)
+}
+
+fn malformed(e: impl fmt::Display) -> HydrationError {
+    HydrationError::Malformed(e.to_string())
+}
+
+fn invalid(e: impl fmt::Display) -> StateError {
+    StateError::Malformed(e.to_string())
+}
+
+pub fn parse_state(text: &str) -> Result<(AppState, HashMap<String, Ctx>), StateError> {
+    state_from_value(serde_json::from_str(text).map_err(|e| StateError::InvalidJson(e.to_string()))?)
+}
+
+/// `parse_state` for a script written by a `Pauser` with `set_packed`.
+pub fn parse_packed_state(text: &str) -> Result<(AppState, HashMap<String, Ctx>), StateError> {
+    state_from_value(packed::unpack(text).map_err(invalid)?)
+}
+
+fn state_from_value(json: Value) -> Result<(AppState, HashMap<String, Ctx>), StateError> {
+    let values = json.as_object().ok_or_else(|| invalid("expected object"))?;
+    let ctx = values.get("ctx").ok_or(StateError::MissingField("ctx"))?;
+    let contexts = ctx.as_object().ok_or_else(|| invalid("expected ctx object"))?;
     let mut cmap = HashMap::new();
     for (id, n) in contexts {
-        let c = serde_json::from_value(n.clone()).unwrap();
+        let c = serde_json::from_value(n.clone()).map_err(invalid)?;
         cmap.insert(id.to_string(), c);
     }
-    let object_array = values.get("objs").unwrap();
+    let object_array = values.get("objs").ok_or(StateError::MissingField("objs"))?.as_array().ok_or_else(|| invalid("expected objs array"))?;
     let mut objs = vec![];
-    for object in object_array.as_array().unwrap() {
+    for object in object_array {
         objs.push(Obj::Js(object.clone()));
     }
-    let sub_array = values.get("subs").unwrap();
+    let sub_array = values.get("subs").ok_or(StateError::MissingField("subs"))?.as_array().ok_or_else(|| invalid("expected subs array"))?;
     let mut subs = vec![];
-    for arr in sub_array.as_array().unwrap() {
+    for arr in sub_array {
         let mut sv = vec![];
-        for sub in arr.as_array().unwrap() {
-            let s = sub.as_str().unwrap();
-            let nums: Vec<&str> = s.split(' ').collect();
-            sv.push((nums[0].parse().unwrap(), nums[1].parse().unwrap()));
+        for sub in arr.as_array().ok_or_else(|| invalid("expected subs array"))? {
+            let s = sub.as_str().ok_or_else(|| invalid("expected sub string"))?;
+            let (a, b) = s.split_once(' ').ok_or_else(|| invalid(s))?;
+            sv.push((a.parse().map_err(invalid)?, b.parse().map_err(invalid)?));
         }
         subs.push(sv);
     }
-    let parent = script.parent_node().unwrap();
-    parent.remove_child(&script).unwrap();
-    *ctx_map = cmap;
-    Some(AppState {objs, subs})
+    Ok((AppState {objs, subs}, cmap))
+}
+
+/// Checks that every `av` marker comment has a matching `/av`, naming the
+/// first unbalanced region.
+#[cfg(any(debug_assertions, test))]
+fn vnode_balance<'a>(markers: impl IntoIterator<Item = &'a str>) -> Result<(), String> {
+    let mut open = vec![];
+    for marker in markers {
+        if marker == "av" {
+            open.push("?".to_string());
+        } else if let Some(attrs) = marker.strip_prefix("av ") {
+            let id = attrs.split(' ').find_map(|a| a.strip_prefix("a:id=")).unwrap_or("?");
+            open.push(id.to_string());
+        } else if marker == "/av" && open.pop().is_none() {
+            return Err("unbalanced vnode markers: found /av with no open av region".to_string());
+        }
+    }
+    match open.last() {
+        Some(id) => Err(format!("unbalanced vnode markers: av region a:id={} is never closed", id)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(debug_assertions)]
+fn vnode_markers(nodes: &NodeList, markers: &mut Vec<String>) {
+    for i in 0..nodes.length() {
+        let node = nodes.get(i).unwrap();
+        if node.node_type() == Node::COMMENT_NODE {
+            markers.push(node.text_content().unwrap());
+        } else {
+            vnode_markers(&node.child_nodes(), markers);
+        }
+    }
 }
 
 fn check_vnodes(nodes: &NodeList, vnode_map: &mut HashMap<String, Node>) {
@@ -1058,14 +2302,40 @@ fn check_vnodes(nodes: &NodeList, vnode_map: &mut HashMap<String, Node>) {
 }
    
 pub fn setup(callbacks: HashMap<String, CallbackData>) {
-    std::panic::set_hook(Box::new(console_error_panic_hook::hook));
+    static HOOK: std::sync::Once = std::sync::Once::new();
+    HOOK.call_once(|| std::panic::set_hook(Box::new(|info| {
+        boundary::on_panic(info.payload_as_str().unwrap_or("render panicked"));
+        console_error_panic_hook::hook(info);
+    })));
+    MOUNTS.with(|m| {
+        let mut mounts = m.borrow_mut();
+        for (name, cb) in &callbacks {
+            if let Some((comp, _)) = name.split_once('_') {
+                mounts.entry(comp.to_string()).or_insert(cb.new);
+            }
+        }
+    });
     CALLBACKS.with(|c| {
         let mut cb = c.borrow_mut();
-        *cb = callbacks;
+        cb.extend(callbacks);
     });
 }
 
+/// Labels the elements created by the next `rerender` with `data-anansi-src`
+/// so devtools show which component produced them. Does nothing in release builds.
+pub fn render_source(name: &'static str) {
+    if cfg!(debug_assertions) {
+        RENDER_SOURCE.with(|s| *s.borrow_mut() = Some((name, 0)));
+    }
+}
+
 pub fn rerender(mut rsx: Rsx) {
+    if BATCH_DEPTH.with(|d| d.get()) > 0 && !SYNC.with(|s| s.get()) {
+        queue_render(rsx);
+        return;
+    }
+    scroll::capture();
+    focus::capture();
     CTX.with(|contexts| {
         let contexts = contexts.borrow();
         VNODE_MAP.with(|vnode_map| {
@@ -1082,6 +2352,14 @@ pub fn rerender(mut rsx: Rsx) {
                     } else {
                         DOCUMENT.with(|document| {
                             let nodes = document.body().unwrap().child_nodes();
+                            #[cfg(debug_assertions)]
+                            {
+                                let mut markers = vec![];
+                                vnode_markers(&nodes, &mut markers);
+                                if let Err(e) = vnode_balance(markers.iter().map(|m| m.as_str())) {
+                                    panic!("{}", e);
+                                }
+                            }
                             check_vnodes(&nodes, &mut vnode_map);
                             let mut node = vnode_map.get(vn_index).unwrap().clone().next_sibling().unwrap();
                             update(&mut rsx, &mut node);
@@ -1093,53 +2371,260 @@ pub fn rerender(mut rsx: Rsx) {
             });
         });
     });
+    scroll::restore();
+    focus::restore();
+    transition::play_pending();
+    flush_unmounted();
+    RENDER_SOURCE.with(|s| s.borrow_mut().take());
 }
 
-#[wasm_bindgen]
-pub fn recall(rid: &str) -> bool {
-    let mut b = false;
-    RECALLS.with(|r| {
-        let recalls = r.borrow();
-        if let Some(rc) = recalls.get(rid) {
-            let r = rc.call;
-            IDS.with(|id| {
-                let arr: Vec<String> = rc.ids.split(' ').map(|s| s.to_string()).collect();
-                *id.borrow_mut() = arr;
-            });
-            drop(recalls);
-            (r)();
-            b = true;
-        }
-    });
-    b
+/// Queues a rerender of the current component for the next animation frame,
+/// so several updates in one tick commit once. Inside `flush_sync` it commits
+/// immediately instead.
+pub fn rerender_batched(rsx: Rsx) {
+    if SYNC.with(|s| s.get()) {
+        rerender(rsx);
+        return;
+    }
+    if queue_render(rsx) {
+        let cb = Closure::once_into_js(flush_renders);
+        WINDOW.with(|w| w.request_animation_frame(cb.unchecked_ref()).unwrap());
+    }
 }
 
-fn check_mount(node_id: &str) -> bool {
-    let mut b = true;
-    CTX.with(|contexts| {
-        let contexts = contexts.borrow();
-        MOUNTED.with(|m| {
-            let mounted = m.borrow();
-            if let Some(vn_index) = contexts.get(node_id) {
-                let index = match vn_index {
-                    Ctx::R(s) => s,
-                };
-                if mounted.contains(index) {
-                    b = false;
-                }
-            }
-        });
-    });
-    b
+/// Queues `rsx` as the pending rerender of the current component, replacing an
+/// earlier one. Returns whether the queue was empty before.
+fn queue_render(rsx: Rsx) -> bool {
+    let node_id = NODE_ID.with(|n| n.borrow().clone());
+    let source = RENDER_SOURCE.with(|s| s.borrow_mut().take());
+    PENDING_RENDERS.with(|p| {
+        let mut pending = p.borrow_mut();
+        let was_empty = pending.is_empty();
+        pending.retain(|(id, _, _)| *id != node_id);
+        pending.push((node_id, source, rsx));
+        was_empty
+    })
 }
 
-#[wasm_bindgen]
-pub fn call(callback: &str, node_id: &str) -> Result<(), JsValue> {
-    let (name, arr) = callback.split_once('[').unwrap();
-    let (arr, _) = arr.rsplit_once(']').unwrap();
-    let arr: Vec<String> = arr.split(' ').map(|s| s.to_string()).collect();
+struct BatchGuard;
 
-    CALLBACKS.with(|c| {
+impl Drop for BatchGuard {
+    fn drop(&mut self) {
+        let depth = BATCH_DEPTH.with(|d| {
+            d.set(d.get() - 1);
+            d.get()
+        });
+        if depth == 0 && !std::thread::panicking() {
+            flush_renders();
+            effect::run_effects();
+        }
+    }
+}
+
+/// Runs `f`, holding back the rerenders it causes until it returns and then
+/// committing each component once. Nested batches flush with the outermost
+/// one. If `f` panics the batch is left so later callbacks render normally.
+pub fn batch<R>(f: impl FnOnce() -> R) -> R {
+    BATCH_DEPTH.with(|d| d.set(d.get() + 1));
+    let _guard = BatchGuard;
+    f()
+}
+
+/// Commits every queued rerender now.
+pub fn flush_renders() {
+    let pending = PENDING_RENDERS.with(|p| std::mem::take(&mut *p.borrow_mut()));
+    let current = NODE_ID.with(|n| n.borrow().clone());
+    for (node_id, source, rsx) in pending {
+        NODE_ID.with(|n| *n.borrow_mut() = node_id);
+        RENDER_SOURCE.with(|s| *s.borrow_mut() = source);
+        rerender(rsx);
+    }
+    NODE_ID.with(|n| *n.borrow_mut() = current);
+}
+
+/// A component's node id and the function that rerenders it.
+type Renderer = (String, fn());
+
+thread_local! {
+    static RENDERERS: RefCell<HashMap<String, Renderer>> = RefCell::new(HashMap::new());
+    static STALE: RefCell<Vec<String>> = const { RefCell::new(vec![]) };
+}
+
+/// Records how the component being mounted rerenders, so that
+/// `schedule_rerender` can rerender it from outside its callbacks.
+pub fn set_renderer(render: fn()) {
+    let node_id = NODE_ID.with(|n| n.borrow().clone());
+    if let Some(index) = component_index() {
+        RENDERERS.with(|r| r.borrow_mut().insert(index, (node_id, render)));
+    }
+}
+
+/// Queues the component at `node_id` to rerender in the next animation frame,
+/// for a change made while its values may still be borrowed, e.g. inside one
+/// of its callbacks.
+pub fn request_rerender(node_id: &str) {
+    let first = STALE.with(|s| {
+        let mut stale = s.borrow_mut();
+        if stale.iter().any(|id| id == node_id) {
+            return false;
+        }
+        stale.push(node_id.to_string());
+        stale.len() == 1
+    });
+    if first {
+        let cb = Closure::once_into_js(|| {
+            for node_id in STALE.with(|s| s.take()) {
+                schedule_rerender(&node_id);
+            }
+        });
+        WINDOW.with(|w| w.request_animation_frame(cb.unchecked_ref()).unwrap());
+    }
+}
+
+/// Rerenders the component at `node_id`, e.g. from an observer or event
+/// listener that changed a value its render reads. Does nothing for a
+/// component that registered no renderer or has unmounted since.
+pub fn schedule_rerender(node_id: &str) {
+    let index = CTX.with(|c| c.borrow().get(node_id).map(|Ctx::R(index)| index.clone()));
+    let Some((node_id, render)) = index.and_then(|index| RENDERERS.with(|r| r.borrow().get(&index).cloned())) else {
+        return;
+    };
+    let current = NODE_ID.with(|n| n.replace(node_id));
+    if in_component(|| guarded(render)) {
+        effect::run_effects();
+    }
+    NODE_ID.with(|n| *n.borrow_mut() = current);
+}
+
+/// Runs `f` with batching turned off, so its updates reach the DOM before
+/// `flush_sync` returns. Use it for state that drives a controlled `<input>`,
+/// where a deferred commit would make the cursor jump.
+pub fn flush_sync<R>(f: impl FnOnce() -> R) -> R {
+    flush_renders();
+    let outer = SYNC.with(|s| s.replace(true));
+    let r = f();
+    SYNC.with(|s| s.set(outer));
+    r
+}
+
+#[wasm_bindgen]
+pub fn recall(rid: &str) -> bool {
+    let mut b = false;
+    RECALLS.with(|r| {
+        let recalls = r.borrow();
+        if let Some(rc) = recalls.get(rid) {
+            let r = rc.call;
+            let arr: Vec<String> = rc.ids.split(' ').filter(|s| !s.is_empty()).map(|s| s.to_string()).collect();
+            if let Err(e) = check_scope_ids(&arr) {
+                log_scope_error(&e);
+                return;
+            }
+            IDS.with(|id| {
+                *id.borrow_mut() = arr;
+            });
+            drop(recalls);
+            if in_component(|| guarded(r)) {
+                effect::run_effects();
+            }
+            b = true;
+        }
+    });
+    b
+}
+
+fn check_mount(node_id: &str) -> bool {
+    let mut b = true;
+    CTX.with(|contexts| {
+        let contexts = contexts.borrow();
+        MOUNTED.with(|m| {
+            let mounted = m.borrow();
+            if let Some(vn_index) = contexts.get(node_id) {
+                let index = match vn_index {
+                    Ctx::R(s) => s,
+                };
+                if mounted.contains(index) {
+                    b = false;
+                }
+            }
+        });
+    });
+    b
+}
+
+pub(crate) fn mount(node_id: &str, new: fn(String)) {
+    if check_mount(node_id) {
+        let outer = MOUNT_HOOKS.with(|h| h.replace(Some(vec![])));
+        in_component(|| (new)(node_id.to_string()));
+        let hooks = MOUNT_HOOKS.with(|h| h.replace(outer)).unwrap_or_default();
+        CTX.with(|contexts| {
+            let contexts = contexts.borrow();
+            MOUNTED.with(|m| {
+                let mut mounted = m.borrow_mut();
+                if let Some(vn_index) = contexts.get(node_id) {
+                    let index = match vn_index {
+                        Ctx::R(s) => s,
+                    };
+                    mounted.insert(index.to_string());
+                }
+            });
+        });
+        for hook in hooks {
+            hook();
+        }
+    }
+}
+
+/// Handles panics raised by callbacks or caught by an `error_boundary` from
+/// then on, in place of logging them.
+/// Recovery needs the app built with `panic = "unwind"`, otherwise a panic aborts.
+pub fn set_error_handler(f: impl Fn(&str) + 'static) {
+    ERROR_HANDLER.with(|h| *h.borrow_mut() = Some(Rc::new(f)));
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "callback panicked".to_string()
+    }
+}
+
+/// Runs a callback, catching a panic so the app stays usable. Renders it
+/// queued in a batch are discarded rather than half-applied, and a callback
+/// running inside another only rolls back what it queued itself.
+fn guarded(f: fn()) -> bool {
+    let depth = BATCH_DEPTH.with(|d| d.get());
+    let sync = SYNC.with(|s| s.get());
+    // The queue is usually empty here, so there is only a copy to keep when not.
+    let pending = PENDING_RENDERS.with(|p| {
+        let pending = p.borrow();
+        (!pending.is_empty()).then(|| pending.clone())
+    });
+    match std::panic::catch_unwind(f) {
+        Ok(()) => true,
+        Err(payload) => {
+            BATCH_DEPTH.with(|d| d.set(depth));
+            SYNC.with(|s| s.set(sync));
+            PENDING_RENDERS.with(|p| *p.borrow_mut() = pending.unwrap_or_default());
+            let message = panic_message(&*payload);
+            match ERROR_HANDLER.with(|h| h.borrow().clone()) {
+                Some(handler) => handler(&message),
+                None => web_sys::console::error_1(&format!("callback panicked: {}", message).into()),
+            }
+            false
+        }
+    }
+}
+
+#[wasm_bindgen]
+pub fn call(callback: &str, node_id: &str) -> Result<(), JsValue> {
+    let (name, arr) = callback.split_once('[').ok_or_else(|| JsValue::from_str("malformed callback"))?;
+    let (arr, _) = arr.rsplit_once(']').ok_or_else(|| JsValue::from_str("malformed callback"))?;
+    let arr: Vec<String> = arr.split(' ').filter(|s| !s.is_empty()).map(|s| s.to_string()).collect();
+
+    CALLBACKS.with(|c| {
         let cbc = {
             let mut callbacks = c.borrow_mut();
             if let Some(cb) = callbacks.get_mut(name) {
@@ -1148,69 +2633,244 @@ pub fn call(callback: &str, node_id: &str) -> Result<(), JsValue> {
                     *id.borrow_mut() = arr;
                 });
 
-                if check_mount(node_id) {
-                    (cb.new)(node_id.to_string());
-                    CTX.with(|contexts| {
-                        let contexts = contexts.borrow();
-                        MOUNTED.with(|m| {
-                            let mut mounted = m.borrow_mut();
-                            if let Some(vn_index) = contexts.get(node_id) {
-                                let index = match vn_index {
-                                    Ctx::R(s) => s,
-                                };
-                                mounted.insert(index.to_string());
-                            }
-                        });
-                    });
+                mount(node_id, cb.new);
+                if let Err(e) = IDS.with(|id| check_scope_ids(&id.borrow())) {
+                    log_scope_error(&e);
+                    return Err(JsValue::from_str(&e.to_string()));
                 }
-                cb.call
+                Ok::<fn(), JsValue>(cb.call)
             } else {
                 panic!("expected callback");
             }
+        }?;
+        if in_component(|| guarded(cbc)) {
+            effect::run_effects();
+        }
+        Ok(())
+    })
+}
+
+/// `call` for a delegated event, which callbacks can reach with `custom::current_event`.
+#[wasm_bindgen]
+pub fn call_event(callback: &str, node_id: &str, event: Event) -> Result<(), JsValue> {
+    custom::with_event(event, || call(callback, node_id))
+}
+
+/// `recall` for a delegated event, which callbacks can reach with `custom::current_event`.
+#[wasm_bindgen]
+pub fn recall_event(rid: &str, event: Event) -> bool {
+    custom::with_event(event, || recall(rid))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwapMode {
+    InnerHtml,
+    OuterHtml,
+    BeforeEnd,
+}
+
+/// Swaps a server-rendered fragment into `target`. The components in the
+/// content it replaces are unmounted and the islands among them forget their
+/// state. State scripts in the fragment are picked up by the islands they
+/// describe, which are then hydrated as their `data-hydrate` hints ask. A
+/// fragment's state script should carry `data-island`, since one without
+/// replaces the state shared by the rest of the page.
+pub fn swap_html(target: &Element, html: &str, mode: SwapMode) {
+    RECALLS.with(|r| {
+        let mut recalls = r.borrow_mut();
+        match mode {
+            SwapMode::InnerHtml => {
+                let children = target.child_nodes();
+                for i in 0..children.length() {
+                    clear_recalls(&mut recalls, &children.get(i).unwrap());
+                }
+            }
+            SwapMode::OuterHtml => clear_recalls(&mut recalls, target),
+            SwapMode::BeforeEnd => {}
+        }
+    });
+    flush_unmounted();
+    let islands = hydrate::islands();
+    for island in &islands {
+        let replaced = match mode {
+            SwapMode::InnerHtml => target.contains(Some(island)) && !target.is_same_node(Some(island)),
+            SwapMode::OuterHtml => target.contains(Some(island)),
+            SwapMode::BeforeEnd => false,
         };
-        cbc();
+        if let Some(id) = hydrate::island_id(island).filter(|_| replaced) {
+            forget_island(&id);
+        }
+    }
+    let scripts = state_scripts();
+    match mode {
+        SwapMode::InnerHtml => target.set_inner_html(html),
+        SwapMode::OuterHtml => target.set_outer_html(html),
+        SwapMode::BeforeEnd => target.insert_adjacent_html("beforeend", html).unwrap(),
+    }
+    for script in state_scripts().iter().filter(|s| !scripts.iter().any(|b| b.is_same_node(Some(s)))) {
+        match script.dyn_ref::<Element>().and_then(|s| s.get_attribute("data-island")) {
+            Some(island) => forget_island(&island),
+            None => APP_STATE.with(|a| *a.borrow_mut() = None),
+        }
+    }
+    for island in hydrate::islands() {
+        if !islands.iter().any(|i| i.is_same_node(Some(&island))) {
+            hydrate::schedule(island);
+        }
+    }
+}
+
+fn state_scripts() -> Vec<Node> {
+    let mut v = vec![];
+    DOCUMENT.with(|document| {
+        let selector = STATE_SELECTOR.with(|s| s.borrow().clone());
+        let scripts = document.query_selector_all(&selector).unwrap();
+        for i in 0..scripts.length() {
+            v.push(scripts.get(i).unwrap());
+        }
     });
+    v
+}
 
-    Ok(())
+/// Forgets the recalls under `node` and queues the components marked inside
+/// it for unmounting once the current render is done.
+pub(crate) fn clear_recalls(recalls: &mut HashMap<String, RecallData>, node: &Node) {
+    match node.node_type() {
+        Node::ELEMENT_NODE => {
+            let el = node.dyn_ref::<Element>().unwrap();
+            if let Some(rid) = el.attributes().get_named_item("rid") {
+                recalls.remove(&rid.value());
+            }
+            let children = node.child_nodes();
+            for i in 0..children.length() {
+                clear_recalls(recalls, &children.get(i).unwrap());
+            }
+        }
+        Node::COMMENT_NODE => {
+            if let Some(index) = node.text_content().as_deref().and_then(marker_index) {
+                queue_unmount(index);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Drops `RECALLS` entries whose element is no longer in the document, e.g.
+/// after third-party code removed it. One `[rid]` query per call, so it is
+/// cheap enough to run periodically. Returns how many entries were removed.
+pub fn gc_recalls() -> usize {
+    let mut live = HashSet::new();
+    DOCUMENT.with(|document| {
+        let nodes = document.query_selector_all("[rid]").unwrap();
+        for i in 0..nodes.length() {
+            if let Some(rid) = nodes.get(i).unwrap().dyn_ref::<Element>().and_then(|el| el.get_attribute("rid")) {
+                live.insert(rid);
+            }
+        }
+    });
+    switch::parked_rids(&mut live);
+    RECALLS.with(|r| retain_live(&mut r.borrow_mut(), &live))
+}
+
+fn retain_live(recalls: &mut HashMap<String, RecallData>, live: &HashSet<String>) -> usize {
+    let before = recalls.len();
+    recalls.retain(|rid, _| live.contains(rid));
+    before - recalls.len()
 }
 
 #[derive(Debug)]
 pub struct ScopeVar {
     pub rf: Rc<RefCell<dyn Any>>,
     pub index: Option<usize>,
+    obj: usize,
+    component: String,
 }
 
 impl ScopeVar {
-    fn new(rf: Rc<RefCell<dyn Any>>, index: Option<usize>) -> Self {
-        Self {rf, index}
+    fn new(rf: Rc<RefCell<dyn Any>>, index: Option<usize>, obj: usize, component: String) -> Self {
+        Self {rf, index, obj, component}
+    }
+    /// Borrows the variable, panicking with the component and object index
+    /// when it is already borrowed, e.g. by a reentrant render.
+    #[track_caller]
+    pub fn borrow_mut(&self) -> borrow::ScopeMut<'_> {
+        borrow::borrow_mut(self)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScopeRef {
+    Var(usize),
+    Item(usize, usize),
+}
+
+impl ScopeRef {
+    fn obj(&self) -> usize {
+        match self {
+            Self::Var(n) | Self::Item(n, _) => *n,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScopeError(String);
+
+impl fmt::Display for ScopeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "malformed scope id: {:?}", self.0)
+    }
+}
+
+impl Error for ScopeError {}
+
+/// Parses a lexical scope id, either `n` for a variable or `n-i` for an item of one.
+pub fn parse_scope_id(s: &str) -> Result<ScopeRef, ScopeError> {
+    let err = || ScopeError(s.to_string());
+    if let Some((f, i)) = s.split_once('-') {
+        Ok(ScopeRef::Item(f.parse().map_err(|_| err())?, i.parse().map_err(|_| err())?))
+    } else {
+        Ok(ScopeRef::Var(s.parse().map_err(|_| err())?))
+    }
+}
+
+fn check_scope_ids(ids: &[String]) -> Result<(), ScopeError> {
+    let len = with_state(|state| state.objs.len());
+    for id in ids {
+        let scope = parse_scope_id(id)?;
+        if len.is_some_and(|len| scope.obj() >= len) {
+            return Err(ScopeError(id.to_string()));
+        }
+    }
+    Ok(())
+}
+
+fn log_scope_error(e: &ScopeError) {
+    thread_local! {
+        static LOGGED: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+    }
+    if !LOGGED.with(|l| l.replace(true)) {
+        web_sys::console::error_1(&e.to_string().into());
     }
 }
 
 pub fn lexical_scope() -> Vec<ScopeVar> {
     let mut v = vec![];
-    APP_STATE.with(|app| {
-        let app = app.borrow();
+    let component = component_index().unwrap_or_default();
+    with_state(|app| {
         IDS.with(|ids| {
             for id in ids.borrow().iter() {
-                if let Some((f, s)) = id.split_once('-') {
-                    let f: usize = f.parse().expect("problem parsing id for lexical scope");
-                    let s: usize = s.parse().expect("problem parsing index for lexical scope");
-                    if let Obj::Rs(var) = &app.as_ref().expect("could not get app state").objs[f] {
-                        v.push(ScopeVar::new(var.clone(), Some(s)));
-                    } else {
-                        panic!("expected Rust type to be restored");
-                    }
+                let (n, index) = match parse_scope_id(id).expect("scope ids are checked before calling") {
+                    ScopeRef::Var(n) => (n, None),
+                    ScopeRef::Item(n, i) => (n, Some(i)),
+                };
+                if let Obj::Rs(var) = &app.objs[n] {
+                    v.push(ScopeVar::new(var.clone(), index, n, component.clone()));
                 } else {
-                    let id: usize = id.parse().expect("problem parsing id for lexical scope");
-                    if let Obj::Rs(var) = &app.as_ref().expect("could not get app state").objs[id] {
-                        v.push(ScopeVar::new(var.clone(), None));
-                    } else {
-                        panic!("expected Rust type to be restored");
-                    }
+                    panic!("expected Rust type to be restored");
                 }
             }
         })
-    });
+    }).expect("could not get app state");
     v
 }
 
@@ -1225,6 +2885,7 @@ fn add_children(children: &mut Vec<Rsx>, node: &Node) {
 fn update(rsx: &mut Rsx, node: &mut Node) {
     match rsx {
         Rsx::Element(element) => {
+            element.check_void();
             element.diff(node);
             if let Some(mut first_child) = node.first_child() {
                 check_siblings(&mut element.children, &mut first_child);
@@ -1233,7 +2894,17 @@ fn update(rsx: &mut Rsx, node: &mut Node) {
             }
         }
         Rsx::Text(text) => {
-            set_content(node, text);
+            if text_unchanged(node.node_type(), node.text_content().as_deref(), &text.text) {
+                text.node = node.dyn_ref::<Text>().cloned();
+            } else {
+                set_content(node, text);
+            }
+        }
+        Rsx::Fragment(fragment) => {
+            fragment.update(node);
+        }
+        Rsx::Html(html) => {
+            html.update(node);
         }
         Rsx::Component(comp) => {
             check_siblings(&mut comp.children, node);
@@ -1248,6 +2919,7 @@ fn update(rsx: &mut Rsx, node: &mut Node) {
 fn vupdate(rsx: &mut Rsx, node: &Rsx, last: bool) {
     match rsx {
         Rsx::Element(element) => {
+            element.check_void();
             if !last {
                 element.vdiff(node);
             } else {
@@ -1266,6 +2938,17 @@ fn vupdate(rsx: &mut Rsx, node: &Rsx, last: bool) {
             }
             vset_content(node, text);
         }
+        Rsx::Fragment(fragment) => {
+            if let Rsx::Fragment(f) = node {
+                fragment.vupdate(f);
+            } else {
+                let new = DOCUMENT.with(|document| fragment.build_node(document));
+                Html::replace(&new, &rendered_nodes(node));
+            }
+        }
+        Rsx::Html(html) => {
+            html.vupdate(node);
+        }
         Rsx::Component(comp) => {
             vcheck_children(&mut comp.children, node);
         }
@@ -1283,7 +2966,102 @@ fn avcheck(node: &Node) -> bool {
     node.node_type() == Node::COMMENT_NODE && node.text_content().unwrap() == "/av"
 }
 
+#[derive(Debug, PartialEq)]
+enum KeyedOp {
+    Keep(usize),
+    Move(usize),
+    Insert,
+}
+
+/// Matches `new` keys against the keys of the existing nodes. Returns the old
+/// indices to remove and, for each new key in order, whether the node at the
+/// cursor is kept, an old node is moved in front of it or a new one inserted.
+fn keyed_plan(old: &[Option<String>], new: &[String]) -> (Vec<usize>, Vec<KeyedOp>) {
+    let mut index = HashMap::new();
+    for (n, key) in old.iter().enumerate() {
+        if let Some(key) = key {
+            index.entry(key.as_str()).or_insert(n);
+        }
+    }
+    let reuse: Vec<Option<usize>> = new.iter().map(|k| index.remove(k.as_str())).collect();
+    let mut removed: Vec<usize> = (0..old.len()).filter(|n| !reuse.contains(&Some(*n))).collect();
+    removed.sort_unstable();
+    let mut current: Vec<Option<usize>> = (0..old.len()).filter(|n| !removed.contains(n)).map(Some).collect();
+    let mut ops = Vec::with_capacity(new.len());
+    for (cursor, r) in reuse.into_iter().enumerate() {
+        match r {
+            Some(o) if current.get(cursor) == Some(&Some(o)) => ops.push(KeyedOp::Keep(o)),
+            Some(o) => {
+                let from = current.iter().position(|c| *c == Some(o)).unwrap();
+                current.remove(from);
+                current.insert(cursor, Some(o));
+                ops.push(KeyedOp::Move(o));
+            }
+            None => {
+                current.insert(cursor, None);
+                ops.push(KeyedOp::Insert);
+            }
+        }
+    }
+    (removed, ops)
+}
+
+/// Builds the node of a keyed child being inserted, from the template cache
+/// and with the enter class.
+fn keyed_node(elem: &mut Elem, document: &Document) -> Node {
+    enter(template::instantiate(elem, document))
+}
+
+fn check_keyed(children: &mut [Rsx], keys: &[String], node: &mut Node) {
+    let parent = node.parent_node().unwrap();
+    let mut nodes = vec![node.clone()];
+    while let Some(sib) = nodes.last().unwrap().next_sibling() {
+        if avcheck(&sib) {
+            break;
+        }
+        nodes.push(sib);
+    }
+    let end = nodes.last().unwrap().next_sibling();
+    let old: Vec<Option<String>> = nodes.iter().map(|n| n.dyn_ref::<Element>().and_then(|e| e.get_attribute("key"))).collect();
+    let (removed, ops) = keyed_plan(&old, keys);
+    RECALLS.with(|r| {
+        let mut recall = r.borrow_mut();
+        for n in &removed {
+            remove_recall(&mut recall, &parent, &nodes[*n]);
+        }
+    });
+    let mut next = (0..nodes.len()).find(|n| !removed.contains(n)).map(|n| nodes[n].clone()).or(end);
+    DOCUMENT.with(|document| {
+        for (child, op) in children.iter_mut().zip(ops) {
+            let mut target = match op {
+                KeyedOp::Keep(o) => {
+                    next = nodes[o].next_sibling();
+                    nodes[o].clone()
+                }
+                KeyedOp::Move(o) => {
+                    parent.insert_before(&nodes[o], next.as_ref()).unwrap();
+                    nodes[o].clone()
+                }
+                KeyedOp::Insert => {
+                    let Rsx::Element(elem) = child else { unreachable!("keyed children are elements") };
+                    let new = keyed_node(elem, document);
+                    parent.insert_before(&new, next.as_ref()).unwrap();
+                    *node = new;
+                    continue;
+                }
+            };
+            update(child, &mut target);
+            *node = target;
+        }
+    });
+}
+
 fn check_siblings(children: &mut Vec<Rsx>, node: &mut Node) {
+    let keys: Option<Vec<String>> = children.iter().map(|c| c.key().map(str::to_string)).collect();
+    if let Some(keys) = keys.filter(|k| !k.is_empty()) {
+        check_keyed(children, &keys, node);
+        return;
+    }
     let mut children = children.iter_mut();
     let l = children.len();
     let mut n = 0;
@@ -1296,7 +3074,7 @@ fn check_siblings(children: &mut Vec<Rsx>, node: &mut Node) {
                 if avcheck(&sib) {
                     while let Some(c) = children.next() {
                         c.edit(&node);
-                        *node = node.next_sibling().unwrap();
+                        *node = run_end(&node.next_sibling().unwrap());
                     }
                     return;
                 }
@@ -1309,7 +3087,7 @@ fn check_siblings(children: &mut Vec<Rsx>, node: &mut Node) {
                     child.edit(&node);
                     while let Some(c) = children.next() {
                         if let Some(sib) = node.next_sibling() {
-                            *node = sib;
+                            *node = run_end(&sib);
                             c.edit(&node);
                         } else {
                             c.edit(&node);
@@ -1327,12 +3105,11 @@ fn check_siblings(children: &mut Vec<Rsx>, node: &mut Node) {
                 let parent = node.parent_node().unwrap();
                 RECALLS.with(|r| {
                     let mut recall = r.borrow_mut();
-                    remove_recall(&mut recall, &parent, &s);
-                    while let Some(sib) = node.next_sibling() {
-                        if !avcheck(&sib) {
-                            remove_recall(&mut recall, &parent, &sib);
-                        } else {
-                            return;
+                    let mut next = Some(s);
+                    while let Some(sib) = next.filter(|sib| !avcheck(sib)) {
+                        next = run_end(&sib).next_sibling();
+                        for n in run_nodes(&sib) {
+                            remove_recall(&mut recall, &parent, &n);
                         }
                     }
                 });
@@ -1348,7 +3125,7 @@ fn vcheck_children(children: &mut Vec<Rsx>, node: &Rsx) {
         vcheck_both(children, node);
     } else {
         if !children.is_empty() {
-            add_children(children, &node.node());
+            add_children(children, &node.node().expect("expected node"));
         }
         return;
     }
@@ -1359,7 +3136,9 @@ fn vcheck_both(children: &mut Vec<Rsx>, node: &Vec<Rsx>) {
     let mut node_children = node.iter();
     let mut children = children.iter_mut();
     let mut n = 0;
-    let mut node = node_children.next().unwrap();
+    let Some(mut node) = node_children.next() else {
+        return;
+    };
 
     loop {
         if let Some(mut child) = children.next() {
@@ -1367,12 +3146,11 @@ fn vcheck_both(children: &mut Vec<Rsx>, node: &Vec<Rsx>) {
             vupdate(&mut child, &node, last);
 
             if n + 1 == m {
-                if children.len() > 0 {
-                    let mut sib = node.node();
+                if let Some(mut sib) = node.node().filter(|_| children.len() > 0) {
                     while let Some(c) = children.next() {
                         let next_sibling = {
                             c.edit(&sib);
-                            sib.next_sibling().unwrap()
+                            run_end(&sib.next_sibling().unwrap())
                         };
                         sib = next_sibling;
                     }
@@ -1380,12 +3158,13 @@ fn vcheck_both(children: &mut Vec<Rsx>, node: &Vec<Rsx>) {
                 return;
             } 
         } else {
-            let parent = node.parent_node().unwrap();
             RECALLS.with(|r| {
                 let mut recall = r.borrow_mut();
-                remove_recall(&mut recall, &parent, &node.node());
-                while let Some(sib) = node_children.next() {
-                    remove_recall(&mut recall, &parent, &sib.node());
+                for rendered in std::iter::once(node).chain(node_children) {
+                    for n in rendered_nodes(rendered) {
+                        let parent = n.parent_node().unwrap();
+                        remove_recall(&mut recall, &parent, &n);
+                    }
                 }
             });
             return;
@@ -1399,28 +3178,31 @@ fn vcheck_both(children: &mut Vec<Rsx>, node: &Vec<Rsx>) {
     }
 }
 
-fn remove_recall(recalls: &mut HashMap<String, RecallData>, parent: &Node, child: &Node) {
-    if child.node_type() == Node::ELEMENT_NODE {
-        let el = child.dyn_ref::<Element>().unwrap();
-        let attrs = el.attributes();
-        if let Some(rid) = attrs.get_named_item("rid") {
-            recalls.remove(&rid.value());
-        }
+/// The live nodes `rsx` was rendered to, of which raw HTML can have several.
+fn rendered_nodes(rsx: &Rsx) -> Vec<Node> {
+    match rsx {
+        Rsx::Html(html) => run_nodes(html.start.as_ref().expect("expected html marker")),
+        Rsx::Fragment(fragment) => run_nodes(fragment.start.as_ref().expect("expected fragment marker")),
+        _ => rsx.node().into_iter().collect(),
     }
+}
+
+fn remove_recall(recalls: &mut HashMap<String, RecallData>, parent: &Node, child: &Node) {
+    clear_recalls(recalls, child);
     parent.remove_child(child).unwrap();
 }
 
 fn replace_recall(recalls: &mut HashMap<String, RecallData>, parent: &Node, child: &Node, new: &Node) {
-    if child.node_type() == Node::ELEMENT_NODE {
-        let el = child.dyn_ref::<Element>().unwrap();
-        let attrs = el.attributes();
-        if let Some(rid) = attrs.get_named_item("rid") {
-            recalls.remove(&rid.value());
-        }
-    }
+    clear_recalls(recalls, child);
     parent.replace_child(new, child).unwrap();
 }
 
+/// Whether a node already holds `text`, in which case `update` keeps it
+/// rather than swapping in an identical `Text` through `replace_recall`.
+fn text_unchanged(node_type: u16, current: Option<&str>, text: &str) -> bool {
+    node_type == Node::TEXT_NODE && current == Some(text)
+}
+
 fn set_content(node: &mut Node, content: &mut Txt) {
     let text = Text::new_with_data(&content.text).unwrap();
     let parent = node.parent_node().unwrap();
@@ -1434,13 +3216,16 @@ fn set_content(node: &mut Node, content: &mut Txt) {
 }
 
 fn vset_content(node: &Rsx, content: &mut Txt) {
+    let Some(old) = node.node() else {
+        return;
+    };
     let text = Text::new_with_data(&content.text).unwrap();
-    let parent = node.parent_node().unwrap();
+    let parent = old.parent_node().unwrap();
     RECALLS.with(|r| {
         let mut recall = r.borrow_mut();
         content.node = Some(text.clone());
         let text_node = text.dyn_into::<Node>().unwrap();
-        replace_recall(&mut recall, &parent, &node.node(), &text_node);
+        replace_recall(&mut recall, &parent, &old, &text_node);
     });
 }
 
@@ -1452,3 +3237,942 @@ fn close_vnode(document: &Document, node: &Node) {
         }
     }
 }
+
+/// Helpers shared by the tests of several modules.
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use std::cell::Cell;
+
+    use crate::{CTX, Ctx, NODE_ID, set_renderer};
+
+    thread_local! {
+        static RENDERS: Cell<u32> = const { Cell::new(0) };
+    }
+
+    fn count_render() {
+        RENDERS.with(|r| r.set(r.get() + 1));
+    }
+
+    /// Makes `node_id` the current component, with a renderer that only
+    /// counts how often it runs.
+    pub(crate) fn counted_component(node_id: &str) {
+        CTX.with(|c| c.borrow_mut().insert(node_id.to_string(), Ctx::R(node_id.to_string())));
+        NODE_ID.with(|n| *n.borrow_mut() = node_id.to_string());
+        set_renderer(count_render);
+        RENDERS.with(|r| r.set(0));
+    }
+
+    /// How often the component of the last `counted_component` rendered.
+    pub(crate) fn renders() -> u32 {
+        RENDERS.with(|r| r.get())
+    }
+
+    /// Resolves after `ms` milliseconds.
+    #[cfg(target_arch = "wasm32")]
+    pub(crate) async fn sleep(ms: i32) {
+        let timeout = js_sys::Promise::new(&mut |resolve, _| {
+            crate::WINDOW.with(|w| w.set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, ms).unwrap());
+        });
+        wasm_bindgen_futures::JsFuture::from(timeout).await.unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use super::*;
+
+    #[derive(Debug)]
+    struct Row {
+        pos: usize,
+        item: char,
+    }
+
+    impl RefChild for Row {
+        type Item = char;
+        fn new(pos: usize, item: char) -> Self {
+            Self {pos, item}
+        }
+        fn pos(&self) -> usize {
+            self.pos
+        }
+        fn pos_mut(&mut self) -> &mut usize {
+            &mut self.pos
+        }
+    }
+
+    #[test]
+    fn remove_indices() {
+        let mut rows: RefVec<Row> = RefVec::new();
+        rows.append(&mut "abcdefg".chars().collect());
+        let removed = rows.remove_indices(&[5, 1, 3, 1]);
+        assert_eq!(removed.iter().map(|r| r.borrow().item).collect::<String>(), "bdf");
+        assert_eq!(rows.iter().map(|r| r.item).collect::<String>(), "aceg");
+        assert_eq!(rows.iter().map(|r| r.pos()).collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+        rows.move_item(3, 0);
+        assert_eq!(rows.iter().map(|r| (r.item, r.pos())).collect::<Vec<_>>(), vec![('g', 0), ('a', 1), ('c', 2), ('e', 3)]);
+    }
+
+    #[test]
+    fn insert_middle() {
+        let mut rows: RefVec<Row> = RefVec::new();
+        rows.append(&mut "abd".chars().collect());
+        rows.insert(2, 'c');
+        rows.insert(0, '_');
+        rows.insert(5, 'e');
+        assert_eq!(rows.iter().map(|r| r.item).collect::<String>(), "_abcde");
+        assert_eq!(rows.iter().map(|r| r.pos()).collect::<Vec<_>>(), (0..6).collect::<Vec<_>>());
+    }
+
+    #[test]
+    #[should_panic(expected = "insertion index (is 3) should be <= len (is 1)")]
+    fn insert_out_of_bounds() {
+        let mut rows: RefVec<Row> = RefVec::new();
+        rows.push('a');
+        rows.insert(3, 'b');
+    }
+
+    #[test]
+    fn retain_renumbers() {
+        let mut rows: RefVec<Row> = RefVec::new();
+        rows.append(&mut "abcdefg".chars().collect());
+        rows.retain(|r| r.pos() % 2 == 0);
+        assert_eq!(rows.iter().map(|r| r.item).collect::<String>(), "aceg");
+        assert_eq!(rows.iter().map(|r| r.pos()).collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+        rows.retain(|_| true);
+        assert_eq!(rows.len(), 4);
+    }
+
+    #[test]
+    fn sort_keeps_cells() {
+        let mut rows: RefVec<Row> = RefVec::new();
+        rows.append(&mut "dbca".chars().collect());
+        let b = rows.inner()[1].clone();
+        let keys = |rows: &RefVec<Row>| rows.iter().map(|r| r.item.to_string()).collect::<Vec<_>>();
+        let before = keys(&rows);
+        rows.sort_by_key(|r| r.item);
+        assert_eq!(rows.iter().map(|r| (r.item, r.pos())).collect::<Vec<_>>(), vec![('a', 0), ('b', 1), ('c', 2), ('d', 3)]);
+        assert!(Rc::ptr_eq(&b, &rows.inner()[1]));
+        let (removed, ops) = keyed_plan(&before.into_iter().map(Some).collect::<Vec<_>>(), &keys(&rows));
+        assert!(removed.is_empty());
+        assert_eq!(ops, vec![KeyedOp::Move(3), KeyedOp::Move(1), KeyedOp::Move(2), KeyedOp::Keep(0)]);
+        b.borrow_mut().item = 'B';
+        assert_eq!(rows.iter().map(|r| r.item).collect::<String>(), "aBcd");
+        rows.sort_by(|x, y| y.pos().cmp(&x.pos()));
+        assert_eq!(rows.iter().map(|r| (r.item, r.pos())).collect::<Vec<_>>(), vec![('d', 0), ('c', 1), ('B', 2), ('a', 3)]);
+    }
+
+    #[test]
+    fn indexed_access() {
+        let mut rows: RefVec<Row> = RefVec::new();
+        assert!(rows.is_empty() && rows.first().is_none() && rows.last().is_none());
+        rows.append(&mut "abc".chars().collect());
+        assert_eq!(rows.get(1).map(|r| r.item), Some('b'));
+        assert!(rows.get(3).is_none());
+        rows.get_mut(2).unwrap().item = 'z';
+        assert_eq!((rows.first().unwrap().item, rows.last().unwrap().item), ('a', 'z'));
+        assert!(!rows.is_empty());
+    }
+
+    #[test]
+    fn merge_dedupes() {
+        static A: &[Static] = &[("/static/styles/a.css", b"a"), ("/static/styles/shared.css", b"1")];
+        static B: &[Static] = &[("/static/styles/shared.css", b"2"), ("/static/styles/b.css", b"b")];
+        let merged = merge_statics(&[A, B]);
+        assert_eq!(merged.iter().map(|s| s.0).collect::<Vec<_>>(), vec!["/static/styles/a.css", "/static/styles/shared.css", "/static/styles/b.css"]);
+        assert_eq!(merged[1].1, b"1");
+    }
+
+    #[test]
+    fn obj_kinds() {
+        let rs: Rc<RefCell<dyn Any>> = Rc::new(RefCell::new(1u8));
+        let objs = vec![Obj::Rs(rs), Obj::Js(Value::Null), Obj::Js(serde_json::json!(2)), Obj::Js(serde_json::json!("s")), Obj::Js(serde_json::json!([])), Obj::Js(serde_json::json!({}))];
+        let state = AppState {objs, subs: vec![]};
+        assert_eq!(state.obj_kind(0), ObjKind::Rs);
+        assert_eq!(state.obj_kind(1), ObjKind::Js(JsonKind::Null));
+        assert_eq!(state.obj_kind(2), ObjKind::Js(JsonKind::Number));
+        assert_eq!(state.obj_kind(3), ObjKind::Js(JsonKind::String));
+        assert_eq!(state.obj_kind(4), ObjKind::Js(JsonKind::Array));
+        assert_eq!(state.obj_kind(5), ObjKind::Js(JsonKind::Object));
+        assert_eq!(state.obj_kind(6), ObjKind::Missing);
+        assert!(state.obj(6).is_none());
+    }
+
+    #[test]
+    fn double_borrow() {
+        let rf: Rc<RefCell<dyn Any>> = Rc::new(RefCell::new(0u32));
+        let var = ScopeVar::new(rf.clone(), None, 3, "counter".to_string());
+        let held = var.borrow_mut();
+        let again = ScopeVar::new(rf, Some(1), 3, "counter".to_string());
+        let msg = *std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            again.borrow_mut();
+        })).unwrap_err().downcast::<String>().unwrap();
+        assert!(msg.starts_with("object 3 item 1 of component counter is already borrowed, held since"));
+        assert!(msg.contains("lib.rs"));
+        drop(held);
+        assert_eq!(var.borrow_mut().downcast_mut::<u32>(), Some(&mut 0));
+    }
+
+    #[test]
+    fn stale_recalls() {
+        fn noop() {}
+        let mut recalls: HashMap<String, RecallData> = ["0", "1", "2"].iter().map(|r| (r.to_string(), RecallData {call: noop, ids: String::new()})).collect();
+        let live: HashSet<String> = ["0", "2"].iter().map(|r| r.to_string()).collect();
+        assert_eq!(retain_live(&mut recalls, &live), 1);
+        assert!(!recalls.contains_key("1"));
+        assert_eq!(recalls.len(), 2);
+    }
+
+    #[test]
+    fn unbalanced_vnodes() {
+        assert_eq!(vnode_balance(["av a:id=0", "av a:id=1", "/av", "/av"]), Ok(()));
+        assert_eq!(vnode_balance(["av a:id=0", "av a:id=3", "/av"]), Err("unbalanced vnode markers: av region a:id=0 is never closed".to_string()));
+        assert!(vnode_balance(["/av"]).unwrap_err().contains("no open av region"));
+    }
+
+    #[test]
+    fn scope_ids() {
+        assert_eq!(parse_scope_id("3"), Ok(ScopeRef::Var(3)));
+        assert_eq!(parse_scope_id("2-7"), Ok(ScopeRef::Item(2, 7)));
+        for bad in ["", "x", "1-", "-1", "1-2-3", "4 "] {
+            assert_eq!(parse_scope_id(bad), Err(ScopeError(bad.to_string())));
+        }
+    }
+
+    #[test]
+    fn lazy_signal() {
+        let ran = Rc::new(Cell::new(0));
+        let r = ran.clone();
+        let mut signal = Signal::lazy(move || {
+            r.set(r.get() + 1);
+            5
+        });
+        assert_eq!(ran.get(), 0);
+        assert_eq!(*signal.value(), 5);
+        *signal.value_mut() += 1;
+        assert_eq!(*signal.value(), 6);
+        assert_eq!(ran.get(), 1);
+    }
+
+    #[test]
+    fn linked_signal() {
+        let first = Rc::new(RefCell::new(Signal::new("Ada".to_string())));
+        let last = Rc::new(RefCell::new(Signal::new("Lovelace".to_string())));
+        let (f, l) = (first.clone(), last.clone());
+        let (f2, l2) = (first.clone(), last.clone());
+        let full = LinkedSignal::new(
+            move || format!("{} {}", f.borrow_mut().value(), l.borrow_mut().value()),
+            move |name: String| {
+                let (a, b) = name.split_once(' ').unwrap();
+                *f2.borrow_mut().value_mut() = a.to_string();
+                *l2.borrow_mut().value_mut() = b.to_string();
+            },
+        );
+        assert_eq!(full.value(), "Ada Lovelace");
+        full.set("Grace Hopper".to_string());
+        assert_eq!(first.borrow_mut().value(), "Grace");
+        assert_eq!(last.borrow_mut().value(), "Hopper");
+    }
+
+    #[test]
+    fn memo_tracks_reads() {
+        let price = Rc::new(RefCell::new(Signal::new(10)));
+        let qty = Rc::new(RefCell::new(Signal::new(2)));
+        let runs = Rc::new(Cell::new(0));
+        let (p, q, r) = (price.clone(), qty.clone(), runs.clone());
+        let total = Rc::new(RefCell::new(Memo::new(move || {
+            r.set(r.get() + 1);
+            *p.borrow_mut().value() * *q.borrow_mut().value()
+        })));
+        let t = total.clone();
+        let mut even = Memo::new(move || *t.borrow_mut().value() % 2 == 0);
+        assert!(*even.value());
+        assert_eq!(*total.borrow_mut().value(), 20);
+        assert_eq!(runs.get(), 1);
+        *qty.borrow_mut().value_mut() = 4;
+        assert!(*even.value());
+        assert_eq!(*total.borrow_mut().value(), 40);
+        assert_eq!(runs.get(), 2);
+        let version = total.borrow()._proxy._version.get();
+        *price.borrow_mut().value_mut() = 10;
+        assert_eq!(*total.borrow_mut().value(), 40);
+        assert_eq!(runs.get(), 3);
+        assert_eq!(total.borrow()._proxy._version.get(), version);
+    }
+
+    #[test]
+    fn tracked_once() {
+        let mut proxy = Proxy::new(vec![]);
+        proxy.start_proxy();
+        assert_eq!((proxy.set(1), proxy.set(1), proxy.set(1)), (true, false, false));
+        assert!(proxy.set(2));
+        assert_eq!(proxy._subs, vec![(0, 1), (0, 2)]);
+        let mut signal = Signal::new(0);
+        signal._proxy.start_proxy();
+        assert!(signal._proxy.set());
+        assert!(!signal._proxy.set());
+        signal._proxy.start_proxy();
+        assert!(signal._proxy.set());
+    }
+
+    #[test]
+    fn batch_depth() {
+        let depth = || BATCH_DEPTH.with(|d| d.get());
+        batch(|| {
+            batch(|| {
+                assert_eq!(depth(), 2);
+                assert!(queue_render(Rsx::component()));
+                assert!(!queue_render(Rsx::component()));
+            });
+            assert_eq!(depth(), 1);
+            assert_eq!(PENDING_RENDERS.with(|p| p.borrow().len()), 1);
+            PENDING_RENDERS.with(|p| p.borrow_mut().clear());
+        });
+        let r = std::panic::catch_unwind(|| batch(|| panic!("callback failed")));
+        assert!(r.is_err());
+        assert_eq!(depth(), 0);
+    }
+
+    #[test]
+    fn equal_set() {
+        let mut signal = Signal::new(vec![1, 2]);
+        signal.set(vec![1, 2]);
+        assert!(!signal._proxy._invalid);
+        signal.update(|v| {
+            let stale = v.contains(&3);
+            v.retain(|n| *n != 3);
+            stale
+        });
+        assert!(!signal._proxy._invalid);
+        signal.set(vec![2]);
+        assert!(signal._proxy._invalid);
+        signal._proxy._invalid = false;
+        signal.update(|v| {
+            v.push(3);
+            true
+        });
+        assert!(signal._proxy._invalid);
+        assert_eq!(*signal.value(), [2, 3]);
+    }
+
+    #[test]
+    fn peek_untracked() {
+        let rate = Rc::new(RefCell::new(Signal::new(2)));
+        let amount = Rc::new(RefCell::new(Signal::new(5)));
+        let runs = Rc::new(Cell::new(0));
+        let (r, a, n) = (rate.clone(), amount.clone(), runs.clone());
+        let mut total = Memo::new(move || {
+            n.set(n.get() + 1);
+            *a.borrow_mut().value() * *r.borrow_mut().peek()
+        });
+        assert_eq!(*total.value(), 10);
+        *rate.borrow_mut().value_mut() = 3;
+        assert_eq!(*total.value(), 10);
+        assert_eq!(runs.get(), 1);
+        *amount.borrow_mut().value_mut() = 6;
+        assert_eq!(*total.value(), 18);
+        assert_eq!(*rate.borrow().peek_untracked(), 3);
+    }
+
+    #[test]
+    fn setup_merges() {
+        fn noop() {}
+        fn mount(_: String) {}
+        let cb = |names: &[&str]| names.iter().map(|n| (n.to_string(), CallbackData {new: mount, call: noop})).collect();
+        setup(cb(&["a_click", "a_input"]));
+        setup(cb(&["b_click"]));
+        CALLBACKS.with(|c| {
+            let c = c.borrow();
+            assert!(c.contains_key("a_click") && c.contains_key("a_input") && c.contains_key("b_click"));
+        });
+    }
+
+    fn keys(s: &str) -> Vec<String> {
+        s.chars().map(|c| c.to_string()).collect()
+    }
+
+    fn old_keys(s: &str) -> Vec<Option<String>> {
+        s.chars().map(|c| Some(c.to_string())).collect()
+    }
+
+    #[test]
+    fn keyed_reversal() {
+        let (removed, ops) = keyed_plan(&old_keys("abc"), &keys("cba"));
+        assert!(removed.is_empty());
+        assert_eq!(ops, vec![KeyedOp::Move(2), KeyedOp::Move(1), KeyedOp::Keep(0)]);
+    }
+
+    #[test]
+    fn keyed_move_to_front() {
+        let (removed, ops) = keyed_plan(&old_keys("abcd"), &keys("dabc"));
+        assert!(removed.is_empty());
+        assert_eq!(ops, vec![KeyedOp::Move(3), KeyedOp::Keep(0), KeyedOp::Keep(1), KeyedOp::Keep(2)]);
+    }
+
+    #[test]
+    fn keyed_delete_middle() {
+        let (removed, ops) = keyed_plan(&old_keys("abc"), &keys("ac"));
+        assert_eq!(removed, vec![1]);
+        assert_eq!(ops, vec![KeyedOp::Keep(0), KeyedOp::Keep(2)]);
+        let (removed, ops) = keyed_plan(&[Some("a".to_string()), None], &keys("ba"));
+        assert_eq!(removed, vec![1]);
+        assert_eq!(ops, vec![KeyedOp::Insert, KeyedOp::Keep(0)]);
+    }
+
+    #[test]
+    fn stale_attributes() {
+        let live = |keys: &[&str]| keys.iter().map(|k| k.to_string()).collect::<Vec<_>>();
+        let expanded = attributes![("class".to_string(), "menu".to_string()), ("aria-expanded".to_string(), "true".to_string())];
+        let collapsed = attributes![("class".to_string(), "menu".to_string())];
+        let shown = live(&["class", "aria-expanded", "rid"]);
+        assert_eq!(stale_attrs(&shown, &collapsed), vec!["aria-expanded"]);
+        assert!(stale_attrs(&shown, &expanded).is_empty());
+        let swapped = attributes![("class".to_string(), "menu".to_string()), ("hidden".to_string(), String::new())];
+        assert_eq!(stale_attrs(&live(&["class", "aria-expanded"]), &swapped), vec!["aria-expanded"]);
+    }
+
+    #[test]
+    fn class_and_style_tokens() {
+        assert_eq!(token_diff("btn primary large", "btn secondary large"), (vec!["primary"], vec!["secondary"]));
+        assert_eq!(token_diff("a  b", "b a"), (vec![], vec![]));
+        let (removed, set) = style_diff("color: red; margin: 0", "margin: 0;color:blue; top: 1px !important;");
+        assert!(removed.is_empty());
+        assert_eq!(set, vec![("color", "blue"), ("top", "1px !important")]);
+        assert_eq!(style_diff("color: red; margin: 0", "margin: 0").0, vec!["color"]);
+    }
+
+    #[test]
+    fn toggle_class() {
+        let class = |active: bool| classes![("btn", true), ("active", active), ("btn primary", true), ("hidden", false)];
+        let (off, on) = (class(false), class(true));
+        assert_eq!(off, "btn primary");
+        assert_eq!(on, "btn active primary");
+        assert_eq!(token_diff(&off, &on), (vec![], vec!["active"]));
+        assert_eq!(token_diff(&on, &off), (vec!["active"], vec![]));
+        assert_eq!(classes![], "");
+    }
+
+    #[test]
+    fn change_style() {
+        let style = |color: &str, width: &str| styles![("display", "flex"), ("color", color), ("width", width)];
+        let (before, after) = (style("red", "10px"), style("blue", ""));
+        assert_eq!(before, "display: flex; color: red; width: 10px");
+        assert_eq!(after, "display: flex; color: blue");
+        assert_eq!(style_diff(&before, &after), (vec!["width"], vec![("color", "blue")]));
+    }
+
+    #[test]
+    fn nested_component_first() {
+        let inner = Rsx::Component(Comp {children: vec![Rsx::component(), element!("SPAN", vec![], vec![])]});
+        let parent = element!("UL", vec![], vec![Rsx::Component(Comp {children: vec![Rsx::component(), inner]}), Rsx::new_text("x".to_string())]);
+        let children = parent.children().unwrap();
+        assert!(matches!(children[0].first(), Some(Rsx::Element(e)) if e.name == "SPAN"));
+        assert!(Rsx::component().first().is_none());
+    }
+
+    #[test]
+    fn empty_component_node() {
+        let nested = Rsx::Component(Comp {children: vec![Rsx::component(), Rsx::new_keyed(vec![])]});
+        for empty in [Rsx::component(), Rsx::new_keyed(vec![]), nested] {
+            assert!(empty.node().is_none());
+            assert!(rendered_nodes(&empty).is_empty());
+        }
+    }
+
+    #[test]
+    fn handler_panics() {
+        thread_local! {
+            static CAUGHT: RefCell<Vec<String>> = const { RefCell::new(vec![]) };
+        }
+        fn bad() {
+            batch(|| {
+                queue_render(Rsx::component());
+                panic!("bad handler");
+            });
+        }
+        fn good() {
+            CAUGHT.with(|c| c.borrow_mut().push("ok".to_string()));
+        }
+        set_error_handler(|e| CAUGHT.with(|c| c.borrow_mut().push(e.to_string())));
+        assert!(!guarded(bad));
+        assert_eq!(BATCH_DEPTH.with(|d| d.get()), 0);
+        assert!(PENDING_RENDERS.with(|p| p.borrow().is_empty()));
+        assert!(guarded(good));
+        assert_eq!(CAUGHT.with(|c| c.borrow().clone()), vec!["bad handler", "ok"]);
+        NODE_ID.with(|n| *n.borrow_mut() = "kept".to_string());
+        queue_render(Rsx::component());
+        NODE_ID.with(|n| n.borrow_mut().clear());
+        assert!(!guarded(bad));
+        assert_eq!(PENDING_RENDERS.with(|p| p.borrow().iter().map(|(id, _, _)| id.clone()).collect::<Vec<_>>()), ["kept"]);
+        PENDING_RENDERS.with(|p| p.borrow_mut().clear());
+    }
+
+    #[test]
+    fn form_properties() {
+        assert_eq!(idl_target("value", Some("typed")), Some(Idl::Text("typed".to_string())));
+        assert_eq!(idl_target("value", None), Some(Idl::Text(String::new())));
+        let checked = |on: bool| if on { Some("") } else { None };
+        for on in [true, false, true] {
+            assert_eq!(idl_target("checked", checked(on)), Some(Idl::Flag(on)));
+        }
+        assert_eq!(idl_target("selected", Some("false")), Some(Idl::Flag(false)));
+        assert_eq!(idl_target("class", Some("a")), None);
+    }
+
+    #[test]
+    fn element_key() {
+        let el = element!("LI", attributes![("key".to_string(), "7".to_string())], vec![]);
+        assert_eq!(el.key(), Some("7"));
+        assert_eq!(Rsx::new_text("7".to_string()).key(), None);
+    }
+
+    #[test]
+    fn unchanged_text_kept() {
+        assert!(text_unchanged(Node::TEXT_NODE, Some("3 items"), "3 items"));
+        assert!(!text_unchanged(Node::TEXT_NODE, Some("3 items"), "4 items"));
+        assert!(!text_unchanged(Node::ELEMENT_NODE, Some("3 items"), "3 items"));
+        assert!(!text_unchanged(Node::COMMENT_NODE, Some(""), ""));
+    }
+
+    #[test]
+    fn island_selection() {
+        let islands = [Some("a".to_string()), None, Some("b".to_string())];
+        assert_eq!(island_script(&islands, Some("b")), Some(2));
+        assert_eq!(island_script(&islands, Some("c")), None);
+        assert_eq!(island_script(&islands, None), Some(1));
+        assert_eq!(island_script(&islands[..1], None), None);
+    }
+
+    #[test]
+    fn attribute_escaping() {
+        assert!(matches!(attr_escape("plain > text"), Cow::Borrowed("plain > text")));
+        assert_eq!(attr_escape(r#"say "hi""#), "say &quot;hi&quot;");
+        assert_eq!(attr_escape("?a=1&b=2"), "?a=1&amp;b=2");
+        assert_eq!(attr_escape("<script>"), "&lt;script>");
+        assert_eq!(attr_escape("it's"), "it&#x27;s");
+    }
+
+    #[test]
+    fn escape_into_buffer() {
+        let mut out = "<p>".to_string();
+        html_escape_to("a < b && \"c\" > 'd'", &mut out);
+        assert_eq!(out, "<p>a &lt; b &amp;&amp; &quot;c&quot; &gt; &#x27;d&#x27;");
+        assert_eq!(html_escape("é<ü"), "é&lt;ü");
+        let clean = "lorem ipsum ".repeat(100 * 1024 / 12);
+        let Cow::Borrowed(borrowed) = html_escape_cow(&clean) else {
+            panic!("clean input was copied");
+        };
+        assert_eq!(borrowed.as_ptr(), clean.as_ptr());
+        assert_eq!(html_escape_cow("1 & 2"), "1 &amp; 2");
+    }
+
+    #[test]
+    fn context_across_mounts() {
+        #[derive(Debug, PartialEq)]
+        struct Theme(&'static str);
+        thread_local! {
+            static SEEN: RefCell<Vec<Option<&'static str>>> = const { RefCell::new(vec![]) };
+        }
+        fn child(_: String) {
+            SEEN.with(|s| s.borrow_mut().push(use_context::<Theme>().map(|t| t.0)));
+            on_unmount(|| SEEN.with(|s| s.borrow_mut().push(None)));
+        }
+        fn parent(_: String) {
+            provide_context(Theme("dark"));
+            NODE_ID.with(|n| *n.borrow_mut() = "ctx-child".to_string());
+            mount("ctx-child", child);
+        }
+        CTX.with(|c| {
+            let mut c = c.borrow_mut();
+            c.insert("ctx-parent".to_string(), Ctx::R("ctx-parent".to_string()));
+            c.insert("ctx-child".to_string(), Ctx::R("ctx-child".to_string()));
+        });
+        NODE_ID.with(|n| *n.borrow_mut() = "ctx-parent".to_string());
+        mount("ctx-parent", parent);
+        assert_eq!(SEEN.with(|s| s.take()), [Some("dark")]);
+        unmount("ctx-parent");
+        assert_eq!(SEEN.with(|s| s.take()), [None]);
+    }
+
+    #[test]
+    fn raw_html() {
+        let rsx = element!("DIV", vec![], vec![Rsx::new_html("<b>hi</b>")]);
+        let Rsx::Html(html) = &rsx.as_elem().children[0] else { panic!("expected raw html") };
+        assert_eq!(html.html, "<b>hi</b>");
+        assert!(rsx.as_elem().children[0].first().is_some());
+        assert_eq!(rsx.as_elem().children[0].key(), None);
+        assert!(!template::shape(rsx.as_elem(), &mut String::new()));
+    }
+
+    #[test]
+    fn nested_fragment() {
+        let row = element!("TR", vec![], vec![
+            element!("TD", vec![], vec![Rsx::new_text("a".to_string())]),
+            Rsx::new_fragment(vec![element!("TD", vec![], vec![]), Rsx::new_fragment(vec![])]),
+        ]);
+        let children = row.as_elem().children[1].children().unwrap();
+        assert_eq!(children.len(), 2);
+        assert!(matches!(children[1], Rsx::Fragment(ref f) if f.children.is_empty()));
+        let markers = [Some("av"), None, Some("av"), Some("/av"), Some("/av"), None];
+        assert_eq!(run_length(markers), Some(4));
+        assert_eq!(run_length([Some("av"), Some("av a:id=2"), Some("/av")]), None);
+        assert_eq!(vnode_balance(["av a:id=0", "av", "av", "/av", "/av", "/av"]), Ok(()));
+    }
+
+    #[test]
+    fn void_children_dropped() {
+        let Rsx::Element(mut img) = element!("IMG", attributes![("src".to_string(), "a.png".to_string())], vec![Rsx::new_text("oops".to_string())]) else { unreachable!() };
+        assert!(img.strip_void_children());
+        assert!(img.children.is_empty());
+        assert!(!img.strip_void_children());
+        let Rsx::Element(mut p) = element!("P", vec![], vec![Rsx::new_text("ok".to_string())]) else { unreachable!() };
+        assert!(!p.strip_void_children());
+        assert_eq!(p.children.len(), 1);
+        assert!(is_void("br") && is_void("INPUT") && !is_void("template"));
+    }
+}
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod dom_tests {
+    use wasm_bindgen_test::*;
+    use super::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    /// Renders `children` into a fresh `<div>`.
+    fn render(children: &mut [Rsx]) -> Element {
+        DOCUMENT.with(|document| {
+            let div = document.create_element("div").unwrap();
+            for child in children {
+                div.append_child(&child.to_node(document)).unwrap();
+            }
+            div
+        })
+    }
+
+    /// Diffs `children` against what `div` holds, as a rerender does.
+    fn rerender_into(children: &mut Vec<Rsx>, div: &Element) {
+        let mut first = div.first_child().unwrap();
+        check_siblings(children, &mut first);
+    }
+
+    fn text(s: &str) -> Rsx {
+        Rsx::new_text(s.to_string())
+    }
+
+    /// Resolves in the next animation frame, after the callbacks requested before it.
+    async fn next_frame() {
+        let frame = js_sys::Promise::new(&mut |resolve, _| {
+            WINDOW.with(|w| w.request_animation_frame(&resolve).unwrap());
+        });
+        wasm_bindgen_futures::JsFuture::from(frame).await.unwrap();
+    }
+
+    #[wasm_bindgen_test]
+    async fn enter_class_frames() {
+        set_enter_class(Some("entering"));
+        let el = DOCUMENT.with(|document| document.create_element("p").unwrap());
+        enter(el.clone().into());
+        set_enter_class(None);
+        assert!(el.class_list().contains("entering"));
+        next_frame().await;
+        assert!(el.class_list().contains("entering"));
+        next_frame().await;
+        assert!(!el.class_list().contains("entering"));
+    }
+
+    #[wasm_bindgen_test]
+    fn refs_cleared_on_unmount() {
+        let canvas = || element!("CANVAS", attributes![("ref".to_string(), "chart".to_string())], vec![]);
+        for index in ["refs0", "refs1"] {
+            CTX.with(|c| c.borrow_mut().insert(index.to_string(), Ctx::R(index.to_string())));
+        }
+        NODE_ID.with(|n| *n.borrow_mut() = "refs0".to_string());
+        let first = render(&mut [canvas()]).first_element_child().unwrap();
+        NODE_ID.with(|n| *n.borrow_mut() = "refs1".to_string());
+        let other = render(&mut [canvas()]).first_element_child().unwrap();
+        assert_eq!(node_ref("chart"), Some(other.clone()));
+        NODE_ID.with(|n| *n.borrow_mut() = "refs0".to_string());
+        assert_eq!(node_ref("chart"), Some(first));
+        unmount("refs0");
+        assert_eq!(node_ref("chart"), None);
+        NODE_ID.with(|n| *n.borrow_mut() = "refs1".to_string());
+        assert_eq!(node_ref("chart"), Some(other));
+        unmount("refs1");
+        assert_eq!(node_ref("chart"), None);
+    }
+
+    #[wasm_bindgen_test]
+    fn recalls_stable() {
+        fn noop() {}
+        fn mount(_: String) {}
+        CALLBACKS.with(|c| c.borrow_mut().insert("menu_toggle".to_string(), CallbackData {new: mount, call: noop}));
+        let menu = |class: &str, ids: &str| element!("BUTTON", attributes![("class".to_string(), class.to_string()), ("on:click".to_string(), format!("menu_toggle[{}]", ids))], vec![]);
+        let diff = |rsx: Rsx, node: &mut Node| match rsx {
+            Rsx::Element(mut elem) => elem.diff(node),
+            _ => unreachable!(),
+        };
+        let div = render(&mut [menu("a", "5")]);
+        let mut node: Node = div.first_child().unwrap();
+        let rid = node.dyn_ref::<Element>().unwrap().get_attribute("rid").unwrap();
+        for class in ["b", "c"] {
+            diff(menu(class, "5"), &mut node);
+            let el = node.dyn_ref::<Element>().unwrap();
+            assert_eq!(el.get_attribute("class").as_deref(), Some(class));
+            assert_eq!(el.get_attribute("rid").as_ref(), Some(&rid));
+            assert_eq!(RECALLS.with(|r| r.borrow()[&rid].ids.clone()), "5");
+        }
+        diff(menu("c", "6"), &mut node);
+        let moved = node.dyn_ref::<Element>().unwrap().get_attribute("rid").unwrap();
+        assert_ne!(moved, rid);
+        RECALLS.with(|r| {
+            let recalls = r.borrow();
+            assert!(!recalls.contains_key(&rid));
+            assert_eq!(recalls[&moved].ids, "6");
+        });
+    }
+
+    #[wasm_bindgen_test]
+    fn empty_component_rerenders() {
+        let mut before = vec![Rsx::component(), text("a")];
+        let div = render(&mut before);
+        let mut after = vec![Rsx::component(), text("b")];
+        vcheck_both(&mut after, &before);
+        assert_eq!(div.inner_html(), "b");
+        vupdate(&mut Rsx::component(), &Rsx::component(), false);
+    }
+
+    /// Appends a state script to the body, marked with `island` if given.
+    fn state_script(island: Option<&str>, json: &str) -> Element {
+        DOCUMENT.with(|document| {
+            let script = document.create_element("script").unwrap();
+            script.set_attribute("type", "app/json").unwrap();
+            if let Some(island) = island {
+                script.set_attribute("data-island", island).unwrap();
+            }
+            script.set_text_content(Some(json));
+            document.body().unwrap().append_child(&script).unwrap();
+            script
+        })
+    }
+
+    #[wasm_bindgen_test]
+    fn two_islands() {
+        let a = state_script(Some("a"), r#"{"ctx":{"a":{"R":"0"}},"objs":[1],"subs":[]}"#);
+        let b = state_script(Some("b"), r#"{"ctx":{"b":{"R":"1"},"b1":{"R":"2"}},"objs":[2,3],"subs":[]}"#);
+        assert_eq!(load_island_state("b"), Ok(true));
+        assert!(a.is_connected());
+        assert!(!b.is_connected());
+        NODE_ID.with(|n| *n.borrow_mut() = "b1".to_string());
+        assert_eq!(with_state(|state| state.objs().len()), Some(2));
+        assert_eq!(load_island_state("a"), Ok(true));
+        assert!(!a.is_connected());
+        NODE_ID.with(|n| *n.borrow_mut() = "a".to_string());
+        assert_eq!(with_state(|state| state.objs().len()), Some(1));
+        assert_eq!(load_island_state("c"), Ok(false));
+    }
+
+    #[wasm_bindgen_test]
+    fn mount_without_state() {
+        APP_STATE.with(|a| a.borrow_mut().take());
+        assert!(!mount_state("orphan"));
+        assert_eq!(island_objs("orphan"), Some(0));
+        APP_STATE.with(|a| a.borrow_mut().take());
+        let bad = state_script(Some("torn"), r#"{"ctx":{"torn":{"R":"0"}},"ob"#);
+        assert!(!mount_state("torn"));
+        assert_eq!(island_objs("torn"), Some(0));
+        bad.remove();
+        APP_STATE.with(|a| a.borrow_mut().take());
+    }
+
+    thread_local! {
+        static HYDRATED: RefCell<Vec<String>> = const { RefCell::new(vec![]) };
+    }
+
+    fn counter(node_id: String) {
+        HYDRATED.with(|h| h.borrow_mut().push(node_id));
+    }
+
+    fn island_objs(node_id: &str) -> Option<usize> {
+        NODE_ID.with(|n| *n.borrow_mut() = node_id.to_string());
+        with_state(|state| state.objs().len())
+    }
+
+    #[wasm_bindgen_test]
+    fn swapped_islands() {
+        MOUNTS.with(|m| m.borrow_mut().insert("Counter".to_string(), counter));
+        let island = |id: &str, objs: &str| format!(r#"<script type="app/json" data-island="{id}">{{"ctx":{{"{id}":{{"R":"{id}"}}}},"objs":[{objs}],"subs":[]}}</script><div data-hydrate="eager" data-component="Counter" a:id="{id}"><button>+</button></div>"#);
+        let page = DOCUMENT.with(|document| document.create_element("div").unwrap());
+        page.set_inner_html(&format!(r#"{}<div id="feed"></div>"#, island("s1", "1")));
+        DOCUMENT.with(|document| document.body().unwrap().append_child(&page).unwrap());
+        hydrate::hydrate_island(&page.query_selector("[data-hydrate]").unwrap().unwrap());
+        let feed = page.query_selector("#feed").unwrap().unwrap();
+        swap_html(&feed, &island("s2", "5"), SwapMode::InnerHtml);
+        assert_eq!(HYDRATED.with(|h| h.take()), ["s1", "s2"]);
+        assert_eq!(island_objs("s1"), Some(1));
+        assert_eq!(island_objs("s2"), Some(1));
+        swap_html(&feed, &island("s2", "6,7"), SwapMode::InnerHtml);
+        assert_eq!(HYDRATED.with(|h| h.take()), ["s2"]);
+        assert_eq!(island_objs("s1"), Some(1));
+        assert_eq!(island_objs("s2"), Some(2));
+        assert!(MOUNTED.with(|m| m.borrow().contains("s1")));
+        page.remove();
+    }
+
+    #[wasm_bindgen_test]
+    fn writes_render_once() {
+        let mut first = vec![text("0")];
+        let div = render(&mut first);
+        CTX.with(|c| c.borrow_mut().insert("batched".to_string(), Ctx::R("batched".to_string())));
+        VIRT_NODES.with(|v| v.borrow_mut().insert("batched".to_string(), Rsx::Component(Comp {children: first})));
+        NODE_ID.with(|n| *n.borrow_mut() = "batched".to_string());
+        let node = div.first_child().unwrap();
+        for n in 1..=5 {
+            rerender_batched(Rsx::Component(Comp {children: vec![text(&n.to_string())]}));
+        }
+        assert_eq!(PENDING_RENDERS.with(|p| p.borrow().len()), 1);
+        assert_eq!(div.text_content().as_deref(), Some("0"));
+        flush_renders();
+        assert!(PENDING_RENDERS.with(|p| p.borrow().is_empty()));
+        assert_eq!(div.text_content().as_deref(), Some("5"));
+        assert!(div.first_child().unwrap().is_same_node(Some(&node)));
+    }
+
+    #[wasm_bindgen_test]
+    fn typed_props() {
+        let prop = |el: &Element, name: &str| js_sys::Reflect::get(el, &JsValue::from_str(name)).unwrap();
+        let input = DOCUMENT.with(|document| document.create_element("input").unwrap());
+        input.set_attribute("type", "checkbox").unwrap();
+        set_prop(&input, "indeterminate", "true");
+        set_prop(&input, "tabIndex", "3");
+        set_prop(&input, "value", "007");
+        set_prop(&input, "note", "1");
+        assert_eq!(prop(&input, "indeterminate"), JsValue::TRUE);
+        assert!(!input.has_attribute("indeterminate"));
+        assert_eq!(prop(&input, "tabIndex").as_f64(), Some(3.0));
+        assert_eq!(prop(&input, "value").as_string().as_deref(), Some("007"));
+        assert_eq!(prop(&input, "note").as_string().as_deref(), Some("1"));
+    }
+
+    #[wasm_bindgen_test]
+    fn unchanged_text_kept() {
+        let p = |a: &str, b: &str| vec![element!("P", vec![], vec![text(a), element!("B", vec![], vec![text(b)])])];
+        let div = render(&mut p("3 items", "total"));
+        let paragraph = div.first_child().unwrap();
+        let (count, total) = (paragraph.first_child().unwrap(), paragraph.last_child().unwrap().first_child().unwrap());
+        let mut rerendered = p("3 items", "sum");
+        rerender_into(&mut rerendered, &div);
+        assert!(paragraph.first_child().unwrap().is_same_node(Some(&count)));
+        assert!(!paragraph.last_child().unwrap().first_child().unwrap().is_same_node(Some(&total)));
+        assert_eq!(div.text_content().as_deref(), Some("3 itemssum"));
+        match &rerendered[0].as_elem().children[0] {
+            Rsx::Text(t) => assert!(t.node.as_ref().unwrap().is_same_node(Some(&count))),
+            _ => unreachable!(),
+        }
+    }
+
+    struct Cell {
+        pos: usize,
+        id: char,
+        label: String,
+    }
+
+    impl RefChild for Cell {
+        type Item = char;
+        fn new(pos: usize, id: char) -> Self {
+            Self {pos, id, label: id.to_string()}
+        }
+        fn pos(&self) -> usize {
+            self.pos
+        }
+        fn pos_mut(&mut self) -> &mut usize {
+            &mut self.pos
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn sorted_rows_keep_nodes() {
+        let view = |rows: &RefVec<Cell>| rows.iter().map(|r| element!("LI", attributes![("key".to_string(), r.id.to_string())], vec![text(&r.label)])).collect::<Vec<_>>();
+        let mut rows: RefVec<Cell> = RefVec::new();
+        rows.append(&mut "dbca".chars().collect());
+        let div = render(&mut view(&rows));
+        let b = div.children().item(1).unwrap();
+        rows.sort_by_key(|r| r.id);
+        rerender_into(&mut view(&rows), &div);
+        assert_eq!(div.text_content().as_deref(), Some("abcd"));
+        assert!(div.children().item(1).unwrap().is_same_node(Some(&b)));
+        rows.get_mut(1).unwrap().label = "B".to_string();
+        rerender_into(&mut view(&rows), &div);
+        assert_eq!(b.text_content().as_deref(), Some("B"));
+        assert_eq!(div.text_content().as_deref(), Some("aBcd"));
+    }
+
+    #[wasm_bindgen_test]
+    fn keyed_insert_enters() {
+        let li = |key: &str| element!("LI", attributes![("key".to_string(), key.to_string())], vec![text(key)]);
+        let div = render(&mut [li("a"), li("b")]);
+        template::set_template_cache(true);
+        template::clear_templates();
+        set_enter_class(Some("entering"));
+        rerender_into(&mut vec![li("a"), li("c"), li("b")], &div);
+        set_enter_class(None);
+        assert_eq!(div.text_content().as_deref(), Some("acb"));
+        let classes: Vec<bool> = (0..3).map(|n| div.children().item(n).unwrap().class_list().contains("entering")).collect();
+        assert_eq!(classes, [false, true, false]);
+        assert_eq!(template::TEMPLATES.with(|t| t.borrow().len()), 1);
+    }
+
+    #[wasm_bindgen_test]
+    fn unmount_once() {
+        thread_local! {
+            static LOG: RefCell<Vec<&'static str>> = const { RefCell::new(vec![]) };
+        }
+        fn new(_: String) {
+            on_mount(|| LOG.with(|l| l.borrow_mut().push("mounted")));
+            LOG.with(|l| l.borrow_mut().push("new"));
+        }
+        mount("clock", new);
+        owner::component("13").on_cleanup(|| LOG.with(|l| l.borrow_mut().push("unmounted")));
+        let mut first = vec![element!("P", vec![], vec![text("a")]), element!("P", vec![], vec![text("b")])];
+        let div = render(&mut first);
+        let paragraphs = div.children();
+        for i in 0..paragraphs.length() {
+            paragraphs.item(i).unwrap().insert_adjacent_html("afterbegin", "<!--av a:id=13 a:name=clock--><!--/av-->").unwrap();
+        }
+        CTX.with(|c| c.borrow_mut().insert("diffed".to_string(), Ctx::R("diffed".to_string())));
+        VIRT_NODES.with(|v| v.borrow_mut().insert("diffed".to_string(), Rsx::Component(Comp {children: first})));
+        NODE_ID.with(|n| *n.borrow_mut() = "diffed".to_string());
+        rerender(Rsx::component());
+        assert_eq!(div.child_nodes().length(), 0);
+        rerender(Rsx::component());
+        assert_eq!(LOG.with(|l| l.take()), ["new", "mounted", "unmounted"]);
+    }
+
+    #[wasm_bindgen_test]
+    fn notify_schedules() {
+        let mut flags = Signal::new(std::cell::Cell::new(false));
+        flags._proxy._invalid = false;
+        NODE_ID.with(|n| *n.borrow_mut() = "flags".to_string());
+        flags.peek().set(true);
+        flags.notify();
+        flags.notify();
+        assert!(flags._proxy._invalid);
+        assert_eq!(STALE.with(|s| s.borrow().clone()), ["flags"]);
+    }
+
+    #[wasm_bindgen_test]
+    fn raw_html_nodes() {
+        let mut children = vec![Rsx::new_html("<b>hi</b>"), text("after")];
+        let div = render(&mut children);
+        let b = div.query_selector("b").unwrap().expect("expected a <b> element");
+        assert_eq!(b.text_content().as_deref(), Some("hi"));
+        rerender_into(&mut vec![Rsx::new_html("<b>hi</b>"), text("after")], &div);
+        assert!(div.query_selector("b").unwrap().unwrap().is_same_node(Some(&b)));
+        rerender_into(&mut vec![Rsx::new_html("<b>a</b><i>b</i><u>c</u>"), text("after")], &div);
+        assert_eq!(div.text_content().as_deref(), Some("abcafter"));
+        rerender_into(&mut vec![Rsx::new_html("<i>d</i>"), text("after")], &div);
+        assert_eq!(div.text_content().as_deref(), Some("dafter"));
+        assert!(div.query_selector("b").unwrap().is_none());
+    }
+}
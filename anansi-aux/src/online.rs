@@ -0,0 +1,141 @@
+use std::cell::{Cell, RefCell};
+
+use wasm_bindgen::JsCast;
+use wasm_bindgen::closure::Closure;
+use web_sys::Event;
+
+use crate::{WINDOW, NODE_ID, Signal, on_unmount, schedule_rerender};
+
+thread_local! {
+    static ONLINE: Cell<Option<bool>> = const { Cell::new(None) };
+    static LISTENING: Cell<bool> = const { Cell::new(false) };
+    static SUBSCRIBERS: RefCell<Vec<String>> = const { RefCell::new(vec![]) };
+}
+
+/// Applies an `online` or `offline` event, rerendering the components that
+/// read it when the connectivity changed.
+fn record(event_type: &str) {
+    let online = event_type == "online";
+    if ONLINE.with(|o| o.replace(Some(online))) != Some(online) {
+        let subscribers = SUBSCRIBERS.with(|s| s.borrow().clone());
+        for node_id in subscribers {
+            schedule_rerender(&node_id);
+        }
+    }
+}
+
+/// Adds the window's `online` and `offline` listeners the first time any
+/// component asks. They stay for the page's lifetime and serve everyone.
+fn listen() {
+    if LISTENING.with(|l| l.replace(true)) {
+        return;
+    }
+    ONLINE.with(|o| o.set(Some(WINDOW.with(|w| w.navigator().on_line()))));
+    let cb = Closure::<dyn FnMut(Event)>::new(|event: Event| record(&event.type_()));
+    WINDOW.with(|w| {
+        for event in ["online", "offline"] {
+            w.add_event_listener_with_callback(event, cb.as_ref().unchecked_ref()).unwrap();
+        }
+    });
+    cb.forget();
+}
+
+/// Keeps the component at `node_id` rerendering on connectivity changes
+/// until it unmounts. Returns whether it wasn't already.
+fn subscribe(node_id: String) -> bool {
+    SUBSCRIBERS.with(|s| {
+        let mut subscribers = s.borrow_mut();
+        if subscribers.contains(&node_id) {
+            return false;
+        }
+        subscribers.push(node_id);
+        true
+    })
+}
+
+/// The latest connectivity reported by an `online` or `offline` event, or
+/// `navigator.onLine` before any has fired.
+pub fn is_online() -> bool {
+    ONLINE.with(|o| o.get()).unwrap_or_else(|| WINDOW.with(|w| w.navigator().on_line()))
+}
+
+/// Whether the browser is online. The component rerenders when the window's
+/// `online` and `offline` events change it, until it unmounts.
+pub fn use_online() -> Signal<bool> {
+    listen();
+    let node_id = NODE_ID.with(|n| n.borrow().clone());
+    if subscribe(node_id.clone()) {
+        on_unmount(move || SUBSCRIBERS.with(|s| s.borrow_mut().retain(|id| *id != node_id)));
+    }
+    Signal::new(is_online())
+}
+
+/// Copies the latest connectivity into `online`.
+pub fn sync_online(online: &mut Signal<bool>) {
+    online.set(is_online());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{counted_component, renders};
+
+    #[test]
+    fn toggles() {
+        let mut online = Signal::new(true);
+        record("offline");
+        sync_online(&mut online);
+        assert!(!*online.value());
+        record("online");
+        sync_online(&mut online);
+        assert!(*online.value());
+    }
+
+    #[test]
+    fn change_rerenders() {
+        counted_component("net");
+        ONLINE.with(|o| o.set(Some(true)));
+        assert!(subscribe("net".to_string()));
+        assert!(!subscribe("net".to_string()));
+        record("offline");
+        record("offline");
+        assert_eq!(renders(), 1);
+        assert!(!is_online());
+        record("online");
+        assert_eq!(renders(), 2);
+    }
+}
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod dom_tests {
+    use wasm_bindgen_test::*;
+    use super::*;
+    use crate::fixtures::{counted_component, renders};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn dispatch(event_type: &str) {
+        let event = Event::new(event_type).unwrap();
+        WINDOW.with(|w| w.dispatch_event(&event).unwrap());
+    }
+
+    #[wasm_bindgen_test]
+    fn window_events() {
+        counted_component("net-dom");
+        use_online();
+        dispatch("online");
+        let before = renders();
+        dispatch("offline");
+        assert!(!is_online());
+        assert_eq!(renders(), before + 1);
+        dispatch("offline");
+        assert_eq!(renders(), before + 1);
+        dispatch("online");
+        assert!(is_online());
+        assert_eq!(renders(), before + 2);
+        crate::unmount("net-dom");
+        dispatch("offline");
+        assert_eq!(renders(), before + 2);
+        dispatch("online");
+    }
+}
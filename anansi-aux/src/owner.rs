@@ -0,0 +1,196 @@
+use std::any::{Any, TypeId};
+use std::rc::{Rc, Weak};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+
+thread_local! {
+    static CURRENT: RefCell<Option<Owner>> = const { RefCell::new(None) };
+    static COMPONENTS: RefCell<HashMap<String, Owner>> = RefCell::new(HashMap::new());
+}
+
+#[derive(Default)]
+struct Scope {
+    parent: RefCell<Weak<Scope>>,
+    children: RefCell<Vec<Owner>>,
+    cleanups: RefCell<Vec<Box<dyn FnOnce()>>>,
+    contexts: RefCell<HashMap<TypeId, Rc<dyn Any>>>,
+    disposed: Cell<bool>,
+}
+
+/// A node of the ownership tree. Each mounted component has one, and the
+/// effects, subscriptions and `on_unmount` callbacks registered while it is
+/// current belong to it. Disposing an owner disposes its children first,
+/// newest first, then runs its own cleanups in the order they were added.
+#[derive(Clone)]
+pub struct Owner(Rc<Scope>);
+
+impl Owner {
+    /// A new owner, child of the current one if there is one.
+    pub fn new() -> Self {
+        let owner = Self(Rc::default());
+        if let Some(parent) = current() {
+            parent.adopt(&owner);
+        }
+        owner
+    }
+    fn adopt(&self, child: &Owner) {
+        *child.0.parent.borrow_mut() = Rc::downgrade(&self.0);
+        self.0.children.borrow_mut().push(child.clone());
+    }
+    /// Runs `f` with this owner current, so what `f` registers belongs to it.
+    /// The outer owner is put back even if `f` panics.
+    pub fn run<R>(&self, f: impl FnOnce() -> R) -> R {
+        struct Restore(Option<Owner>);
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                set_current(self.0.take());
+            }
+        }
+        let _restore = Restore(CURRENT.with(|c| c.replace(Some(self.clone()))));
+        f()
+    }
+    pub fn on_cleanup(&self, f: impl FnOnce() + 'static) {
+        if self.0.disposed.get() {
+            f();
+        } else {
+            self.0.cleanups.borrow_mut().push(Box::new(f));
+        }
+    }
+    /// Makes `value` the context of its type for this owner and its descendants.
+    pub fn provide<T: 'static>(&self, value: T) {
+        self.0.contexts.borrow_mut().insert(TypeId::of::<T>(), Rc::new(value));
+    }
+    /// The nearest context of type `T` provided by this owner or its ancestors.
+    pub fn lookup<T: 'static>(&self) -> Option<Rc<T>> {
+        let mut scope = self.0.clone();
+        loop {
+            let found = scope.contexts.borrow().get(&TypeId::of::<T>()).cloned();
+            if let Some(value) = found {
+                return value.downcast().ok();
+            }
+            let parent = scope.parent.borrow().upgrade()?;
+            scope = parent;
+        }
+    }
+    pub fn is_disposed(&self) -> bool {
+        self.0.disposed.get()
+    }
+    pub fn dispose(&self) {
+        if self.0.disposed.replace(true) {
+            return;
+        }
+        let children = std::mem::take(&mut *self.0.children.borrow_mut());
+        for child in children.iter().rev() {
+            child.dispose();
+        }
+        let cleanups = std::mem::take(&mut *self.0.cleanups.borrow_mut());
+        for cleanup in cleanups {
+            cleanup();
+        }
+        if let Some(parent) = self.0.parent.borrow().upgrade() {
+            parent.children.borrow_mut().retain(|c| !Rc::ptr_eq(&c.0, &self.0));
+        }
+    }
+}
+
+impl Default for Owner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The owner set by `Owner::run`, if any.
+pub fn current() -> Option<Owner> {
+    CURRENT.with(|c| c.borrow().clone())
+}
+
+pub(crate) fn set_current(owner: Option<Owner>) {
+    CURRENT.with(|c| *c.borrow_mut() = owner);
+}
+
+/// The owner of the component at `index`, created on first use as a child of
+/// the current owner, i.e. of the component whose render mounts it. A
+/// component remounted after its owner was disposed gets a fresh one.
+pub fn component(index: &str) -> Owner {
+    if let Some(owner) = COMPONENTS.with(|c| c.borrow().get(index).filter(|o| !o.is_disposed()).cloned()) {
+        return owner;
+    }
+    let owner = Owner::new();
+    COMPONENTS.with(|c| c.borrow_mut().insert(index.to_string(), owner.clone()));
+    owner
+}
+
+pub(crate) fn dispose_component(index: &str) {
+    if let Some(owner) = COMPONENTS.with(|c| c.borrow_mut().remove(index)) {
+        owner.dispose();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disposes_bottom_up() {
+        let log = Rc::new(RefCell::new(vec![]));
+        let push = |s: &'static str| {
+            let log = log.clone();
+            move || log.borrow_mut().push(s)
+        };
+        let parent = Owner::new();
+        parent.run(|| {
+            parent.on_cleanup(push("parent"));
+            let child = Owner::new();
+            child.run(|| {
+                child.on_cleanup(push("child"));
+                Owner::new().on_cleanup(push("grandchild"));
+            });
+            Owner::new().on_cleanup(push("sibling"));
+        });
+        assert!(current().is_none());
+        let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| parent.run(|| panic!("render failed"))));
+        assert!(current().is_none());
+        parent.dispose();
+        assert_eq!(*log.borrow(), ["sibling", "grandchild", "child", "parent"]);
+        parent.on_cleanup(push("late"));
+        assert_eq!(log.borrow().last(), Some(&"late"));
+    }
+
+    #[test]
+    fn component_tree() {
+        let log = Rc::new(RefCell::new(vec![]));
+        let l = log.clone();
+        let parent = component("p1");
+        let child = parent.run(|| component("c1"));
+        assert!(Rc::ptr_eq(&child.0, &component("c1").0));
+        child.on_cleanup(move || l.borrow_mut().push("child"));
+        dispose_component("p1");
+        assert_eq!(*log.borrow(), ["child"]);
+        assert!(child.is_disposed());
+        assert!(!component("c1").is_disposed());
+    }
+
+    #[test]
+    fn context_two_levels_up() {
+        #[derive(Debug, PartialEq)]
+        struct Theme(&'static str);
+        let root = Owner::new();
+        let (read, shadowed, sibling) = root.run(|| {
+            root.provide(Theme("dark"));
+            root.provide(7u32);
+            let child = Owner::new();
+            let (read, shadowed) = child.run(|| {
+                let grandchild = Owner::new();
+                let read = grandchild.lookup::<Theme>();
+                child.provide(Theme("light"));
+                (read, grandchild.lookup::<Theme>())
+            });
+            (read, shadowed, Owner::new().lookup::<Theme>())
+        });
+        assert_eq!(read.as_deref(), Some(&Theme("dark")));
+        assert_eq!(shadowed.as_deref(), Some(&Theme("light")));
+        assert_eq!(sibling.as_deref(), Some(&Theme("dark")));
+        assert_eq!(Owner::new().lookup::<Theme>(), None);
+        assert_eq!(root.lookup::<u32>().as_deref(), Some(&7));
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de::{MapAccess, SeqAccess, Visitor};
+use serde::ser::{SerializeMap, SerializeSeq};
+use serde_json::{Map, Number, Value};
+
+/// A `Value` whose object keys are indices into the document's key table. It
+/// is written as the plain MessagePack value, with maps keyed by integers.
+enum Packed {
+    Null,
+    Bool(bool),
+    Uint(u64),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Array(Vec<Packed>),
+    Map(Vec<(u32, Packed)>),
+}
+
+impl Serialize for Packed {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Self::Null => serializer.serialize_unit(),
+            Self::Bool(b) => serializer.serialize_bool(*b),
+            Self::Uint(u) => serializer.serialize_u64(*u),
+            Self::Int(i) => serializer.serialize_i64(*i),
+            Self::Float(f) => serializer.serialize_f64(*f),
+            Self::Str(s) => serializer.serialize_str(s),
+            Self::Array(values) => {
+                let mut seq = serializer.serialize_seq(Some(values.len()))?;
+                for v in values {
+                    seq.serialize_element(v)?;
+                }
+                seq.end()
+            }
+            Self::Map(entries) => {
+                let mut map = serializer.serialize_map(Some(entries.len()))?;
+                for (key, v) in entries {
+                    map.serialize_entry(key, v)?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+struct PackedVisitor;
+
+impl<'de> Visitor<'de> for PackedVisitor {
+    type Value = Packed;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a packed value")
+    }
+    fn visit_unit<E>(self) -> Result<Packed, E> {
+        Ok(Packed::Null)
+    }
+    fn visit_none<E>(self) -> Result<Packed, E> {
+        Ok(Packed::Null)
+    }
+    fn visit_bool<E>(self, b: bool) -> Result<Packed, E> {
+        Ok(Packed::Bool(b))
+    }
+    fn visit_u64<E>(self, u: u64) -> Result<Packed, E> {
+        Ok(Packed::Uint(u))
+    }
+    fn visit_i64<E>(self, i: i64) -> Result<Packed, E> {
+        Ok(Packed::Int(i))
+    }
+    fn visit_f64<E>(self, f: f64) -> Result<Packed, E> {
+        Ok(Packed::Float(f))
+    }
+    fn visit_str<E>(self, s: &str) -> Result<Packed, E> {
+        Ok(Packed::Str(s.to_string()))
+    }
+    fn visit_string<E>(self, s: String) -> Result<Packed, E> {
+        Ok(Packed::Str(s))
+    }
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Packed, A::Error> {
+        let mut values = vec![];
+        while let Some(v) = seq.next_element()? {
+            values.push(v);
+        }
+        Ok(Packed::Array(values))
+    }
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Packed, A::Error> {
+        let mut entries = vec![];
+        while let Some(entry) = map.next_entry()? {
+            entries.push(entry);
+        }
+        Ok(Packed::Map(entries))
+    }
+}
+
+impl<'de> Deserialize<'de> for Packed {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(PackedVisitor)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Document {
+    keys: Vec<String>,
+    value: Packed,
+}
+
+/// Encodes `value` as base64 MessagePack, for a state script marked
+/// `data-format="packed"`. Object keys are written once into a table and
+/// referenced by index, so the many objects of a large `RefVec` don't each
+/// repeat their field names.
+pub fn pack(value: &Value) -> String {
+    let mut keys = Keys::default();
+    let value = keys.pack(value);
+    let document = Document {keys: keys.names.into_iter().map(str::to_string).collect(), value};
+    STANDARD.encode(rmp_serde::to_vec(&document).expect("problem encoding state"))
+}
+
+/// Decodes a document written by `pack`.
+pub fn unpack(text: &str) -> Result<Value, String> {
+    let bytes = STANDARD.decode(text.trim()).map_err(|e| format!("state is not base64: {}", e))?;
+    let document: Document = rmp_serde::from_slice(&bytes).map_err(|e| e.to_string())?;
+    unpacked(document.value, &document.keys)
+}
+
+#[derive(Default)]
+struct Keys<'a> {
+    names: Vec<&'a str>,
+    index: HashMap<&'a str, u32>,
+}
+
+impl<'a> Keys<'a> {
+    fn key(&mut self, name: &'a str) -> u32 {
+        *self.index.entry(name).or_insert_with(|| {
+            self.names.push(name);
+            self.names.len() as u32 - 1
+        })
+    }
+    fn pack(&mut self, value: &'a Value) -> Packed {
+        match value {
+            Value::Null => Packed::Null,
+            Value::Bool(b) => Packed::Bool(*b),
+            Value::Number(n) => {
+                if let Some(u) = n.as_u64() {
+                    Packed::Uint(u)
+                } else if let Some(i) = n.as_i64() {
+                    Packed::Int(i)
+                } else {
+                    Packed::Float(n.as_f64().unwrap_or_default())
+                }
+            }
+            Value::String(s) => Packed::Str(s.clone()),
+            Value::Array(values) => Packed::Array(values.iter().map(|v| self.pack(v)).collect()),
+            Value::Object(map) => Packed::Map(map.iter().map(|(key, v)| (self.key(key), self.pack(v))).collect()),
+        }
+    }
+}
+
+fn unpacked(packed: Packed, keys: &[String]) -> Result<Value, String> {
+    Ok(match packed {
+        Packed::Null => Value::Null,
+        Packed::Bool(b) => Value::Bool(b),
+        Packed::Uint(u) => Value::from(u),
+        Packed::Int(i) => Value::from(i),
+        Packed::Float(f) => Number::from_f64(f).map(Value::Number).unwrap_or(Value::Null),
+        Packed::Str(s) => Value::String(s),
+        Packed::Array(values) => Value::Array(values.into_iter().map(|v| unpacked(v, keys)).collect::<Result<_, _>>()?),
+        Packed::Map(entries) => {
+            let mut map = Map::new();
+            for (key, v) in entries {
+                let key = keys.get(key as usize).ok_or("unknown key")?;
+                map.insert(key.clone(), unpacked(v, keys)?);
+            }
+            Value::Object(map)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn round_trip() {
+        let value = json!({"ctx": {"0": {"R": "0"}}, "objs": [null, true, -3, 1.5, "héllo", u64::MAX, i64::MIN], "subs": [[], ["0 1"]]});
+        assert_eq!(unpack(&pack(&value)).unwrap(), value);
+        let mut keys = Keys::default();
+        assert_eq!((keys.key("id"), keys.key("title"), keys.key("id")), (0, 1, 0));
+        assert_eq!(keys.names, ["id", "title"]);
+        assert!(unpack("AQ").is_err());
+        assert!(unpack("not base64!").is_err());
+        let unknown = STANDARD.encode(rmp_serde::to_vec(&Document {keys: vec![], value: Packed::Map(vec![(0, Packed::Null)])}).unwrap());
+        assert_eq!(unpack(&unknown).err().as_deref(), Some("unknown key"));
+    }
+
+    #[test]
+    fn smaller_than_json() {
+        let rows: Vec<Value> = (0..500).map(|n| json!({"id": n, "title": format!("row {}", n), "completed": n % 3 == 0})).collect();
+        let state = json!({"ctx": {"0": {"R": "0"}}, "objs": [rows], "subs": [["0 0"]]});
+        let json = serde_json::to_string(&state).unwrap();
+        let packed = pack(&state);
+        assert_eq!(unpack(&packed).unwrap(), state);
+        assert!(packed.len() * 2 < json.len() + json.len() / 4, "packed {} bytes, json {} bytes", packed.len(), json.len());
+    }
+}
@@ -0,0 +1,367 @@
+use std::collections::HashMap;
+
+use serde::{Serialize, Deserialize};
+use wasm_bindgen::JsCast;
+use web_sys::{Element, HtmlTemplateElement, Node};
+
+use crate::{Rsx, Elem, Attribute, DOCUMENT, check_recall, put_attr, drop_attr, flush_unmounted};
+
+pub type Path = Vec<usize>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PatchNode {
+    Element {name: String, attrs: Vec<(String, String)>, children: Vec<PatchNode>},
+    Text(String),
+    /// Raw markup, inserted as the nodes it parses to. It counts as one child
+    /// in paths, so it should parse to a single node.
+    Html(String),
+}
+
+impl PatchNode {
+    pub fn from_rsx(rsx: &Rsx) -> Vec<Self> {
+        let mut v = vec![];
+        for r in flatten(std::slice::from_ref(rsx)) {
+            match r {
+                Rsx::Element(el) => {
+                    let attrs = el.attrs.iter().map(|a| (a.key.clone(), a.value.clone())).collect();
+                    let mut children = vec![];
+                    for child in &el.children {
+                        children.append(&mut Self::from_rsx(child));
+                    }
+                    v.push(Self::Element {name: el.name.to_string(), attrs, children});
+                }
+                Rsx::Text(txt) => v.push(Self::Text(txt.text.clone())),
+                Rsx::Html(html) => v.push(Self::Html(html.html.clone())),
+                _ => unreachable!(),
+            }
+        }
+        v
+    }
+    fn to_node(&self) -> Node {
+        DOCUMENT.with(|document| {
+            match self {
+                Self::Element {name, attrs, children} => {
+                    let el = document.create_element(name).unwrap();
+                    for (key, value) in attrs {
+                        let attr = Attribute {key: key.clone(), value: value.clone()};
+                        put_attr(&el, &attr);
+                        check_recall(&el, &attr);
+                    }
+                    for child in children {
+                        el.append_child(&child.to_node()).unwrap();
+                    }
+                    el.dyn_into::<Node>().unwrap()
+                }
+                Self::Text(text) => document.create_text_node(text).dyn_into::<Node>().unwrap(),
+                Self::Html(html) => {
+                    let template = document.create_element("template").unwrap().dyn_into::<HtmlTemplateElement>().unwrap();
+                    template.set_inner_html(html);
+                    template.content().dyn_into::<Node>().unwrap()
+                }
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Patch {
+    ReplaceText {path: Path, text: String},
+    SetAttr {path: Path, key: String, value: String},
+    RemoveAttr {path: Path, key: String},
+    InsertChild {path: Path, index: usize, node: PatchNode},
+    RemoveChild {path: Path, index: usize},
+    Move {path: Path, from: usize, to: usize},
+}
+
+/// Computes the operations turning `old` into `new`. Paths index the children
+/// of the container both trees are rendered into, so the roots sit at `[i]`.
+pub fn diff_rsx(old: &Rsx, new: &Rsx) -> Vec<Patch> {
+    let mut patches = vec![];
+    diff_children(std::slice::from_ref(old), std::slice::from_ref(new), &vec![], &mut patches);
+    patches
+}
+
+fn flatten(rsx: &[Rsx]) -> Vec<&Rsx> {
+    let mut v = vec![];
+    for r in rsx {
+        match r {
+            Rsx::Component(comp) => v.append(&mut flatten(&comp.children)),
+            Rsx::Keyed(keyed) => v.append(&mut flatten(&keyed.children)),
+            Rsx::Fragment(fragment) => v.append(&mut flatten(&fragment.children)),
+            _ => v.push(r),
+        }
+    }
+    v
+}
+
+fn single_keyed(rsx: &[Rsx]) -> Option<Vec<&Elem>> {
+    let flat = flatten_components(rsx);
+    if let [Rsx::Keyed(keyed)] = flat.as_slice() {
+        let mut v = vec![];
+        for child in &keyed.children {
+            if let Rsx::Element(el) = child {
+                if el.attrs.is_empty() {
+                    return None;
+                }
+                v.push(el);
+            } else {
+                return None;
+            }
+        }
+        Some(v)
+    } else {
+        None
+    }
+}
+
+fn flatten_components(rsx: &[Rsx]) -> Vec<&Rsx> {
+    let mut v = vec![];
+    for r in rsx {
+        match r {
+            Rsx::Component(comp) => v.append(&mut flatten_components(&comp.children)),
+            Rsx::Fragment(fragment) => v.append(&mut flatten_components(&fragment.children)),
+            _ => v.push(r),
+        }
+    }
+    v
+}
+
+fn child_path(path: &Path, n: usize) -> Path {
+    let mut p = path.clone();
+    p.push(n);
+    p
+}
+
+fn diff_children(old: &[Rsx], new: &[Rsx], path: &Path, patches: &mut Vec<Patch>) {
+    if let (Some(old), Some(new)) = (single_keyed(old), single_keyed(new)) {
+        diff_keyed(old, new, path, patches);
+        return;
+    }
+    let old = flatten(old);
+    let new = flatten(new);
+    let shared = old.len().min(new.len());
+    for n in 0..shared {
+        diff_node(old[n], new[n], path, n, patches);
+    }
+    for (n, child) in new.iter().enumerate().skip(shared) {
+        for node in PatchNode::from_rsx(child) {
+            patches.push(Patch::InsertChild {path: path.clone(), index: n, node});
+        }
+    }
+    for n in (shared..old.len()).rev() {
+        patches.push(Patch::RemoveChild {path: path.clone(), index: n});
+    }
+}
+
+fn diff_keyed(old: Vec<&Elem>, new: Vec<&Elem>, path: &Path, patches: &mut Vec<Patch>) {
+    let new_keys: HashMap<&str, usize> = new.iter().enumerate().map(|(n, el)| (el.attrs[0].value.as_str(), n)).collect();
+    let mut current = vec![];
+    for (n, el) in old.iter().enumerate().rev() {
+        if new_keys.contains_key(el.attrs[0].value.as_str()) {
+            current.push(*el);
+        } else {
+            patches.push(Patch::RemoveChild {path: path.clone(), index: n});
+        }
+    }
+    current.reverse();
+    for (n, el) in new.iter().enumerate() {
+        let key = &el.attrs[0].value;
+        if let Some(m) = current.iter().position(|c| &c.attrs[0].value == key) {
+            if m != n {
+                let moved = current.remove(m);
+                current.insert(n, moved);
+                patches.push(Patch::Move {path: path.clone(), from: m, to: n});
+            }
+            diff_elem(current[n], el, &child_path(path, n), patches);
+        } else {
+            current.insert(n, el);
+            for node in PatchNode::from_rsx(&Rsx::Element((*el).clone())) {
+                patches.push(Patch::InsertChild {path: path.clone(), index: n, node});
+            }
+        }
+    }
+}
+
+fn diff_node(old: &Rsx, new: &Rsx, path: &Path, n: usize, patches: &mut Vec<Patch>) {
+    match (old, new) {
+        (Rsx::Text(t1), Rsx::Text(t2)) => {
+            if t1.text != t2.text {
+                patches.push(Patch::ReplaceText {path: child_path(path, n), text: t2.text.clone()});
+            }
+        }
+        (Rsx::Element(e1), Rsx::Element(e2)) if e1.name == e2.name => {
+            diff_elem(e1, e2, &child_path(path, n), patches);
+        }
+        (Rsx::Html(h1), Rsx::Html(h2)) if h1.html == h2.html => {}
+        _ => {
+            patches.push(Patch::RemoveChild {path: path.clone(), index: n});
+            for node in PatchNode::from_rsx(new) {
+                patches.push(Patch::InsertChild {path: path.clone(), index: n, node});
+            }
+        }
+    }
+}
+
+fn diff_elem(old: &Elem, new: &Elem, path: &Path, patches: &mut Vec<Patch>) {
+    for attr in &new.attrs {
+        if !old.attrs.iter().any(|a| a.key == attr.key && a.value == attr.value) {
+            patches.push(Patch::SetAttr {path: path.clone(), key: attr.key.clone(), value: attr.value.clone()});
+        }
+    }
+    for attr in &old.attrs {
+        if !new.attrs.iter().any(|a| a.key == attr.key) {
+            patches.push(Patch::RemoveAttr {path: path.clone(), key: attr.key.clone()});
+        }
+    }
+    diff_children(&old.children, &new.children, path, patches);
+}
+
+fn resolve(root: &Node, path: &[usize]) -> Node {
+    let mut node = root.clone();
+    for n in path {
+        node = node.child_nodes().get(*n as u32).expect("patch path out of range");
+    }
+    node
+}
+
+/// Executes `patches` against `node`, the container `diff_rsx`'s paths are relative to.
+pub fn apply_patches(node: &Node, patches: &[Patch]) {
+    for patch in patches {
+        match patch {
+            Patch::ReplaceText {path, text} => {
+                resolve(node, path).set_node_value(Some(text));
+            }
+            Patch::SetAttr {path, key, value} => {
+                let el = resolve(node, path).dyn_into::<Element>().unwrap();
+                let attr = Attribute {key: key.clone(), value: value.clone()};
+                put_attr(&el, &attr);
+                check_recall(&el, &attr);
+            }
+            Patch::RemoveAttr {path, key} => {
+                let el = resolve(node, path).dyn_into::<Element>().unwrap();
+                drop_attr(&el, key);
+            }
+            Patch::InsertChild {path, index, node: child} => {
+                let parent = resolve(node, path);
+                let next = parent.child_nodes().get(*index as u32);
+                parent.insert_before(&child.to_node(), next.as_ref()).unwrap();
+            }
+            Patch::RemoveChild {path, index} => {
+                let parent = resolve(node, path);
+                let child = parent.child_nodes().get(*index as u32).expect("patch index out of range");
+                crate::RECALLS.with(|r| crate::remove_recall(&mut r.borrow_mut(), &parent, &child));
+            }
+            Patch::Move {path, from, to} => {
+                let parent = resolve(node, path);
+                let children = parent.child_nodes();
+                let child = children.get(*from as u32).expect("patch index out of range");
+                parent.remove_child(&child).unwrap();
+                let next = parent.child_nodes().get(*to as u32);
+                parent.insert_before(&child, next.as_ref()).unwrap();
+            }
+        }
+    }
+    flush_unmounted();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{attributes, element};
+
+    fn text(s: &str) -> Rsx {
+        Rsx::new_text(s.to_string())
+    }
+
+    fn li(key: &str) -> Rsx {
+        element!("LI", attributes![("key".to_string(), key.to_string())], vec![text(key)])
+    }
+
+    #[test]
+    fn text_and_attrs() {
+        let old = element!("DIV", attributes![("class".to_string(), "a".to_string()), ("id".to_string(), "x".to_string())], vec![text("hi")]);
+        let new = element!("DIV", attributes![("class".to_string(), "b".to_string())], vec![text("bye")]);
+        assert_eq!(diff_rsx(&old, &new), vec![
+            Patch::SetAttr {path: vec![0], key: "class".to_string(), value: "b".to_string()},
+            Patch::RemoveAttr {path: vec![0], key: "id".to_string()},
+            Patch::ReplaceText {path: vec![0, 0], text: "bye".to_string()},
+        ]);
+    }
+
+    #[test]
+    fn insert_and_remove() {
+        let old = element!("UL", vec![], vec![text("a"), text("b"), text("c")]);
+        let new = element!("UL", vec![], vec![text("a")]);
+        assert_eq!(diff_rsx(&old, &new), vec![
+            Patch::RemoveChild {path: vec![0], index: 2},
+            Patch::RemoveChild {path: vec![0], index: 1},
+        ]);
+        assert_eq!(diff_rsx(&new, &old), vec![
+            Patch::InsertChild {path: vec![0], index: 1, node: PatchNode::Text("b".to_string())},
+            Patch::InsertChild {path: vec![0], index: 2, node: PatchNode::Text("c".to_string())},
+        ]);
+    }
+
+    #[test]
+    fn replace_tag() {
+        let old = Rsx::Component(crate::Comp {children: vec![element!("P", vec![], vec![])]});
+        let new = Rsx::Component(crate::Comp {children: vec![text("p")]});
+        assert_eq!(diff_rsx(&old, &new), vec![
+            Patch::RemoveChild {path: vec![], index: 0},
+            Patch::InsertChild {path: vec![], index: 0, node: PatchNode::Text("p".to_string())},
+        ]);
+    }
+
+    #[test]
+    fn raw_html() {
+        let old = element!("DIV", vec![], vec![Rsx::new_html("<b>a</b>"), text("x")]);
+        assert!(diff_rsx(&old, &old.clone()).is_empty());
+        let new = element!("DIV", vec![], vec![Rsx::new_html("<i>b</i>"), text("y")]);
+        assert_eq!(diff_rsx(&old, &new), vec![
+            Patch::RemoveChild {path: vec![0], index: 0},
+            Patch::InsertChild {path: vec![0], index: 0, node: PatchNode::Html("<i>b</i>".to_string())},
+            Patch::ReplaceText {path: vec![0, 1], text: "y".to_string()},
+        ]);
+        let grown = element!("DIV", vec![], vec![Rsx::new_html("<b>a</b>"), text("x"), Rsx::new_html("<hr>")]);
+        assert_eq!(diff_rsx(&old, &grown), vec![Patch::InsertChild {path: vec![0], index: 2, node: PatchNode::Html("<hr>".to_string())}]);
+        assert_eq!(diff_rsx(&text("x"), &Rsx::new_html("<b>a</b>"))[1], Patch::InsertChild {path: vec![], index: 0, node: PatchNode::Html("<b>a</b>".to_string())});
+    }
+
+    #[test]
+    fn fragment_children() {
+        let old = element!("DIV", vec![], vec![text("a"), Rsx::new_fragment(vec![text("b"), li("c")])]);
+        let new = element!("DIV", vec![], vec![text("a"), Rsx::new_fragment(vec![text("b2"), li("c"), text("d")])]);
+        assert_eq!(diff_rsx(&old, &new), vec![
+            Patch::ReplaceText {path: vec![0, 1], text: "b2".to_string()},
+            Patch::InsertChild {path: vec![0], index: 3, node: PatchNode::Text("d".to_string())},
+        ]);
+        assert_eq!(PatchNode::from_rsx(&old), vec![PatchNode::Element {name: "DIV".to_string(), attrs: vec![], children: vec![
+            PatchNode::Text("a".to_string()),
+            PatchNode::Text("b".to_string()),
+            PatchNode::Element {name: "LI".to_string(), attrs: vec![("key".to_string(), "c".to_string())], children: vec![PatchNode::Text("c".to_string())]},
+        ]}]);
+        let keyed = element!("UL", vec![], vec![Rsx::new_fragment(vec![Rsx::new_keyed(vec![li("a"), li("b")])])]);
+        let swapped = element!("UL", vec![], vec![Rsx::new_fragment(vec![Rsx::new_keyed(vec![li("b"), li("a")])])]);
+        assert_eq!(diff_rsx(&keyed, &swapped), vec![Patch::Move {path: vec![0], from: 1, to: 0}]);
+    }
+
+    #[test]
+    fn keyed_moves() {
+        let old = element!("UL", vec![], vec![Rsx::new_keyed(vec![li("a"), li("b"), li("c")])]);
+        let new = element!("UL", vec![], vec![Rsx::new_keyed(vec![li("c"), li("a")])]);
+        assert_eq!(diff_rsx(&old, &new), vec![
+            Patch::RemoveChild {path: vec![0], index: 1},
+            Patch::Move {path: vec![0], from: 1, to: 0},
+        ]);
+    }
+
+    #[test]
+    fn meta_ignored() {
+        let old = li("a").with_meta("src/list.rs:12");
+        let copy = old.clone();
+        assert_eq!(copy.as_elem().meta.as_deref(), Some("src/list.rs:12"));
+        assert!(diff_rsx(&old, &li("a")).is_empty());
+        assert!(diff_rsx(&li("a"), &copy).is_empty());
+        assert_eq!(PatchNode::from_rsx(&copy), PatchNode::from_rsx(&li("a")));
+    }
+}
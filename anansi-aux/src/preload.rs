@@ -0,0 +1,106 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+
+use serde::Serialize;
+use wasm_bindgen::JsCast;
+use web_sys::Node;
+
+use crate::{DOCUMENT, Rsx, Elem};
+
+const MARKER: &str = "data-anansi-preload";
+
+thread_local! {
+    static PRELOADED: RefCell<HashMap<String, Rsx>> = RefCell::new(HashMap::new());
+    static PRELOADING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Whether the current render is a preload. Components can check it to skip
+/// side effects like requests or analytics that should only run once mounted.
+pub fn is_preloading() -> bool {
+    PRELOADING.with(|p| p.get())
+}
+
+fn render_preloading(render: impl FnOnce() -> Rsx) -> Rsx {
+    let outer = PRELOADING.with(|p| p.replace(true));
+    let rsx = render();
+    PRELOADING.with(|p| p.set(outer));
+    rsx
+}
+
+/// Renders a component into a detached container ahead of time, e.g. when the
+/// link to its route is hovered. The next time the component `name` is mounted
+/// in place of its cached `COMP_RSX` entry, the prebuilt tree and its nodes are
+/// used instead of rendering it again, as long as it's mounted with the same
+/// props. `key` comes from `preload_key`; the `preload!` macro passes both.
+pub fn preload_component(key: String, render: impl FnOnce() -> Rsx) {
+    let mut rsx = render_preloading(render);
+    DOCUMENT.with(|document| {
+        let container = document.create_element("div").unwrap();
+        container.set_attribute(MARKER, "").unwrap();
+        if let Rsx::Component(comp) = &mut rsx {
+            for child in &mut comp.children {
+                child.attach_to_element(&container, document);
+            }
+        }
+    });
+    PRELOADED.with(|p| p.borrow_mut().insert(key, rsx));
+}
+
+/// Identifies a preload of the component `name` rendered with `props`.
+pub fn preload_key(name: &str, props: &impl Serialize) -> String {
+    format!("{}:{}", name, serde_json::to_string(props).expect("problem serializing props"))
+}
+
+/// The tree preloaded for `name` with the same `props`, which is given out once.
+pub fn take_preloaded(name: &str, props: &impl Serialize) -> Option<Rsx> {
+    PRELOADED.with(|p| {
+        let mut preloaded = p.borrow_mut();
+        if preloaded.is_empty() {
+            return None;
+        }
+        preloaded.remove(&preload_key(name, props))
+    })
+}
+
+/// The prebuilt node of `elem` when it still sits in a preload container.
+pub(crate) fn adopt(elem: &Elem) -> Option<Node> {
+    let el = elem.el.as_ref()?;
+    if el.parent_element()?.has_attribute(MARKER) {
+        Some(el.clone().dyn_into::<Node>().unwrap())
+    } else {
+        None
+    }
+}
+
+#[macro_export]
+macro_rules! preload {
+    ($comp:ident, $props:expr) => {
+        {
+            let props = $props;
+            let key = $crate::preload::preload_key(stringify!($comp), &props);
+            $crate::preload::preload_component(key, move || $comp::restart(props))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Comp;
+
+    #[test]
+    fn preload_then_mount() {
+        let rsx = render_preloading(|| {
+            assert!(is_preloading());
+            Rsx::Component(Comp {children: vec![Rsx::new_text("profile".to_string())]})
+        });
+        assert!(!is_preloading());
+        PRELOADED.with(|p| p.borrow_mut().insert(preload_key("Profile", &[7]), rsx));
+        assert!(take_preloaded("Profile", &[8]).is_none());
+        match take_preloaded("Profile", &[7]) {
+            Some(Rsx::Component(comp)) => assert_eq!(comp.children.len(), 1),
+            _ => panic!("expected the preloaded tree"),
+        }
+        assert!(take_preloaded("Profile", &[7]).is_none());
+    }
+}
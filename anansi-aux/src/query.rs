@@ -0,0 +1,100 @@
+use wasm_bindgen::JsValue;
+
+use crate::{WINDOW, Signal};
+
+fn decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok()).and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'+', _) => out.push(b' '),
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 2;
+            }
+            (b, _) => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => out.push(b as char),
+            b' ' => out.push('+'),
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+/// Splits a query string, with or without its leading `?`, into decoded pairs.
+pub fn parse_query(search: &str) -> Vec<(String, String)> {
+    let search = search.strip_prefix('?').unwrap_or(search);
+    search.split('&').filter(|p| !p.is_empty()).map(|p| {
+        let (k, v) = p.split_once('=').unwrap_or((p, ""));
+        (decode(k), decode(v))
+    }).collect()
+}
+
+/// Replaces every value of `name` with `value`, or removes the key when `value` is `None`.
+pub fn with_param(search: &str, name: &str, value: Option<&str>) -> String {
+    let mut pairs = parse_query(search);
+    let at = pairs.iter().position(|(k, _)| k == name);
+    pairs.retain(|(k, _)| k != name);
+    if let Some(value) = value {
+        pairs.insert(at.unwrap_or(pairs.len()), (name.to_string(), value.to_string()));
+    }
+    if pairs.is_empty() {
+        return String::new();
+    }
+    let query: Vec<String> = pairs.iter().map(|(k, v)| format!("{}={}", encode(k), encode(v))).collect();
+    format!("?{}", query.join("&"))
+}
+
+fn search() -> String {
+    WINDOW.with(|w| w.location().search().unwrap())
+}
+
+/// All values of `name`, in order, for keys repeated in the query string.
+pub fn query_params(name: &str) -> Vec<String> {
+    parse_query(&search()).into_iter().filter(|(k, _)| k == name).map(|(_, v)| v).collect()
+}
+
+/// The first value of `name` in `window.location.search`.
+pub fn use_query_param(name: &str) -> Signal<Option<String>> {
+    Signal::new(query_params(name).into_iter().next())
+}
+
+/// Sets `param` and writes it to the URL with `history.replaceState`, without navigating.
+pub fn set_query_param(name: &str, param: &mut Signal<Option<String>>, value: Option<String>) {
+    let search = with_param(&search(), name, value.as_deref());
+    WINDOW.with(|w| {
+        let location = w.location();
+        let url = format!("{}{}{}", location.pathname().unwrap(), search, location.hash().unwrap());
+        w.history().unwrap().replace_state_with_url(&JsValue::NULL, "", Some(&url)).unwrap();
+    });
+    *param.value_mut() = value;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_and_update() {
+        let search = "?page=2&tag=a%20b&tag=c+d";
+        let pairs = parse_query(search);
+        assert_eq!(pairs[0], ("page".to_string(), "2".to_string()));
+        assert_eq!(pairs[1].1, "a b");
+        assert_eq!(pairs[2].1, "c d");
+        assert_eq!(with_param(search, "page", Some("3")), "?page=3&tag=a+b&tag=c+d");
+        assert_eq!(with_param(search, "tag", Some("x&y")), "?page=2&tag=x%26y");
+        assert_eq!(with_param("?page=2", "page", None), "");
+    }
+}
@@ -0,0 +1,167 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use serde::{Serialize, Serializer, ser::SerializeSeq, Deserialize, Deserializer};
+
+use crate::GetOne;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RenameError {
+    Missing,
+    Exists,
+}
+
+impl fmt::Display for RenameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Missing => write!(f, "no entry to rename"),
+            Self::Exists => write!(f, "an entry already has the new key"),
+        }
+    }
+}
+
+impl std::error::Error for RenameError {}
+
+#[derive(Debug)]
+struct Entry<K, V> {
+    id: u64,
+    key: K,
+    value: Rc<RefCell<V>>,
+}
+
+/// An insertion-ordered map of shared cells. Every entry has an id that stays
+/// the same when its key is renamed, so rendering entries with `key={id}`
+/// keeps their nodes across a rename instead of rebuilding them.
+#[derive(Debug)]
+pub struct RefMap<K, V> {
+    entries: Vec<Entry<K, V>>,
+    next_id: u64,
+}
+
+impl<K, V> Default for RefMap<K, V> {
+    fn default() -> Self {
+        Self {entries: vec![], next_id: 0}
+    }
+}
+
+impl<K: PartialEq, V> RefMap<K, V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    fn position(&self, key: &K) -> Option<usize> {
+        self.entries.iter().position(|e| e.key == *key)
+    }
+    /// Sets the value of `key`, writing into its existing cell if there is one.
+    pub fn insert(&mut self, key: K, value: V) -> Rc<RefCell<V>> {
+        if let Some(n) = self.position(&key) {
+            let cell = self.entries[n].value.clone();
+            *cell.borrow_mut() = value;
+            return cell;
+        }
+        let value = Rc::new(RefCell::new(value));
+        self.entries.push(Entry {id: self.next_id, key, value: value.clone()});
+        self.next_id += 1;
+        value
+    }
+    pub fn get(&self, key: &K) -> Option<&Rc<RefCell<V>>> {
+        self.position(key).map(|n| &self.entries[n].value)
+    }
+    /// The id the entry of `key` renders with.
+    pub fn id(&self, key: &K) -> Option<u64> {
+        self.position(key).map(|n| self.entries[n].id)
+    }
+    pub fn remove(&mut self, key: &K) -> Option<Rc<RefCell<V>>> {
+        self.position(key).map(|n| self.entries.remove(n).value)
+    }
+    /// Changes the key of an entry in place, keeping its cell, id and position.
+    /// Renaming onto a key that is already taken leaves the map unchanged.
+    pub fn rename(&mut self, from: &K, to: K) -> Result<(), RenameError> {
+        if *from == to {
+            return self.position(from).map(|_| ()).ok_or(RenameError::Missing);
+        }
+        if self.position(&to).is_some() {
+            return Err(RenameError::Exists);
+        }
+        let n = self.position(from).ok_or(RenameError::Missing)?;
+        self.entries[n].key = to;
+        Ok(())
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (u64, &K, &Rc<RefCell<V>>)> {
+        self.entries.iter().map(|e| (e.id, &e.key, &e.value))
+    }
+}
+
+impl<K: 'static, V: 'static> GetOne for RefMap<K, V> {
+    fn get_one(&self, n: usize) -> Rc<dyn Any> {
+        self.entries[n].value.clone() as Rc<dyn Any>
+    }
+}
+
+impl<K: Serialize, V: Serialize> Serialize for RefMap<K, V> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut seq = serializer.serialize_seq(Some(self.entries.len()))?;
+        for e in &self.entries {
+            seq.serialize_element(&(&e.key, &*e.value.borrow()))?;
+        }
+        seq.end()
+    }
+}
+
+impl<'de, K: Deserialize<'de> + PartialEq, V: Deserialize<'de>> Deserialize<'de> for RefMap<K, V> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let mut map = Self::new();
+        for (key, value) in Vec::<(K, V)>::deserialize(deserializer)? {
+            map.insert(key, value);
+        }
+        Ok(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{keyed_plan, KeyedOp};
+
+    #[test]
+    fn rename_keeps_cell() {
+        let mut headers = RefMap::new();
+        headers.insert("Accept".to_string(), "text/html".to_string());
+        let cell = headers.insert("X-Tokn".to_string(), "abc".to_string());
+        let ids = |m: &RefMap<String, String>| m.iter().map(|(id, _, _)| id.to_string()).collect::<Vec<_>>();
+        let before = ids(&headers);
+        headers.rename(&"X-Tokn".to_string(), "X-Token".to_string()).unwrap();
+        assert!(Rc::ptr_eq(&cell, headers.get(&"X-Token".to_string()).unwrap()));
+        assert!(headers.get(&"X-Tokn".to_string()).is_none());
+        let (removed, ops) = keyed_plan(&before.iter().cloned().map(Some).collect::<Vec<_>>(), &ids(&headers));
+        assert!(removed.is_empty());
+        assert_eq!(ops, vec![KeyedOp::Keep(0), KeyedOp::Keep(1)]);
+        assert_eq!(headers.rename(&"X-Token".to_string(), "Accept".to_string()), Err(RenameError::Exists));
+        assert_eq!(headers.rename(&"Gone".to_string(), "Other".to_string()), Err(RenameError::Missing));
+        assert_eq!(*cell.borrow(), "abc");
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut map = RefMap::new();
+        map.insert("a".to_string(), 1);
+        map.insert("b".to_string(), 2);
+        let json = serde_json::to_string(&map).unwrap();
+        assert_eq!(json, r#"[["a",1],["b",2]]"#);
+        let map: RefMap<String, i32> = serde_json::from_str(&json).unwrap();
+        assert_eq!(*map.get(&"b".to_string()).unwrap().borrow(), 2);
+    }
+}
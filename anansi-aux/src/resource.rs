@@ -0,0 +1,237 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use wasm_bindgen::JsValue;
+use wasm_bindgen::closure::Closure;
+use web_sys::{AbortController, AbortSignal};
+
+use crate::{NODE_ID, Resource, Signal, call, component_index, on_unmount};
+
+type Key = (String, String);
+
+#[derive(Default)]
+struct Slot {
+    generation: u64,
+    source: Option<String>,
+    settled: Option<Result<String, FetchError>>,
+    controller: Option<AbortController>,
+    watched: bool,
+}
+
+thread_local! {
+    static SLOTS: RefCell<HashMap<Key, Slot>> = RefCell::new(HashMap::new());
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FetchError {
+    /// The fetch was aborted, which the UI usually ignores.
+    Aborted,
+    /// The promise was rejected.
+    Failed(String),
+    /// The value it resolved to was not the expected type.
+    Decode(String),
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Aborted => write!(f, "fetch aborted"),
+            Self::Failed(e) => write!(f, "fetch failed: {}", e),
+            Self::Decode(e) => write!(f, "could not decode result: {}", e),
+        }
+    }
+}
+
+impl Error for FetchError {}
+
+/// Whether `resource` was rejected because its fetch was aborted.
+pub fn is_aborted<D>(resource: &Resource<D>) -> bool {
+    matches!(resource, Resource::Rejected(e) if matches!(e.downcast_ref::<FetchError>(), Some(FetchError::Aborted)))
+}
+
+fn abort_error(e: &JsValue) -> bool {
+    js_sys::Reflect::get(e, &JsValue::from_str("name")).ok().and_then(|n| n.as_string()).as_deref() == Some("AbortError")
+}
+
+/// Starts a new fetch for `key`, aborting and superseding any still in flight.
+fn begin(key: &Key, controller: Option<AbortController>) -> u64 {
+    SLOTS.with(|s| {
+        let mut slots = s.borrow_mut();
+        let slot = slots.entry(key.clone()).or_default();
+        if let Some(previous) = std::mem::replace(&mut slot.controller, controller) {
+            previous.abort();
+        }
+        slot.generation += 1;
+        slot.settled = None;
+        slot.generation
+    })
+}
+
+/// Aborts the fetch in flight for `key` and drops whatever it settles with.
+/// The generation is kept so a late result can't land in a remounted slot,
+/// which starts out unwatched and fetches its source afresh.
+fn cancel(key: &Key) {
+    SLOTS.with(|s| {
+        if let Some(slot) = s.borrow_mut().get_mut(key) {
+            if let Some(controller) = slot.controller.take() {
+                controller.abort();
+            }
+            slot.generation += 1;
+            slot.watched = false;
+            slot.source = None;
+            slot.settled = None;
+        }
+    });
+}
+
+/// Whether `key` still needs its unmount cleanup registered.
+fn watch(key: &Key) -> bool {
+    SLOTS.with(|s| !std::mem::replace(&mut s.borrow_mut().entry(key.clone()).or_default().watched, true))
+}
+
+/// Records the outcome of fetch `generation`, unless a newer one has started.
+fn store(key: &Key, generation: u64, result: Result<String, FetchError>) -> bool {
+    SLOTS.with(|s| match s.borrow_mut().get_mut(key) {
+        Some(slot) if slot.generation == generation => {
+            slot.settled = Some(result);
+            true
+        }
+        _ => false,
+    })
+}
+
+/// Whether `source` differs from the one the last fetch was started for.
+fn changed(key: &Key, source: String) -> bool {
+    SLOTS.with(|s| {
+        let mut slots = s.borrow_mut();
+        let slot = slots.entry(key.clone()).or_default();
+        if slot.source.as_ref() == Some(&source) {
+            false
+        } else {
+            slot.source = Some(source);
+            true
+        }
+    })
+}
+
+fn decode<D: DeserializeOwned>(settled: Result<String, FetchError>) -> Resource<D> {
+    match settled {
+        Ok(json) => match serde_json::from_str(&json) {
+            Ok(d) => Resource::Resolved(d),
+            Err(e) => Resource::Rejected(Box::new(FetchError::Decode(e.to_string()))),
+        },
+        Err(e) => Resource::Rejected(Box::new(e)),
+    }
+}
+
+/// Runs an async fetch whenever a source signal changes and feeds the result
+/// into a `Resource`. The resource turns `Pending` when a fetch starts, and
+/// `callback` is run once it settles so the component can `read` the outcome
+/// and rerender. Starting a fetch aborts the one before it, as does
+/// unmounting, and anything a superseded fetch settles with is discarded.
+pub struct Fetcher {
+    key: Key,
+    callback: &'static str,
+}
+
+impl Fetcher {
+    /// `callback` names the component callback run, e.g. `"user_loaded"`.
+    pub fn new(name: &str, callback: &'static str) -> Self {
+        Self {key: (component_index().unwrap_or_default(), name.to_string()), callback}
+    }
+    /// Fetches with `f` when `source` differs from the last fetch's source.
+    /// `f` should pass the `AbortSignal` on, e.g. to `RequestInit::set_signal`.
+    pub fn track<S: Serialize, D>(&self, source: &mut Signal<S>, f: impl FnOnce(&S, &AbortSignal) -> js_sys::Promise, resource: &mut Resource<D>) {
+        let json = serde_json::to_string(source.value()).expect("problem serializing source");
+        if changed(&self.key, json) {
+            self.refetch(source, f, resource);
+        }
+    }
+    /// Fetches with `f` again even though `source` hasn't changed.
+    pub fn refetch<S, D>(&self, source: &mut Signal<S>, f: impl FnOnce(&S, &AbortSignal) -> js_sys::Promise, resource: &mut Resource<D>) {
+        if watch(&self.key) {
+            let key = self.key.clone();
+            on_unmount(move || cancel(&key));
+        }
+        let controller = AbortController::new().expect("problem creating abort controller");
+        let promise = f(source.value(), &controller.signal());
+        let generation = begin(&self.key, Some(controller));
+        *resource = Resource::Pending;
+        let node_id = NODE_ID.with(|n| n.borrow().clone());
+        let (key, callback) = (self.key.clone(), self.callback);
+        let settle = move |result: Result<String, FetchError>| {
+            if store(&key, generation, result) {
+                let _ = call(&format!("{}[]", callback), &node_id);
+            }
+        };
+        let settle2 = settle.clone();
+        let ok = Closure::once(move |v: JsValue| {
+            let json = js_sys::JSON::stringify(&v).ok().and_then(|j| j.as_string()).unwrap_or_else(|| "null".to_string());
+            settle(Ok(json));
+        });
+        let err = Closure::once(move |e: JsValue| {
+            let error = if abort_error(&e) {
+                FetchError::Aborted
+            } else {
+                FetchError::Failed(e.as_string().unwrap_or_else(|| format!("{:?}", e)))
+            };
+            settle2(Err(error));
+        });
+        let _ = promise.then2(&ok, &err);
+        ok.forget();
+        err.forget();
+    }
+    /// Replaces `resource` with the outcome of the latest fetch once it has
+    /// settled, e.g. at the start of `callback`.
+    pub fn read<D: DeserializeOwned>(&self, resource: &mut Resource<D>) {
+        if let Some(settled) = SLOTS.with(|s| s.borrow_mut().get_mut(&self.key).and_then(|slot| slot.settled.take())) {
+            *resource = decode(settled);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale_result_dropped() {
+        let fetcher = Fetcher {key: ("0".to_string(), "user".to_string()), callback: "user_loaded"};
+        assert!(changed(&fetcher.key, "1".to_string()));
+        assert!(!changed(&fetcher.key, "1".to_string()));
+        let first = begin(&fetcher.key, None);
+        assert!(changed(&fetcher.key, "2".to_string()));
+        let second = begin(&fetcher.key, None);
+        assert!(store(&fetcher.key, second, Ok(r#""bea""#.to_string())));
+        assert!(!store(&fetcher.key, first, Ok(r#""ann""#.to_string())));
+        let mut user: Resource<String> = Resource::Pending;
+        fetcher.read(&mut user);
+        assert!(matches!(user, Resource::Resolved(ref u) if u == "bea"));
+        let third = begin(&fetcher.key, None);
+        store(&fetcher.key, third, Err(FetchError::Failed("offline".to_string())));
+        fetcher.read(&mut user);
+        assert!(matches!(user, Resource::Rejected(ref e) if e.to_string() == "fetch failed: offline"));
+        assert!(!is_aborted(&user));
+    }
+
+    #[test]
+    fn aborted_on_unmount() {
+        let key = ("1".to_string(), "feed".to_string());
+        assert!(watch(&key));
+        assert!(!watch(&key));
+        let generation = begin(&key, None);
+        cancel(&key);
+        assert!(!store(&key, generation, Ok("[1]".to_string())));
+        assert!(watch(&key));
+        assert!(changed(&key, "1".to_string()));
+        let generation = begin(&key, None);
+        assert!(store(&key, generation, Err(FetchError::Aborted)));
+        let mut feed: Resource<Vec<u32>> = Resource::Pending;
+        Fetcher {key, callback: "feed_loaded"}.read(&mut feed);
+        assert!(is_aborted(&feed));
+    }
+}
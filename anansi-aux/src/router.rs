@@ -0,0 +1,234 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen::closure::Closure;
+use web_sys::{Element, Event, MouseEvent};
+
+use crate::{DOCUMENT, NODE_ID, WINDOW, Rsx, Elem, Attribute, attributes, element, call, on_unmount};
+use crate::custom::{prevent_default, with_event};
+
+thread_local! {
+    static LINKS: Cell<bool> = const { Cell::new(false) };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NotFound;
+
+pub type View<R> = fn(&Params) -> Result<R, NotFound>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Static(String),
+    Param(String),
+    Optional(String),
+    Wildcard(String),
+}
+
+fn parse(path: &str) -> Vec<Segment> {
+    path.split('/').filter(|s| !s.is_empty()).map(|s| {
+        if let Some(name) = s.strip_prefix('*') {
+            Segment::Wildcard(name.to_string())
+        } else if let Some(name) = s.strip_prefix(':') {
+            match name.strip_suffix('?') {
+                Some(name) => Segment::Optional(name.to_string()),
+                None => Segment::Param(name.to_string()),
+            }
+        } else {
+            Segment::Static(s.to_string())
+        }
+    }).collect()
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Params(HashMap<String, String>);
+
+impl Params {
+    pub fn raw(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(|s| s.as_str())
+    }
+    /// Parses the param `name`, failing with `NotFound` when it is missing or malformed.
+    pub fn get<T: FromStr>(&self, name: &str) -> Result<T, NotFound> {
+        self.raw(name).ok_or(NotFound)?.parse().map_err(|_| NotFound)
+    }
+    /// Like `get` but a missing optional param is `Ok(None)`.
+    pub fn get_opt<T: FromStr>(&self, name: &str) -> Result<Option<T>, NotFound> {
+        match self.raw(name) {
+            Some(s) => s.parse().map(Some).map_err(|_| NotFound),
+            None => Ok(None),
+        }
+    }
+}
+
+fn matches(segments: &[Segment], path: &str) -> Option<Params> {
+    let parts: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    let mut params = HashMap::new();
+    let mut n = 0;
+    for segment in segments {
+        match segment {
+            Segment::Static(s) => {
+                if parts.get(n) != Some(&s.as_str()) {
+                    return None;
+                }
+                n += 1;
+            }
+            Segment::Param(name) => {
+                params.insert(name.clone(), parts.get(n)?.to_string());
+                n += 1;
+            }
+            Segment::Optional(name) => {
+                if let Some(part) = parts.get(n) {
+                    params.insert(name.clone(), part.to_string());
+                    n += 1;
+                }
+            }
+            Segment::Wildcard(name) => {
+                params.insert(name.clone(), parts[n.min(parts.len())..].join("/"));
+                n = parts.len();
+            }
+        }
+    }
+    if n == parts.len() {
+        Some(Params(params))
+    } else {
+        None
+    }
+}
+
+pub struct Router<R> {
+    routes: Vec<(Vec<Segment>, View<R>)>,
+    fallback: fn() -> R,
+}
+
+impl<R> Router<R> {
+    pub fn new(fallback: fn() -> R) -> Self {
+        Self {routes: vec![], fallback}
+    }
+    /// Adds a route such as `/user/:id`, `/posts/:page?` or `/files/*path`.
+    pub fn route(mut self, path: &str, view: View<R>) -> Self {
+        self.routes.push((parse(path), view));
+        self
+    }
+    /// Renders the first route matching `path`, or the fallback when none
+    /// matches or the view could not extract its params.
+    pub fn resolve(&self, path: &str) -> R {
+        let path = path.split(['?', '#']).next().unwrap_or_default();
+        for (segments, view) in &self.routes {
+            if let Some(params) = matches(segments, path) {
+                return view(&params).unwrap_or_else(|_| (self.fallback)());
+            }
+        }
+        (self.fallback)()
+    }
+    pub fn resolve_current(&self) -> R {
+        self.resolve(&WINDOW.with(|w| w.location().pathname().unwrap()))
+    }
+}
+
+/// Pushes `path` onto the history and fires `popstate`, so routers listening
+/// through `on_navigate` rerender without a page load.
+pub fn navigate(path: &str) {
+    WINDOW.with(|w| {
+        w.history().unwrap().push_state_with_url(&JsValue::NULL, "", Some(path)).unwrap();
+        w.dispatch_event(&Event::new("popstate").unwrap()).unwrap();
+    });
+}
+
+/// Runs the component callback named `callback`, e.g. `"route_changed"`, each
+/// time the location changes through back, forward or `navigate`, until the
+/// component unmounts. The callback can then render `resolve_current` again.
+pub fn on_navigate(callback: &'static str) {
+    intercept_links();
+    let node_id = NODE_ID.with(|n| n.borrow().clone());
+    let cb = Closure::<dyn FnMut(Event)>::new(move |_: Event| {
+        let _ = call(&format!("{}[]", callback), &node_id);
+    });
+    WINDOW.with(|w| w.add_event_listener_with_callback("popstate", cb.as_ref().unchecked_ref()).unwrap());
+    on_unmount(move || {
+        WINDOW.with(|w| w.remove_event_listener_with_callback("popstate", cb.as_ref().unchecked_ref()).unwrap());
+    });
+}
+
+/// An `<a>` to `href` that `navigate`s when clicked instead of loading the page.
+pub fn link(href: &str, children: Vec<Rsx>) -> Rsx {
+    element!("A", attributes![("href".to_string(), href.to_string()), ("data-link".to_string(), String::new())], children)
+}
+
+/// The path a click on a `link` to `href` navigates to in place. Clicks with a
+/// modifier or another button still open a tab or window, and links off the
+/// site load normally.
+fn internal_path(href: &str, button: i16, modified: bool) -> Option<&str> {
+    (button == 0 && !modified && href.starts_with('/') && !href.starts_with("//")).then_some(href)
+}
+
+/// Adds the one document listener that handles all clicks on `link`s.
+fn intercept_links() {
+    if LINKS.with(|l| l.replace(true)) {
+        return;
+    }
+    let cb = Closure::<dyn FnMut(Event)>::new(|event: Event| {
+        let Some(click) = event.dyn_ref::<MouseEvent>() else {
+            return;
+        };
+        let modified = click.ctrl_key() || click.meta_key() || click.shift_key() || click.alt_key();
+        let Some(href) = event.composed_path().iter().filter_map(|t| t.dyn_into::<Element>().ok()).find(|el| el.has_attribute("data-link")).and_then(|el| el.get_attribute("href")) else {
+            return;
+        };
+        if let Some(path) = internal_path(&href, click.button(), modified) {
+            let path = path.to_string();
+            with_event(event, || {
+                prevent_default();
+                navigate(&path);
+            });
+        }
+    });
+    DOCUMENT.with(|document| document.add_event_listener_with_callback("click", cb.as_ref().unchecked_ref()).unwrap());
+    cb.forget();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(params: &Params) -> Result<String, NotFound> {
+        let id: u32 = params.get("id")?;
+        Ok(format!("user {}", id))
+    }
+
+    fn posts(params: &Params) -> Result<String, NotFound> {
+        let page: Option<u32> = params.get_opt("page")?;
+        Ok(format!("page {}", page.unwrap_or(1)))
+    }
+
+    fn files(params: &Params) -> Result<String, NotFound> {
+        Ok(params.raw("path").unwrap().to_string())
+    }
+
+    fn fallback() -> String {
+        "not found".to_string()
+    }
+
+    #[test]
+    fn typed_params() {
+        let router = Router::new(fallback).route("/user/:id", user).route("/posts/:page?", posts).route("/files/*path", files);
+        assert_eq!(router.resolve("/user/42"), "user 42");
+        assert_eq!(router.resolve("/user/abc"), "not found");
+        assert_eq!(router.resolve("/posts"), "page 1");
+        assert_eq!(router.resolve("/posts/3?sort=new"), "page 3");
+        assert_eq!(router.resolve("/files/a/b.txt"), "a/b.txt");
+        assert_eq!(router.resolve("/nowhere"), "not found");
+    }
+
+    #[test]
+    fn links() {
+        let a = link("/user/42", vec![Rsx::new_text("bea".to_string())]);
+        assert_eq!(a.as_elem().name, "A");
+        assert_eq!(a.as_elem().attrs[0].value, "/user/42");
+        assert_eq!(internal_path("/user/42", 0, false), Some("/user/42"));
+        assert_eq!(internal_path("/user/42", 0, true), None);
+        assert_eq!(internal_path("/user/42", 1, false), None);
+        assert_eq!(internal_path("//cdn.example.com/a", 0, false), None);
+        assert_eq!(internal_path("https://example.com", 0, false), None);
+    }
+}
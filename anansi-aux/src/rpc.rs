@@ -0,0 +1,190 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen::closure::Closure;
+use web_sys::{RequestInit, Response};
+
+use crate::{WINDOW, NODE_ID, Resource, schedule_rerender};
+
+/// The status and body text of a response, or why there is none.
+pub(crate) type Settled = Result<(u16, String), String>;
+
+thread_local! {
+    static SETTLED: RefCell<HashMap<CallId, Settled>> = RefCell::new(HashMap::new());
+    static NEXT_CALL: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Tells a `call_server` apart from the other calls, including earlier ones
+/// to the same endpoint, for `read_server`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CallId(u64);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RpcError {
+    /// The request could not be serialized.
+    Encode(String),
+    /// The request failed before a response arrived.
+    Network(String),
+    /// The server answered with a status outside 200-299.
+    Status(u16, String),
+    /// The response body was not the expected type.
+    Decode(String),
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Encode(e) => write!(f, "could not encode request: {}", e),
+            Self::Network(e) => write!(f, "request failed: {}", e),
+            Self::Status(status, body) => write!(f, "server responded with {}: {}", status, body),
+            Self::Decode(e) => write!(f, "could not decode response: {}", e),
+        }
+    }
+}
+
+impl Error for RpcError {}
+
+pub(crate) fn rejected<D>(e: RpcError) -> Resource<D> {
+    Resource::Rejected(Box::new(e))
+}
+
+pub(crate) fn decode<Res: DeserializeOwned>(settled: Settled) -> Resource<Res> {
+    match settled {
+        Ok((status, body)) if (200..300).contains(&status) => match serde_json::from_str(&body) {
+            Ok(res) => Resource::Resolved(res),
+            Err(e) => rejected(RpcError::Decode(e.to_string())),
+        },
+        Ok((status, body)) => rejected(RpcError::Status(status, body)),
+        Err(e) => rejected(RpcError::Network(e)),
+    }
+}
+
+/// Records the outcome of `call` and rerenders the component that made it.
+fn settle(call: CallId, node_id: &str, settled: Settled) {
+    SETTLED.with(|s| s.borrow_mut().insert(call, settled));
+    schedule_rerender(node_id);
+}
+
+pub(crate) fn network(result: Result<(u16, String), JsValue>) -> Settled {
+    result.map_err(|e| e.as_string().unwrap_or_else(|| format!("{:?}", e)))
+}
+
+/// Fetches `url` and hands `settle` the status and body text, or the error
+/// the request or body read failed with.
+pub(crate) fn send(url: &str, init: &RequestInit, settle: impl FnOnce(Result<(u16, String), JsValue>) + Clone + 'static) {
+    let promise = WINDOW.with(|w| w.fetch_with_str_and_init(url, init));
+    let settle2 = settle.clone();
+    let ok = Closure::once(move |r: JsValue| {
+        let response: Response = r.unchecked_into();
+        let status = response.status();
+        match response.text() {
+            Ok(text) => {
+                let settle2 = settle.clone();
+                let ok = Closure::once(move |t: JsValue| settle(Ok((status, t.as_string().unwrap_or_default()))));
+                let err = Closure::once(move |e: JsValue| settle2(Err(e)));
+                let _ = text.then2(&ok, &err);
+                ok.forget();
+                err.forget();
+            }
+            Err(err) => settle(Err(err)),
+        }
+    });
+    let err = Closure::once(move |e: JsValue| settle2(Err(e)));
+    let _ = promise.then2(&ok, &err);
+    ok.forget();
+    err.forget();
+}
+
+/// POSTs `req` as JSON to `endpoint` and returns the call's id with `Pending`,
+/// or with `Rejected` when the request can't be serialized. Once the response
+/// has arrived the calling component rerenders, and `read_server` with the id
+/// turns the response into the final `Resource`.
+pub fn call_server<Req: Serialize, Res: DeserializeOwned>(endpoint: &str, req: Req) -> (CallId, Resource<Res>) {
+    let call = CallId(NEXT_CALL.with(|n| n.replace(n.get() + 1)));
+    let body = match serde_json::to_string(&req) {
+        Ok(body) => body,
+        Err(e) => return (call, rejected(RpcError::Encode(e.to_string()))),
+    };
+    let init = RequestInit::new();
+    init.set_method("POST");
+    init.set_body(&JsValue::from_str(&body));
+    let headers = js_sys::Object::new();
+    js_sys::Reflect::set(&headers, &"Content-Type".into(), &"application/json".into()).unwrap();
+    init.set_headers(&headers);
+    let node_id = NODE_ID.with(|n| n.borrow().clone());
+    send(endpoint, &init, move |result| settle(call, &node_id, network(result)));
+    (call, Resource::Pending)
+}
+
+/// Replaces `resource` with the outcome of `call` once it has settled.
+pub fn read_server<Res: DeserializeOwned>(call: CallId, resource: &mut Resource<Res>) {
+    if let Some(settled) = SETTLED.with(|s| s.borrow_mut().remove(&call)) {
+        *resource = decode(settled);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use crate::fixtures::{counted_component, renders};
+
+    #[derive(Serialize)]
+    struct AddReq {
+        a: i32,
+        b: i32,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct AddRes {
+        sum: i32,
+    }
+
+    fn error<D>(resource: Resource<D>) -> String {
+        match resource {
+            Resource::Rejected(e) => e.to_string(),
+            _ => panic!("expected rejected"),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let body = serde_json::to_string(&AddReq {a: 2, b: 3}).unwrap();
+        assert_eq!(body, r#"{"a":2,"b":3}"#);
+        match decode::<AddRes>(Ok((200, r#"{"sum":5}"#.to_string()))) {
+            Resource::Resolved(res) => assert_eq!(res, AddRes {sum: 5}),
+            _ => panic!("expected resolved"),
+        }
+    }
+
+    #[test]
+    fn calls_settle_apart() {
+        counted_component("cart");
+        let (first, second) = (CallId(10), CallId(11));
+        let (mut a, mut b) = (Resource::<AddRes>::Pending, Resource::<AddRes>::Pending);
+        settle(second, "cart", Ok((200, r#"{"sum":7}"#.to_string())));
+        assert_eq!(renders(), 1);
+        read_server(first, &mut a);
+        read_server(second, &mut b);
+        assert!(matches!(a, Resource::Pending));
+        assert!(matches!(b, Resource::Resolved(AddRes {sum: 7})));
+        settle(first, "cart", Ok((200, r#"{"sum":3}"#.to_string())));
+        read_server(first, &mut a);
+        assert!(matches!(a, Resource::Resolved(AddRes {sum: 3})));
+        assert_eq!(renders(), 2);
+    }
+
+    #[test]
+    fn errors_reject() {
+        assert_eq!(error(decode::<AddRes>(Ok((500, "boom".to_string())))), "server responded with 500: boom");
+        assert!(error(decode::<AddRes>(Ok((200, "{}".to_string())))).starts_with("could not decode response"));
+        assert_eq!(error(decode::<AddRes>(Err("offline".to_string()))), "request failed: offline");
+        let unkeyable = HashMap::from([((1, 2), 3)]);
+        assert!(error(call_server::<_, AddRes>("/add", unkeyable).1).starts_with("could not encode request"));
+    }
+}
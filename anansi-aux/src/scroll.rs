@@ -0,0 +1,67 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use wasm_bindgen::JsCast;
+use web_sys::Element;
+
+use crate::DOCUMENT;
+
+thread_local! {
+    static SCROLLS: RefCell<HashMap<String, (Element, i32, i32)>> = RefCell::new(HashMap::new());
+}
+
+fn keyed() -> Vec<(String, Element)> {
+    let mut v = vec![];
+    DOCUMENT.with(|document| {
+        let nodes = document.query_selector_all("[data-scroll-key]").unwrap();
+        for i in 0..nodes.length() {
+            let el = nodes.get(i).unwrap().dyn_into::<Element>().unwrap();
+            v.push((el.get_attribute("data-scroll-key").unwrap(), el));
+        }
+    });
+    v
+}
+
+/// Keeps `offset` within a container whose content may have shrunk while it was hidden.
+pub fn clamp(offset: i32, scroll_size: i32, client_size: i32) -> i32 {
+    offset.min((scroll_size - client_size).max(0)).max(0)
+}
+
+/// Records the offsets of every `[data-scroll-key]` container currently in the document.
+pub fn capture() {
+    SCROLLS.with(|s| {
+        let mut scrolls = s.borrow_mut();
+        for (key, el) in keyed() {
+            let (top, left) = (el.scroll_top(), el.scroll_left());
+            scrolls.insert(key, (el, top, left));
+        }
+    });
+}
+
+/// Restores saved offsets onto containers that were re-created since `capture`,
+/// e.g. a view that was toggled off and back on.
+pub fn restore() {
+    SCROLLS.with(|s| {
+        let scrolls = s.borrow();
+        for (key, el) in keyed() {
+            if let Some((old, top, left)) = scrolls.get(&key) {
+                if !old.is_same_node(Some(&el)) {
+                    el.set_scroll_top(clamp(*top, el.scroll_height(), el.client_height()));
+                    el.set_scroll_left(clamp(*left, el.scroll_width(), el.client_width()));
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clamp_resized() {
+        assert_eq!(clamp(300, 1000, 200), 300);
+        assert_eq!(clamp(300, 400, 200), 200);
+        assert_eq!(clamp(300, 100, 200), 0);
+    }
+}
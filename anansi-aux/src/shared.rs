@@ -0,0 +1,153 @@
+use std::ops::Deref;
+use std::rc::Rc;
+
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+
+/// A copy-on-write value. Cloning shares the allocation, and `make_mut` only
+/// copies when a snapshot still holds it, so large, mostly-unchanged parts of a
+/// signal's state stay shared across history snapshots.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Shared<T>(Rc<T>);
+
+impl<T> Clone for Shared<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> Shared<T> {
+    pub fn new(t: T) -> Self {
+        Self(Rc::new(t))
+    }
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<T: Clone> Shared<T> {
+    pub fn make_mut(&mut self) -> &mut T {
+        Rc::make_mut(&mut self.0)
+    }
+}
+
+impl<T> Deref for Shared<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: Serialize> Serialize for Shared<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Shared<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(Self::new)
+    }
+}
+
+const CHUNK: usize = 32;
+
+/// A vector stored in shared chunks, so setting or pushing an item copies only
+/// the chunk it lands in.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SharedVec<T> {
+    chunks: Vec<Shared<Vec<T>>>,
+    len: usize,
+}
+
+impl<T> Clone for SharedVec<T> {
+    fn clone(&self) -> Self {
+        Self {chunks: self.chunks.clone(), len: self.len}
+    }
+}
+
+impl<T: Clone> SharedVec<T> {
+    pub fn new() -> Self {
+        Self {chunks: vec![], len: 0}
+    }
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    pub fn get(&self, n: usize) -> Option<&T> {
+        self.chunks.get(n / CHUNK).and_then(|c| c.get(n % CHUNK))
+    }
+    pub fn set(&mut self, n: usize, t: T) {
+        assert!(n < self.len, "index out of bounds");
+        self.chunks[n / CHUNK].make_mut()[n % CHUNK] = t;
+    }
+    pub fn push(&mut self, t: T) {
+        if self.len.is_multiple_of(CHUNK) {
+            self.chunks.push(Shared::new(Vec::with_capacity(CHUNK)));
+        }
+        self.chunks.last_mut().unwrap().make_mut().push(t);
+        self.len += 1;
+    }
+    pub fn pop(&mut self) -> Option<T> {
+        let last = self.chunks.last_mut()?.make_mut().pop();
+        self.len -= 1;
+        if self.len.is_multiple_of(CHUNK) {
+            self.chunks.pop();
+        }
+        last
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.chunks.iter().flat_map(|c| c.iter())
+    }
+    /// The number of chunks `self` and `other` still share.
+    pub fn shared_chunks(&self, other: &Self) -> usize {
+        self.chunks.iter().zip(&other.chunks).filter(|(a, b)| a.ptr_eq(b)).count()
+    }
+}
+
+impl<T: Clone> FromIterator<T> for SharedVec<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut v = Self::new();
+        for t in iter {
+            v.push(t);
+        }
+        v
+    }
+}
+
+impl<T: Serialize> Serialize for SharedVec<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.chunks.iter().flat_map(|c| c.iter()))
+    }
+}
+
+impl<'de, T: Clone + Deserialize<'de>> Deserialize<'de> for SharedVec<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::<T>::deserialize(deserializer).map(|v| v.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_shares_structure() {
+        let mut rows: SharedVec<u32> = (0..100).collect();
+        let snapshot = rows.clone();
+        rows.set(40, 0);
+        assert_eq!(snapshot.get(40), Some(&40));
+        assert_eq!(rows.get(40), Some(&0));
+        assert_eq!(rows.shared_chunks(&snapshot), 3);
+        let json = serde_json::to_string(&rows).unwrap();
+        assert_eq!(serde_json::from_str::<SharedVec<u32>>(&json).unwrap().iter().sum::<u32>(), rows.iter().sum::<u32>());
+
+        let mut big = Shared::new(vec![1, 2, 3]);
+        let before = big.clone();
+        assert!(big.ptr_eq(&before));
+        big.make_mut().push(4);
+        assert!(!big.ptr_eq(&before));
+        assert_eq!(*before, vec![1, 2, 3]);
+    }
+}
@@ -0,0 +1,170 @@
+use wasm_bindgen::JsCast;
+use web_sys::{Element, Node};
+
+use crate::{FRAMEWORK_ATTRS, attr_escape, is_void};
+
+#[derive(Debug, Clone, Copy)]
+pub struct SnapshotOptions {
+    /// Drop the `av` and `/av` comments marking virtual node regions.
+    pub strip_markers: bool,
+    /// Drop `rid`, `a:id`, `data-anansi-src` and `data-anansi-meta`.
+    pub strip_attrs: bool,
+}
+
+impl Default for SnapshotOptions {
+    fn default() -> Self {
+        Self {strip_markers: true, strip_attrs: true}
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum SnapNode {
+    Element {name: String, attrs: Vec<(String, String)>, children: Vec<SnapNode>},
+    Text(String),
+    Comment(String),
+}
+
+impl SnapNode {
+    fn from_node(node: &Node) -> Option<Self> {
+        match node.node_type() {
+            Node::ELEMENT_NODE => {
+                let el = node.dyn_ref::<Element>().unwrap();
+                let attributes = el.attributes();
+                let mut attrs = vec![];
+                for i in 0..attributes.length() {
+                    let attr = attributes.item(i).unwrap();
+                    attrs.push((attr.name(), attr.value()));
+                }
+                let children = children(node);
+                Some(Self::Element {name: el.tag_name().to_lowercase(), attrs, children})
+            }
+            Node::TEXT_NODE => Some(Self::Text(node.node_value().unwrap_or_default())),
+            Node::COMMENT_NODE => Some(Self::Comment(node.node_value().unwrap_or_default())),
+            _ => None,
+        }
+    }
+    fn normalize(self, options: &SnapshotOptions) -> Option<Self> {
+        match self {
+            Self::Element {name, mut attrs, children} => {
+                if options.strip_attrs {
+                    attrs.retain(|(k, _)| !FRAMEWORK_ATTRS.contains(&k.as_str()));
+                }
+                attrs.sort();
+                let children = normalize_all(children, options);
+                Some(Self::Element {name, attrs, children})
+            }
+            Self::Comment(c) if options.strip_markers && (c.starts_with("av ") || c == "av" || c == "/av") => None,
+            _ => Some(self),
+        }
+    }
+    fn write(&self, depth: usize, s: &mut String) {
+        let indent = "  ".repeat(depth);
+        match self {
+            Self::Element {name, attrs, children} => {
+                s.push_str(&format!("{}<{}", indent, name));
+                for (k, v) in attrs {
+                    s.push_str(&format!(" {}=\"{}\"", k, attr_escape(v)));
+                }
+                if is_void(name) {
+                    s.push_str(" />\n");
+                } else if children.is_empty() {
+                    s.push_str(&format!("></{}>\n", name));
+                } else {
+                    s.push_str(">\n");
+                    for child in children {
+                        child.write(depth + 1, s);
+                    }
+                    s.push_str(&format!("{}</{}>\n", indent, name));
+                }
+            }
+            Self::Text(t) => s.push_str(&format!("{}{}\n", indent, escape(t.trim()))),
+            Self::Comment(c) => s.push_str(&format!("{}<!--{}-->\n", indent, c)),
+        }
+    }
+}
+
+fn children(node: &Node) -> Vec<SnapNode> {
+    let nodes = node.child_nodes();
+    (0..nodes.length()).filter_map(|i| SnapNode::from_node(&nodes.get(i).unwrap())).collect()
+}
+
+/// Drops filtered nodes and whitespace-only text, merging the text runs left adjacent.
+fn normalize_all(nodes: Vec<SnapNode>, options: &SnapshotOptions) -> Vec<SnapNode> {
+    let mut v: Vec<SnapNode> = vec![];
+    for node in nodes.into_iter().filter_map(|n| n.normalize(options)) {
+        match (v.last_mut(), node) {
+            (_, SnapNode::Text(t)) if t.trim().is_empty() => {}
+            (Some(SnapNode::Text(prev)), SnapNode::Text(t)) => prev.push_str(&t),
+            (_, node) => v.push(node),
+        }
+    }
+    v
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn serialize(nodes: Vec<SnapNode>, options: &SnapshotOptions) -> String {
+    let mut s = String::new();
+    for node in normalize_all(nodes, options) {
+        node.write(0, &mut s);
+    }
+    s
+}
+
+/// Serializes the children of `root` as they currently are in the DOM, one
+/// node per line with sorted attributes, for comparing against golden strings.
+pub fn dom_snapshot(root: &Element, options: SnapshotOptions) -> String {
+    serialize(children(root.unchecked_ref()), &options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn el(name: &str, attrs: &[(&str, &str)], children: Vec<SnapNode>) -> SnapNode {
+        let attrs = attrs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        SnapNode::Element {name: name.to_string(), attrs, children}
+    }
+
+    fn text(s: &str) -> SnapNode {
+        SnapNode::Text(s.to_string())
+    }
+
+    fn tree(count: &str) -> Vec<SnapNode> {
+        vec![
+            SnapNode::Comment("av a:id=0".to_string()),
+            el("div", &[("rid", "3"), ("class", "counter"), ("data-anansi-src", "counter:0")], vec![
+                text("\n  "),
+                el("button", &[("on:click", "counter_inc[0]"), ("rid", "4")], vec![text("+")]),
+                text("Count: "),
+                text(count),
+            ]),
+            SnapNode::Comment("/av".to_string()),
+        ]
+    }
+
+    #[test]
+    fn snapshot_after_update() {
+        assert_eq!(serialize(tree("0"), &SnapshotOptions::default()), "\
+<div class=\"counter\">
+  <button on:click=\"counter_inc[0]\">
+    +
+  </button>
+  Count: 0
+</div>
+");
+        let keep = SnapshotOptions {strip_markers: false, strip_attrs: false};
+        let s = serialize(tree("1 < 2"), &keep);
+        assert!(s.starts_with("<!--av a:id=0-->\n<div class=\"counter\" data-anansi-src=\"counter:0\" rid=\"3\">"));
+        assert!(s.contains("Count: 1 &lt; 2"));
+        assert!(s.ends_with("<!--/av-->\n"));
+    }
+
+    #[test]
+    fn void_self_closed() {
+        let nodes = vec![el("p", &[], vec![el("img", &[("alt", "say \"hi\"")], vec![]), el("br", &[], vec![])])];
+        assert_eq!(serialize(nodes, &SnapshotOptions::default()), "<p>\n  <img alt=\"say &quot;hi&quot;\" />\n  <br />\n</p>\n");
+    }
+}
@@ -0,0 +1,316 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::{Deref, DerefMut};
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use wasm_bindgen::JsCast;
+use wasm_bindgen::closure::Closure;
+use web_sys::{Storage, StorageEvent};
+
+use crate::{WINDOW, NODE_ID, Signal, on_unmount, schedule_rerender};
+
+type Entries = Rc<RefCell<BTreeMap<String, String>>>;
+
+/// A view over every `localStorage` key starting with a prefix. Keys are
+/// exposed without the prefix, and writes from other tabs arrive through the
+/// `storage` event.
+pub struct StorageMap {
+    prefix: String,
+    entries: Entries,
+}
+
+fn local_storage() -> Storage {
+    WINDOW.with(|w| w.local_storage().unwrap().expect("expected local storage"))
+}
+
+/// Applies a `storage` event. A `None` key means the other tab cleared storage.
+fn apply(entries: &mut BTreeMap<String, String>, prefix: &str, key: Option<&str>, value: Option<&str>) {
+    match key {
+        None => entries.clear(),
+        Some(key) => {
+            if let Some(key) = key.strip_prefix(prefix) {
+                match value {
+                    Some(value) => entries.insert(key.to_string(), value.to_string()),
+                    None => entries.remove(key),
+                };
+            }
+        }
+    }
+}
+
+thread_local! {
+    static MAPS: RefCell<HashMap<(String, String), Entries>> = RefCell::new(HashMap::new());
+}
+
+fn read_entries(prefix: &str) -> BTreeMap<String, String> {
+    let storage = local_storage();
+    let mut map = BTreeMap::new();
+    for i in 0..storage.length().unwrap() {
+        if let Some(key) = storage.key(i).unwrap() {
+            if let Some(k) = key.strip_prefix(prefix) {
+                map.insert(k.to_string(), storage.get_item(&key).unwrap().unwrap_or_default());
+            }
+        }
+    }
+    map
+}
+
+/// Applies a `storage` event to the entries of the component at `node_id`
+/// and rerenders it when they changed.
+fn changed(entries: &Entries, node_id: &str, prefix: &str, key: Option<&str>, value: Option<&str>) {
+    let before = entries.borrow().clone();
+    apply(&mut entries.borrow_mut(), prefix, key, value);
+    if *entries.borrow() != before {
+        schedule_rerender(node_id);
+    }
+}
+
+/// The entries under `prefix` for the current component. The first render
+/// reads them and listens for `storage` events, which rerender the component,
+/// until it unmounts. Later renders share what the listener keeps current.
+pub fn use_local_storage_map(prefix: &str) -> StorageMap {
+    let node_id = NODE_ID.with(|n| n.borrow().clone());
+    let key = (node_id.clone(), prefix.to_string());
+    if let Some(entries) = MAPS.with(|m| m.borrow().get(&key).cloned()) {
+        return StorageMap {prefix: prefix.to_string(), entries};
+    }
+    let entries = Rc::new(RefCell::new(read_entries(prefix)));
+    MAPS.with(|m| m.borrow_mut().insert(key.clone(), entries.clone()));
+    let (e, p) = (entries.clone(), prefix.to_string());
+    let cb = Closure::<dyn FnMut(StorageEvent)>::new(move |event: StorageEvent| {
+        changed(&e, &node_id, &p, event.key().as_deref(), event.new_value().as_deref());
+    });
+    WINDOW.with(|w| w.add_event_listener_with_callback("storage", cb.as_ref().unchecked_ref()).unwrap());
+    on_unmount(move || {
+        WINDOW.with(|w| w.remove_event_listener_with_callback("storage", cb.as_ref().unchecked_ref()).unwrap());
+        MAPS.with(|m| m.borrow_mut().remove(&key));
+    });
+    StorageMap {prefix: prefix.to_string(), entries}
+}
+
+impl StorageMap {
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.entries.borrow().get(key).cloned()
+    }
+    pub fn set(&self, key: &str, value: &str) {
+        local_storage().set_item(&format!("{}{}", self.prefix, key), value).unwrap();
+        self.entries.borrow_mut().insert(key.to_string(), value.to_string());
+    }
+    pub fn remove(&self, key: &str) {
+        local_storage().remove_item(&format!("{}{}", self.prefix, key)).unwrap();
+        self.entries.borrow_mut().remove(key);
+    }
+    pub fn entries(&self) -> BTreeMap<String, String> {
+        self.entries.borrow().clone()
+    }
+    /// Copies the current entries into `map`.
+    pub fn sync(&self, map: &mut Signal<BTreeMap<String, String>>) {
+        let entries = self.entries.borrow();
+        if *map.value() != *entries {
+            *map.value_mut() = entries.clone();
+        }
+    }
+}
+
+/// Which of the window's storage areas a `Persistent` lives in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Area {
+    Local,
+    Session,
+}
+
+/// A signal whose value is kept as JSON under a storage key. It is read back
+/// the first time it's used and written after every `value_mut`.
+pub struct Persistent<T> {
+    store: Rc<dyn Store>,
+    key: String,
+    area: Area,
+    signal: Signal<T>,
+}
+
+/// Mutable access to a `Persistent` value, which is stored when dropped.
+pub struct PersistMut<'a, T: Serialize> {
+    store: &'a dyn Store,
+    key: &'a str,
+    area: Area,
+    value: &'a mut T,
+}
+
+/// A `Signal` persisted in `localStorage`, e.g. for a theme preference.
+/// Falls back to `default` when nothing is stored or the stored JSON doesn't parse.
+pub fn persistent_signal<T: Serialize + DeserializeOwned + 'static>(key: &str, default: T) -> Persistent<T> {
+    Persistent::new(Rc::new(Web), Area::Local, key, default)
+}
+
+/// `persistent_signal` in `sessionStorage`, which the tab forgets when closed.
+pub fn session_signal<T: Serialize + DeserializeOwned + 'static>(key: &str, default: T) -> Persistent<T> {
+    Persistent::new(Rc::new(Web), Area::Session, key, default)
+}
+
+fn restore<T: DeserializeOwned>(store: &dyn Store, area: Area, key: &str, default: T) -> T {
+    match store.read(area, key).map(|json| serde_json::from_str(&json)) {
+        Some(Ok(t)) => t,
+        Some(Err(e)) => {
+            store.report(&format!("ignoring stored {:?} value for {}: {}", area, key, e));
+            default
+        }
+        None => default,
+    }
+}
+
+impl<T: Serialize + DeserializeOwned + 'static> Persistent<T> {
+    fn new(store: Rc<dyn Store>, area: Area, key: &str, default: T) -> Self {
+        let (s, k) = (store.clone(), key.to_string());
+        Self {store, key: key.to_string(), area, signal: Signal::lazy(move || restore(&*s, area, &k, default))}
+    }
+}
+
+impl<T: Serialize> Persistent<T> {
+    pub fn value(&mut self) -> &T {
+        self.signal.value()
+    }
+    pub fn value_mut(&mut self) -> PersistMut<'_, T> {
+        PersistMut {store: &*self.store, key: &self.key, area: self.area, value: self.signal.value_mut()}
+    }
+    pub fn signal(&mut self) -> &mut Signal<T> {
+        &mut self.signal
+    }
+}
+
+impl<T: Serialize> Deref for PersistMut<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+impl<T: Serialize> DerefMut for PersistMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.value
+    }
+}
+
+impl<T: Serialize> Drop for PersistMut<'_, T> {
+    /// Stores the value. When storage refuses it, e.g. over quota, the
+    /// in-memory value is kept and the failure logged.
+    fn drop(&mut self) {
+        let result = serde_json::to_string(&*self.value).map_err(|e| e.to_string()).and_then(|json| self.store.write(self.area, self.key, &json));
+        if let Err(e) = result {
+            self.store.report(&format!("problem storing {:?} value for {}: {}", self.area, self.key, e));
+        }
+    }
+}
+
+/// Where `Persistent` values are read from and written to, and where
+/// problems with them are reported.
+trait Store {
+    fn read(&self, area: Area, key: &str) -> Option<String>;
+    fn write(&self, area: Area, key: &str, value: &str) -> Result<(), String>;
+    fn report(&self, message: &str);
+}
+
+/// The window's storage areas, reporting to the console.
+struct Web;
+
+impl Web {
+    fn area(area: Area) -> Option<Storage> {
+        WINDOW.with(|w| match area {
+            Area::Local => w.local_storage(),
+            Area::Session => w.session_storage(),
+        }).ok().flatten()
+    }
+}
+
+impl Store for Web {
+    fn read(&self, area: Area, key: &str) -> Option<String> {
+        Self::area(area)?.get_item(key).ok().flatten()
+    }
+    fn write(&self, area: Area, key: &str, value: &str) -> Result<(), String> {
+        let storage = Self::area(area).ok_or_else(|| "storage unavailable".to_string())?;
+        storage.set_item(key, value).map_err(|e| js_sys::Reflect::get(&e, &"name".into()).ok().and_then(|n| n.as_string()).unwrap_or_else(|| format!("{:?}", e)))
+    }
+    fn report(&self, message: &str) {
+        web_sys::console::warn_1(&message.into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{counted_component, renders};
+
+    const QUOTA: usize = 16;
+
+    /// Storage in memory that refuses values over `QUOTA` bytes.
+    #[derive(Default)]
+    struct Memory {
+        items: RefCell<BTreeMap<(bool, String), String>>,
+        reports: RefCell<Vec<String>>,
+    }
+
+    impl Store for Memory {
+        fn read(&self, area: Area, key: &str) -> Option<String> {
+            self.items.borrow().get(&(area == Area::Local, key.to_string())).cloned()
+        }
+        fn write(&self, area: Area, key: &str, value: &str) -> Result<(), String> {
+            if value.len() > QUOTA {
+                return Err("QuotaExceededError".to_string());
+            }
+            self.items.borrow_mut().insert((area == Area::Local, key.to_string()), value.to_string());
+            Ok(())
+        }
+        fn report(&self, message: &str) {
+            self.reports.borrow_mut().push(message.to_string());
+        }
+    }
+
+    #[test]
+    fn other_tab_events() {
+        let mut entries = BTreeMap::new();
+        apply(&mut entries, "settings.", Some("settings.theme"), Some("dark"));
+        apply(&mut entries, "settings.", Some("other.key"), Some("x"));
+        assert_eq!(entries.get("theme").map(|s| s.as_str()), Some("dark"));
+        assert_eq!(entries.len(), 1);
+        apply(&mut entries, "settings.", Some("settings.theme"), None);
+        assert!(entries.is_empty());
+        apply(&mut entries, "settings.", Some("settings.a"), Some("1"));
+        apply(&mut entries, "settings.", None, None);
+        assert!(entries.is_empty());
+    }
+
+    #[test]
+    fn other_tab_rerenders() {
+        counted_component("prefs");
+        let entries: Entries = Rc::default();
+        changed(&entries, "prefs", "settings.", Some("settings.theme"), Some("dark"));
+        changed(&entries, "prefs", "settings.", Some("settings.theme"), Some("dark"));
+        changed(&entries, "prefs", "settings.", Some("other.key"), Some("x"));
+        assert_eq!(renders(), 1);
+        let map = StorageMap {prefix: "settings.".to_string(), entries};
+        let mut signal = Signal::new(BTreeMap::new());
+        map.sync(&mut signal);
+        assert_eq!(signal.value().get("theme").map(|s| s.as_str()), Some("dark"));
+    }
+
+    #[test]
+    fn persisted_theme() {
+        let store = Rc::new(Memory::default());
+        let local = |key: &str, default: String| Persistent::new(store.clone(), Area::Local, key, default);
+        let mut theme = local("theme", "light".to_string());
+        assert_eq!(theme.value(), "light");
+        *theme.value_mut() = "dark".to_string();
+        assert_eq!(local("theme", "light".to_string()).value(), "dark");
+        assert_eq!(Persistent::new(store.clone(), Area::Session, "theme", "light".to_string()).value(), "light");
+        *theme.value_mut() = "a name past the quota".to_string();
+        assert_eq!(theme.value(), "a name past the quota");
+        assert_eq!(local("theme", String::new()).value(), "dark");
+        store.write(Area::Session, "count", "{oops").unwrap();
+        assert_eq!(*Persistent::new(store.clone(), Area::Session, "count", 3).value(), 3);
+        let reports = store.reports.take();
+        assert_eq!(reports.len(), 2);
+        assert!(reports[0].contains("QuotaExceededError"));
+        assert!(reports[1].starts_with("ignoring stored Session value for count"));
+    }
+}
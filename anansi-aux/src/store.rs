@@ -0,0 +1,112 @@
+use std::rc::Rc;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::Value;
+
+use crate::{component_index, on_unmount};
+
+type Subscriber = (u64, Rc<dyn Fn(&Value)>);
+
+thread_local! {
+    static STORES: RefCell<HashMap<String, Value>> = RefCell::new(HashMap::new());
+    static SUBSCRIBERS: RefCell<HashMap<String, Vec<Subscriber>>> = RefCell::new(HashMap::new());
+    static NEXT_ID: Cell<u64> = const { Cell::new(0) };
+}
+
+/// A named value shared by every island on the page. Islands refer to the same
+/// store by name, so they don't need to know about each other.
+pub struct Store<T> {
+    name: String,
+    t: PhantomData<T>,
+}
+
+pub fn store<T: Serialize + DeserializeOwned>(name: &str) -> Store<T> {
+    Store {name: name.to_string(), t: PhantomData}
+}
+
+impl<T: Serialize + DeserializeOwned> Store<T> {
+    pub fn get(&self) -> Option<T> {
+        STORES.with(|s| s.borrow().get(&self.name).and_then(|v| serde_json::from_value(v.clone()).ok()))
+    }
+    /// Sets the value only if the store is still empty, e.g. from whichever island loads first.
+    pub fn init(&self, t: T) {
+        if STORES.with(|s| !s.borrow().contains_key(&self.name)) {
+            self.set(t);
+        }
+    }
+    pub fn set(&self, t: T) {
+        let value = serde_json::to_value(t).expect("problem serializing store");
+        STORES.with(|s| s.borrow_mut().insert(self.name.clone(), value.clone()));
+        let subscribers: Vec<_> = SUBSCRIBERS.with(|s| s.borrow().get(&self.name).map(|v| v.iter().map(|(_, f)| f.clone()).collect()).unwrap_or_default());
+        for f in subscribers {
+            f(&value);
+        }
+    }
+    pub fn update(&self, f: impl FnOnce(&mut T)) where T: Default {
+        let mut t = self.get().unwrap_or_default();
+        f(&mut t);
+        self.set(t);
+    }
+    /// Calls `f` with every new value. An island that subscribes after the
+    /// store was set is called at once with the current value. Subscriptions
+    /// made while a component is mounted end when it unmounts.
+    pub fn subscribe(&self, f: impl Fn(T) + 'static) -> u64 {
+        let id = NEXT_ID.with(|n| {
+            let id = n.get();
+            n.set(id + 1);
+            id
+        });
+        let cb: Rc<dyn Fn(&Value)> = Rc::new(move |v: &Value| {
+            if let Ok(t) = serde_json::from_value(v.clone()) {
+                f(t);
+            }
+        });
+        SUBSCRIBERS.with(|s| s.borrow_mut().entry(self.name.clone()).or_default().push((id, cb.clone())));
+        if component_index().is_some() {
+            let name = self.name.clone();
+            on_unmount(move || unsubscribe(&name, id));
+        }
+        if let Some(value) = STORES.with(|s| s.borrow().get(&self.name).cloned()) {
+            cb(&value);
+        }
+        id
+    }
+    pub fn unsubscribe(&self, id: u64) {
+        unsubscribe(&self.name, id);
+    }
+}
+
+fn unsubscribe(name: &str, id: u64) {
+    SUBSCRIBERS.with(|s| {
+        if let Some(v) = s.borrow_mut().get_mut(name) {
+            v.retain(|(n, _)| *n != id);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_between_islands() {
+        let header = Rc::new(Cell::new(0));
+        let h = header.clone();
+        let cart: Store<u32> = store("cart");
+        cart.subscribe(move |n| h.set(n));
+        let island: Store<u32> = store("cart");
+        island.update(|n| *n += 2);
+        assert_eq!(header.get(), 2);
+
+        let late = Rc::new(Cell::new(0));
+        let l = late.clone();
+        let id = store::<u32>("cart").subscribe(move |n| l.set(n));
+        assert_eq!(late.get(), 2);
+        cart.unsubscribe(id);
+        island.set(5);
+        assert_eq!((header.get(), late.get()), (5, 2));
+    }
+}
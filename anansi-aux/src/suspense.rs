@@ -0,0 +1,97 @@
+use std::cell::RefCell;
+
+use crate::{Rsx, Comp, Resource};
+
+thread_local! {
+    static BOUNDARIES: RefCell<Vec<bool>> = const { RefCell::new(vec![]) };
+}
+
+/// Reads a resource during render. A `Pending` resource suspends the nearest
+/// enclosing `suspense` boundary, so components at any depth don't need to
+/// handle the pending state themselves.
+pub fn read<D>(resource: &Resource<D>) -> Option<&D> {
+    match resource {
+        Resource::Resolved(d) => Some(d),
+        Resource::Pending => {
+            BOUNDARIES.with(|b| {
+                if let Some(suspended) = b.borrow_mut().last_mut() {
+                    *suspended = true;
+                }
+            });
+            None
+        }
+        Resource::Rejected(_) => None,
+    }
+}
+
+pub(crate) fn depth() -> usize {
+    BOUNDARIES.with(|b| b.borrow().len())
+}
+
+/// Drops the boundaries a panicking render left open above `depth`.
+pub(crate) fn unwind(depth: usize) {
+    BOUNDARIES.with(|b| b.borrow_mut().truncate(depth));
+}
+
+/// Renders `children`, discarding them in favour of `fallback` when anything
+/// rendered inside them read a pending resource. The boundary is decided on
+/// every render, so a resource going back to `Pending` on refetch shows the
+/// fallback again until it settles.
+pub fn suspense(children: impl FnOnce() -> Vec<Rsx>, fallback: Rsx) -> Rsx {
+    BOUNDARIES.with(|b| b.borrow_mut().push(false));
+    let children = children();
+    let suspended = BOUNDARIES.with(|b| b.borrow_mut().pop().unwrap());
+    if suspended {
+        Rsx::Component(Comp {children: vec![fallback]})
+    } else {
+        Rsx::Component(Comp {children})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{attributes, element, Attribute, Elem};
+
+    fn text(rsx: &Rsx) -> String {
+        match rsx {
+            Rsx::Component(comp) => comp.children.iter().map(text).collect(),
+            Rsx::Element(el) => el.children.iter().map(text).collect(),
+            Rsx::Text(txt) => txt.text.clone(),
+            _ => unreachable!(),
+        }
+    }
+
+    fn grandchild(res: &Resource<String>) -> Vec<Rsx> {
+        vec![Rsx::new_text(read(res).cloned().unwrap_or_default())]
+    }
+
+    fn child(res: &Resource<String>) -> Vec<Rsx> {
+        vec![Rsx::Component(Comp {children: grandchild(res)})]
+    }
+
+    #[test]
+    fn grandchild_suspends() {
+        let fallback = || Rsx::new_text("loading".to_string());
+        let pending = Resource::Pending;
+        assert_eq!(text(&suspense(|| child(&pending), fallback())), "loading");
+        let resolved = Resource::Resolved("done".to_string());
+        assert_eq!(text(&suspense(|| child(&resolved), fallback())), "done");
+        let outer = suspense(|| vec![suspense(|| child(&pending), fallback()), Rsx::new_text("!".to_string())], Rsx::new_text(String::new()));
+        assert_eq!(text(&outer), "loading!");
+    }
+
+    #[test]
+    fn refetch_suspends_again() {
+        let spinner = || element!("DIV", attributes![("class".to_string(), "spinner".to_string())], vec![Rsx::new_text("...".to_string())]);
+        let mut feed = Resource::Resolved("first".to_string());
+        let mut shown = vec![];
+        for next in [Resource::Pending, Resource::Resolved("second".to_string())] {
+            let boundary = suspense(|| vec![Rsx::Component(Comp {children: child(&feed)}), Rsx::new_text("!".to_string())], spinner());
+            shown.push(text(&boundary));
+            feed = next;
+        }
+        shown.push(text(&suspense(|| child(&feed), spinner())));
+        assert_eq!(shown, ["first!", "...", "second"]);
+    }
+}
@@ -0,0 +1,145 @@
+use std::cell::RefCell;
+
+use web_sys::{Document, Element};
+
+use crate::{Rsx, Elem, Attribute, attributes, element};
+
+pub const SVG_NS: &str = "http://www.w3.org/2000/svg";
+pub const XLINK_NS: &str = "http://www.w3.org/1999/xlink";
+
+pub const MATHML_NS: &str = "http://www.w3.org/1998/Math/MathML";
+
+thread_local! {
+    /// The namespace the children being created go in, one entry per open element.
+    static NAMESPACE: RefCell<Vec<Option<&'static str>>> = const { RefCell::new(vec![]) };
+}
+
+/// The namespace `name` is created in: `<svg>` and `<math>` start their own,
+/// other elements stay in their parent's.
+fn namespace(parent: Option<&'static str>, name: &str) -> Option<&'static str> {
+    if name.eq_ignore_ascii_case("svg") {
+        Some(SVG_NS)
+    } else if name.eq_ignore_ascii_case("math") {
+        Some(MATHML_NS)
+    } else {
+        parent
+    }
+}
+
+/// The namespace the children of `name` are created in. `<foreignObject>`
+/// switches back to HTML.
+fn child_namespace(ns: Option<&'static str>, name: &str) -> Option<&'static str> {
+    if name == "foreignObject" {
+        None
+    } else {
+        ns
+    }
+}
+
+/// Creates `name` in the SVG or MathML namespace when it opens or sits inside
+/// one, and marks its children to follow. `leave` must be called once they are created.
+pub(crate) fn create(document: &Document, name: &str) -> Element {
+    let parent = NAMESPACE.with(|s| s.borrow().last().copied().flatten());
+    let ns = namespace(parent, name);
+    NAMESPACE.with(|s| s.borrow_mut().push(child_namespace(ns, name)));
+    match ns {
+        Some(ns) => document.create_element_ns(Some(ns), name).unwrap(),
+        None => document.create_element(name).unwrap(),
+    }
+}
+
+pub(crate) fn leave() {
+    NAMESPACE.with(|s| s.borrow_mut().pop());
+}
+
+/// Sets an attribute, putting `xlink:` ones in the XLink namespace.
+pub(crate) fn set_attr(el: &Element, key: &str, value: &str) {
+    if key.starts_with("xlink:") {
+        el.set_attribute_ns(Some(XLINK_NS), key, value).unwrap();
+    } else {
+        el.set_attribute(key, value).unwrap();
+    }
+}
+
+pub(crate) fn remove_attr(el: &Element, key: &str) {
+    if let Some(local) = key.strip_prefix("xlink:") {
+        el.remove_attribute_ns(Some(XLINK_NS), local).unwrap();
+    } else {
+        el.remove_attribute(key).unwrap();
+    }
+}
+
+fn reference(sprite_id: &str) -> String {
+    if sprite_id.contains('#') {
+        sprite_id.to_string()
+    } else {
+        format!("#{}", sprite_id)
+    }
+}
+
+/// `<svg><use href="#id"/></svg>` for a symbol of an inline sprite, or of an
+/// external one when given as `sprite.svg#id`. The reference is set as both
+/// `href` and `xlink:href` for browsers that only know the latter.
+pub fn icon(sprite_id: &str) -> Rsx {
+    let href = reference(sprite_id);
+    let use_el = element!("use", attributes![("href".to_string(), href.clone()), ("xlink:href".to_string(), href)], vec![]);
+    element!("svg", attributes![("class".to_string(), "icon".to_string()), ("aria-hidden".to_string(), "true".to_string())], vec![use_el])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn icon_use() {
+        let svg = icon("arrow");
+        let svg = svg.as_elem();
+        assert_eq!(svg.name, "svg");
+        let use_el = svg.children[0].as_elem();
+        assert_eq!(use_el.name, "use");
+        let attrs: Vec<(&str, &str)> = use_el.attrs.iter().map(|a| (a.key.as_str(), a.value.as_str())).collect();
+        assert_eq!(attrs, [("href", "#arrow"), ("xlink:href", "#arrow")]);
+        assert_eq!(reference("/icons.svg#arrow"), "/icons.svg#arrow");
+    }
+
+    #[test]
+    fn nested_namespaces() {
+        let svg = namespace(None, "svg");
+        assert_eq!(svg, Some(SVG_NS));
+        assert_eq!(namespace(child_namespace(svg, "svg"), "circle"), Some(SVG_NS));
+        assert_eq!(namespace(child_namespace(svg, "foreignObject"), "DIV"), None);
+        assert_eq!(namespace(None, "DIV"), None);
+        let math = namespace(None, "math");
+        assert_eq!(namespace(child_namespace(math, "math"), "mi"), Some(MATHML_NS));
+    }
+}
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod dom_tests {
+    use wasm_bindgen_test::*;
+    use super::*;
+    use crate::DOCUMENT;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    fn icon_attached() {
+        DOCUMENT.with(|document| {
+            let page = document.create_element("div").unwrap();
+            page.set_inner_html(r#"<svg style="display: none"><symbol id="icon-arrow" viewBox="0 0 8 8"><path d="M0 4h8"/></symbol></svg>"#);
+            page.append_child(&icon("icon-arrow").to_node(document)).unwrap();
+            document.body().unwrap().append_child(&page).unwrap();
+            let svg = page.last_element_child().unwrap();
+            let use_el = svg.first_element_child().unwrap();
+            assert_eq!(svg.namespace_uri().as_deref(), Some(SVG_NS));
+            assert_eq!(use_el.namespace_uri().as_deref(), Some(SVG_NS));
+            assert_eq!(use_el.local_name(), "use");
+            let href = use_el.get_attribute_ns(Some(XLINK_NS), "href").unwrap();
+            assert_eq!(href, "#icon-arrow");
+            assert_eq!(use_el.get_attribute("href").as_ref(), Some(&href));
+            let symbol = document.query_selector(&href).unwrap().unwrap();
+            assert_eq!(symbol.local_name(), "symbol");
+            page.remove();
+        });
+    }
+}
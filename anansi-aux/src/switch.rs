@@ -0,0 +1,141 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+
+use wasm_bindgen::JsCast;
+use web_sys::{Element, Node};
+
+use crate::{Rsx, Elem, Attribute, Signal, attributes, element};
+
+thread_local! {
+    static PARKED: RefCell<HashMap<String, Elem>> = RefCell::new(HashMap::new());
+}
+
+const PREFIX: &str = "switch:";
+
+type Arm<'a> = Box<dyn FnOnce() -> Vec<Rsx> + 'a>;
+
+/// Renders one of several arms depending on a discriminant. Each arm is wrapped
+/// in a keyed `display: contents` element, so a change of discriminant swaps
+/// whole arms instead of morphing one into the other, and the DOM of an arm
+/// that was switched away from is parked and reused when it comes back.
+pub struct Switch<'a, T> {
+    name: &'a str,
+    value: T,
+    arms: Vec<(T, Arm<'a>)>,
+}
+
+impl<'a, T: PartialEq + Clone> Switch<'a, T> {
+    pub fn new(name: &'a str, value: &mut Signal<T>) -> Self {
+        Self {name, value: value.value().clone(), arms: vec![]}
+    }
+    pub fn arm(mut self, variant: T, f: impl FnOnce() -> Vec<Rsx> + 'a) -> Self {
+        self.arms.push((variant, Box::new(f)));
+        self
+    }
+    /// Finishes the switch, rendering `f` when no arm matched.
+    pub fn default(self, f: impl FnOnce() -> Vec<Rsx>) -> Rsx {
+        let value = self.value;
+        let (key, children) = match self.arms.into_iter().enumerate().find(|(_, (v, _))| *v == value) {
+            Some((n, (_, arm))) => (n.to_string(), arm()),
+            None => ("default".to_string(), f()),
+        };
+        let key = format!("{}{}:{}", PREFIX, self.name, key);
+        let arm = element!("DIV", attributes![("key".to_string(), key), ("style".to_string(), "display: contents".to_string())], children);
+        Rsx::new_keyed(vec![arm])
+    }
+}
+
+fn is_arm(elem: &Elem) -> bool {
+    elem.attrs.first().is_some_and(|a| a.value.starts_with(PREFIX))
+}
+
+/// Removes `child` from `parent`, keeping it for reuse when it is a switch arm.
+pub(crate) fn detach(parent: &Node, child: &Elem) {
+    park(child);
+    parent.remove_child(&child.node()).unwrap();
+}
+
+pub(crate) fn park(child: &Elem) {
+    if is_arm(child) {
+        PARKED.with(|p| p.borrow_mut().insert(child.attrs[0].value.clone(), child.clone()));
+    }
+}
+
+/// The parked DOM of the arm `elem` renders, brought up to date with it.
+pub(crate) fn unpark(elem: &mut Elem) -> Option<Node> {
+    if !is_arm(elem) {
+        return None;
+    }
+    let parked = PARKED.with(|p| p.borrow_mut().remove(&elem.attrs[0].value))?;
+    elem.kdiff(&parked);
+    Some(elem.node())
+}
+
+/// Adds the `rid`s inside parked arms, which are out of the document but still live.
+pub(crate) fn parked_rids(live: &mut HashSet<String>) {
+    PARKED.with(|p| {
+        for elem in p.borrow().values() {
+            let el = elem.el.as_ref().unwrap();
+            if let Some(rid) = el.get_attribute("rid") {
+                live.insert(rid);
+            }
+            let nodes = el.query_selector_all("[rid]").unwrap();
+            for i in 0..nodes.length() {
+                if let Some(rid) = nodes.get(i).unwrap().dyn_ref::<Element>().and_then(|el| el.get_attribute("rid")) {
+                    live.insert(rid);
+                }
+            }
+        }
+    });
+}
+
+/// Drops the parked arms of the switch `name`.
+pub fn clear_parked(name: &str) {
+    let prefix = format!("{}{}:", PREFIX, name);
+    PARKED.with(|p| p.borrow_mut().retain(|k, _| !k.starts_with(&prefix)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[derive(Clone, PartialEq)]
+    enum Tab {
+        Home,
+        Settings,
+        Profile,
+        Help,
+    }
+
+    fn arm(rsx: &Rsx) -> (String, String) {
+        let Rsx::Keyed(keys) = rsx else { unreachable!() };
+        assert_eq!(keys.children.len(), 1);
+        let elem = keys.children[0].as_elem();
+        let Rsx::Text(txt) = &elem.children[0] else { unreachable!() };
+        (elem.attrs[0].value.clone(), txt.text.clone())
+    }
+
+    #[test]
+    fn cycles_arms() {
+        let calls = Cell::new(0);
+        let render = |tab: Tab| {
+            let mut signal = Signal::new(tab);
+            let text = |s: &str| {
+                calls.set(calls.get() + 1);
+                vec![Rsx::new_text(s.to_string())]
+            };
+            arm(&Switch::new("tabs", &mut signal)
+                .arm(Tab::Home, || text("home"))
+                .arm(Tab::Settings, || text("settings"))
+                .arm(Tab::Profile, || text("profile"))
+                .default(|| vec![Rsx::new_text("missing".to_string())]))
+        };
+        assert_eq!(render(Tab::Home), ("switch:tabs:0".to_string(), "home".to_string()));
+        assert_eq!(render(Tab::Settings), ("switch:tabs:1".to_string(), "settings".to_string()));
+        assert_eq!(render(Tab::Profile), ("switch:tabs:2".to_string(), "profile".to_string()));
+        assert_eq!(calls.get(), 3);
+        assert_eq!(render(Tab::Help), ("switch:tabs:default".to_string(), "missing".to_string()));
+        assert_eq!(calls.get(), 3);
+    }
+}
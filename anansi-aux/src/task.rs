@@ -0,0 +1,161 @@
+use std::rc::Rc;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+
+use crate::component_index;
+
+thread_local! {
+    pub static TOKENS: RefCell<HashMap<String, CancellationToken>> = RefCell::new(HashMap::new());
+}
+
+/// The waker of each pending `Cancellable`, by the slot it was given.
+#[derive(Default)]
+struct TokenState {
+    cancelled: Cell<bool>,
+    wakers: RefCell<HashMap<u64, Waker>>,
+    next_slot: Cell<u64>,
+}
+
+#[derive(Clone, Default)]
+pub struct CancellationToken(Rc<TokenState>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn cancel(&self) {
+        if !self.0.cancelled.replace(true) {
+            let wakers: Vec<Waker> = self.0.wakers.borrow_mut().drain().map(|(_, w)| w).collect();
+            for waker in wakers {
+                waker.wake();
+            }
+        }
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.get()
+    }
+}
+
+/// Resolves to `None` as soon as `token` is cancelled, otherwise to the output of `future`.
+/// It keeps one waker with the token, replaced on each poll and dropped with it.
+pub struct Cancellable<F> {
+    token: CancellationToken,
+    future: Pin<Box<F>>,
+    slot: Option<u64>,
+}
+
+impl<F> Cancellable<F> {
+    fn release(&mut self) {
+        if let Some(slot) = self.slot.take() {
+            self.token.0.wakers.borrow_mut().remove(&slot);
+        }
+    }
+}
+
+impl<F> Unpin for Cancellable<F> {}
+
+impl<F: Future> Future for Cancellable<F> {
+    type Output = Option<F::Output>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.token.is_cancelled() {
+            self.release();
+            return Poll::Ready(None);
+        }
+        if let Poll::Ready(out) = self.future.as_mut().poll(cx) {
+            self.release();
+            return if self.token.is_cancelled() {
+                Poll::Ready(None)
+            } else {
+                Poll::Ready(Some(out))
+            };
+        }
+        let state = self.token.0.clone();
+        let slot = *self.slot.get_or_insert_with(|| state.next_slot.replace(state.next_slot.get() + 1));
+        state.wakers.borrow_mut().insert(slot, cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<F> Drop for Cancellable<F> {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+pub fn cancellable<F: Future>(token: &CancellationToken, future: F) -> Cancellable<F> {
+    Cancellable {token: token.clone(), future: Box::pin(future), slot: None}
+}
+
+/// The token of the component currently being called, cancelled when it unmounts.
+pub fn component_token() -> CancellationToken {
+    let index = component_index().unwrap_or_default();
+    TOKENS.with(|t| t.borrow_mut().entry(index).or_default().clone())
+}
+
+pub fn cancel_component(index: &str) {
+    if let Some(token) = TOKENS.with(|t| t.borrow_mut().remove(index)) {
+        token.cancel();
+    }
+}
+
+pub fn cancel_all() {
+    let tokens: Vec<CancellationToken> = TOKENS.with(|t| t.borrow_mut().drain().map(|(_, v)| v).collect());
+    for token in tokens {
+        token.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancel_mid_flight() {
+        let token = CancellationToken::new();
+        let ran = Rc::new(Cell::new(false));
+        let r = ran.clone();
+        let mut pending = true;
+        let fut = std::future::poll_fn(move |_| {
+            if pending {
+                pending = false;
+                Poll::Pending
+            } else {
+                Poll::Ready(())
+            }
+        });
+        let t = token.clone();
+        let mut task = Box::pin(async move {
+            if cancellable(&t, fut).await.is_some() {
+                r.set(true);
+            }
+        });
+        let mut cx = Context::from_waker(Waker::noop());
+        assert!(task.as_mut().poll(&mut cx).is_pending());
+        token.cancel();
+        assert!(task.as_mut().poll(&mut cx).is_ready());
+        assert!(!ran.get());
+    }
+
+    #[test]
+    fn one_waker_each() {
+        let token = CancellationToken::new();
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut first = cancellable(&token, std::future::pending::<()>());
+        let mut second = cancellable(&token, std::future::pending::<()>());
+        for _ in 0..3 {
+            assert!(Pin::new(&mut first).poll(&mut cx).is_pending());
+        }
+        assert_eq!(token.0.wakers.borrow().len(), 1);
+        assert!(Pin::new(&mut second).poll(&mut cx).is_pending());
+        assert_eq!(token.0.wakers.borrow().len(), 2);
+        drop(first);
+        assert_eq!(token.0.wakers.borrow().len(), 1);
+        token.cancel();
+        assert!(token.0.wakers.borrow().is_empty());
+        assert_eq!(Pin::new(&mut second).poll(&mut cx), Poll::Ready(None));
+    }
+}
@@ -0,0 +1,273 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+
+use wasm_bindgen::JsCast;
+use web_sys::{Document, Element, Node};
+
+use crate::{Rsx, Elem, check_recall, register_ref, set_prop, stamp_source};
+
+thread_local! {
+    pub(crate) static TEMPLATES: RefCell<HashMap<String, (Elem, Node)>> = RefCell::new(HashMap::new());
+    static ENABLED: Cell<bool> = const { Cell::new(true) };
+}
+
+/// Turns the template cache on or off, e.g. to compare render times.
+pub fn set_template_cache(enabled: bool) {
+    ENABLED.with(|e| e.set(enabled));
+    if !enabled {
+        clear_templates();
+    }
+}
+
+pub fn clear_templates() {
+    TEMPLATES.with(|t| t.borrow_mut().clear());
+}
+
+/// The parts of an element written at `site` that differ from one render to
+/// the next: the indices of its dynamic attributes and of its dynamic text
+/// children. The `component` macro emits these for elements whose children
+/// don't depend on control flow, so a clone only has its holes filled in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Holes {
+    pub site: &'static str,
+    pub attrs: &'static [usize],
+    pub texts: &'static [usize],
+}
+
+/// Writes the static structure of `elem` (tag names and attribute keys) into `key`.
+/// Returns false when the tree holds components or keyed lists, which are never cached.
+pub(crate) fn shape(elem: &Elem, key: &mut String) -> bool {
+    key.push_str(elem.name);
+    for attr in &elem.attrs {
+        key.push(' ');
+        key.push_str(&attr.key);
+    }
+    key.push('(');
+    for child in &elem.children {
+        match child {
+            Rsx::Element(el) => {
+                if !shape(el, key) {
+                    return false;
+                }
+            }
+            Rsx::Text(_) => key.push('#'),
+            _ => return false,
+        }
+    }
+    key.push(')');
+    true
+}
+
+/// Builds the node for `elem`, cloning a cached skeleton of the same shape when one
+/// exists and only writing the attributes, `meta` and text that differ from it, or
+/// just its holes when the macro marked them. Event attributes are always
+/// re-registered so each clone gets its own `rid`, and `data-anansi-src` labels
+/// are written afresh while `render_source` is set.
+pub(crate) fn instantiate(elem: &mut Elem, document: &Document) -> Node {
+    if let Some(node) = crate::switch::unpark(elem) {
+        return node;
+    }
+    let mut key = elem.holes.map(|h| format!("{}|", h.site)).unwrap_or_default();
+    if !ENABLED.with(|e| e.get()) || !shape(elem, &mut key) {
+        return elem.to_node(document);
+    }
+    let cached = TEMPLATES.with(|t| t.borrow().get(&key).map(|(tmpl, node)| (tmpl.clone(), node.clone_node_with_deep(true).unwrap())));
+    if let Some((tmpl, node)) = cached {
+        fill(elem, &tmpl, node.dyn_ref::<Element>().unwrap());
+        node
+    } else {
+        let node = elem.to_node(document);
+        let skeleton = node.clone_node_with_deep(true).unwrap();
+        TEMPLATES.with(|t| t.borrow_mut().insert(key, (elem.clone(), skeleton)));
+        node
+    }
+}
+
+fn fill(elem: &mut Elem, tmpl: &Elem, el: &Element) {
+    if !stamp_source(el) && el.has_attribute("data-anansi-src") {
+        el.remove_attribute("data-anansi-src").unwrap();
+    }
+    if elem.meta != tmpl.meta {
+        match &elem.meta {
+            Some(meta) => el.set_attribute("data-anansi-meta", meta).unwrap(),
+            None => el.remove_attribute("data-anansi-meta").unwrap(),
+        }
+    }
+    let holes = elem.holes;
+    for (n, (attr, old)) in elem.attrs.iter().zip(&tmpl.attrs).enumerate() {
+        if let Some(prop) = attr.key.strip_prefix("prop:") {
+            set_prop(el, prop, &attr.value);
+            continue;
+        }
+        let dynamic = holes.is_none_or(|h| h.attrs.contains(&n));
+        debug_assert!(dynamic || attr.value == old.value, "static attribute {} changed at {:?}", attr.key, holes);
+        if dynamic && attr.value != old.value {
+            crate::svg::set_attr(el, &attr.key, &attr.value);
+        }
+        if attr.key == "ref" {
+            register_ref(&attr.value, el);
+        }
+        check_recall(el, attr);
+    }
+    let nodes = el.child_nodes();
+    for (n, (child, old)) in elem.children.iter_mut().zip(&tmpl.children).enumerate() {
+        let node = nodes.get(n as u32).unwrap();
+        match (child, old) {
+            (Rsx::Element(child), Rsx::Element(old)) => fill(child, old, node.dyn_ref::<Element>().unwrap()),
+            (Rsx::Text(txt), Rsx::Text(old)) => {
+                let dynamic = holes.is_none_or(|h| h.texts.contains(&n));
+                debug_assert!(dynamic || txt.text == old.text, "static text changed at {:?}", holes);
+                if dynamic && txt.text != old.text {
+                    node.set_node_value(Some(&txt.text));
+                }
+                txt.node = Some(node.dyn_into().unwrap());
+            }
+            _ => unreachable!(),
+        }
+    }
+    elem.el = Some(el.clone());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Attribute, attributes, element};
+
+    #[test]
+    fn shape_keys() {
+        let row = |label: &str| element!("LI", attributes![("class".to_string(), label.to_string())], vec![Rsx::new_text(label.to_string())]);
+        let (mut a, mut b) = (String::new(), String::new());
+        assert!(shape(row("x").as_elem(), &mut a));
+        assert!(shape(row("y").as_elem(), &mut b));
+        assert_eq!(a, b);
+        let keyed = element!("UL", vec![], vec![Rsx::new_keyed(vec![])]);
+        assert!(!shape(keyed.as_elem(), &mut String::new()));
+    }
+}
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod dom_tests {
+    use wasm_bindgen_test::*;
+    use super::*;
+    use crate::{Attribute, DOCUMENT, element};
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    fn row(meta: Option<&str>) -> Rsx {
+        let mut row = element!("LI", vec![], vec![element!("SPAN", vec![], vec![Rsx::new_text("x".to_string())])]);
+        if let Rsx::Element(el) = &mut row {
+            el.meta = meta.map(|m| m.to_string());
+            if let Some(Rsx::Element(span)) = el.children.first_mut() {
+                span.meta = meta.map(|m| format!("{}:span", m));
+            }
+        }
+        row
+    }
+
+    fn build(meta: Option<&str>) -> Element {
+        let mut row = row(meta);
+        let Rsx::Element(elem) = &mut row else { unreachable!() };
+        DOCUMENT.with(|document| instantiate(elem, document)).dyn_into().unwrap()
+    }
+
+    #[wasm_bindgen_test]
+    fn meta_per_instance() {
+        set_template_cache(true);
+        clear_templates();
+        let a = build(Some("list.rs:3"));
+        let b = build(Some("list.rs:9"));
+        let none = build(None);
+        assert_eq!(TEMPLATES.with(|t| t.borrow().len()), 1);
+        assert_eq!(a.get_attribute("data-anansi-meta").as_deref(), Some("list.rs:3"));
+        assert_eq!(b.get_attribute("data-anansi-meta").as_deref(), Some("list.rs:9"));
+        assert_eq!(b.first_element_child().unwrap().get_attribute("data-anansi-meta").as_deref(), Some("list.rs:9:span"));
+        assert!(!none.has_attribute("data-anansi-meta"));
+        assert!(!none.first_element_child().unwrap().has_attribute("data-anansi-meta"));
+    }
+
+    #[wasm_bindgen_test]
+    fn cached_with_source() {
+        set_template_cache(true);
+        clear_templates();
+        let plain = build(None);
+        assert!(!plain.has_attribute("data-anansi-src"));
+        // Marks the skeleton, so that the rows below show they were cloned from it.
+        TEMPLATES.with(|t| t.borrow().values().for_each(|(_, node)| node.dyn_ref::<Element>().unwrap().set_attribute("data-cloned", "").unwrap()));
+        crate::RENDER_SOURCE.with(|s| *s.borrow_mut() = Some(("List", 0)));
+        let (a, b) = (build(None), build(None));
+        crate::RENDER_SOURCE.with(|s| s.borrow_mut().take());
+        assert!(a.has_attribute("data-cloned") && b.has_attribute("data-cloned"));
+        assert_eq!(a.get_attribute("data-anansi-src").as_deref(), Some("List:0"));
+        assert_eq!(a.first_element_child().unwrap().get_attribute("data-anansi-src").as_deref(), Some("List:1"));
+        assert_eq!(b.get_attribute("data-anansi-src").as_deref(), Some("List:2"));
+        clear_templates();
+        crate::RENDER_SOURCE.with(|s| *s.borrow_mut() = Some(("List", 0)));
+        build(None);
+        crate::RENDER_SOURCE.with(|s| s.borrow_mut().take());
+        let after = build(None);
+        assert!(!after.has_attribute("data-anansi-src"));
+        assert!(!after.first_element_child().unwrap().has_attribute("data-anansi-src"));
+    }
+
+    fn marked(n: usize) -> Element {
+        let attrs = vec![Attribute {key: "class".to_string(), value: "row".to_string()}, Attribute {key: "title".to_string(), value: format!("row {}", n)}];
+        let mut row = element!("LI", attrs, vec![Rsx::new_text("Row".to_string()), Rsx::new_text(n.to_string())]).with_holes("list:0", &[1], &[1]);
+        let Rsx::Element(elem) = &mut row else { unreachable!() };
+        DOCUMENT.with(|document| instantiate(elem, document)).dyn_into().unwrap()
+    }
+
+    #[wasm_bindgen_test]
+    fn holes_filled() {
+        set_template_cache(true);
+        clear_templates();
+        marked(0);
+        // Changes the static parts of the skeleton, which clones keep since only holes are written.
+        TEMPLATES.with(|t| t.borrow().values().for_each(|(_, node)| {
+            let el = node.dyn_ref::<Element>().unwrap();
+            el.set_attribute("class", "skeleton").unwrap();
+            el.first_child().unwrap().set_node_value(Some("Skeleton"));
+        }));
+        let row = marked(7);
+        assert_eq!(row.get_attribute("class").as_deref(), Some("skeleton"));
+        assert_eq!(row.get_attribute("title").as_deref(), Some("row 7"));
+        assert_eq!(row.first_child().unwrap().node_value().as_deref(), Some("Skeleton"));
+        assert_eq!(row.last_child().unwrap().node_value().as_deref(), Some("7"));
+        assert!(TEMPLATES.with(|t| t.borrow().keys().all(|key| key.starts_with("list:0|"))));
+    }
+
+    fn rows(marked: bool) -> (f64, String) {
+        let list = DOCUMENT.with(|document| document.create_element("ul").unwrap());
+        let start = js_sys::Date::now();
+        for n in 0..1000 {
+            let children = vec![element!("SPAN", vec![Attribute {key: "class".to_string(), value: "id".to_string()}], vec![Rsx::new_text(n.to_string())]), element!("A", vec![Attribute {key: "href".to_string(), value: format!("/rows/{}", n)}], vec![Rsx::new_text(format!("row {}", n))])];
+            let mut row = element!("LI", vec![Attribute {key: "class".to_string(), value: "row".to_string()}], children);
+            if marked {
+                if let Rsx::Element(elem) = &mut row {
+                    elem.children = elem.children.drain(..).enumerate().map(|(i, child)| match i {
+                        0 => child.with_holes("bench:0", &[], &[0]),
+                        _ => child.with_holes("bench:1", &[0], &[0]),
+                    }).collect();
+                }
+                row = row.with_holes("bench:2", &[], &[]);
+            }
+            let Rsx::Element(elem) = &mut row else { unreachable!() };
+            let node = DOCUMENT.with(|document| instantiate(elem, document));
+            list.append_child(&node).unwrap();
+        }
+        (js_sys::Date::now() - start, list.inner_html())
+    }
+
+    #[wasm_bindgen_test]
+    fn bench_thousand_rows() {
+        set_template_cache(false);
+        let (uncached, expected) = rows(false);
+        set_template_cache(true);
+        clear_templates();
+        let (cached, html) = rows(false);
+        assert_eq!(html, expected);
+        clear_templates();
+        let (marked, html) = rows(true);
+        assert_eq!(html, expected);
+        web_sys::console::log_1(&format!("1000 rows: {}ms uncached, {}ms cached, {}ms cached with holes", uncached, cached, marked).into());
+    }
+}
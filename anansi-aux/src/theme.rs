@@ -0,0 +1,69 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+
+use wasm_bindgen::JsCast;
+use web_sys::HtmlElement;
+
+use crate::{DOCUMENT, Signal};
+
+thread_local! {
+    static APPLIED: RefCell<BTreeMap<String, String>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+fn property(name: &str) -> String {
+    if name.starts_with("--") {
+        name.to_string()
+    } else {
+        format!("--{}", name)
+    }
+}
+
+/// The properties to set and the ones to remove to go from `applied` to `vars`.
+fn theme_diff(applied: &BTreeMap<String, String>, vars: &HashMap<String, String>) -> (Vec<(String, String)>, Vec<String>) {
+    let vars: BTreeMap<String, &String> = vars.iter().map(|(k, v)| (property(k), v)).collect();
+    let set = vars.iter().filter(|(k, v)| applied.get(*k) != Some(**v)).map(|(k, v)| (k.clone(), v.to_string())).collect();
+    let remove = applied.keys().filter(|k| !vars.contains_key(*k)).cloned().collect();
+    (set, remove)
+}
+
+/// Writes each entry of `vars` as a CSS custom property on `:root`, with or
+/// without the leading `--`. Called while rendering, it follows the signal on
+/// every rerender. Properties that leave the map are removed, so rules reading
+/// them fall back to their `var()` default or the inherited value.
+pub fn use_theme(vars: &mut Signal<HashMap<String, String>>) {
+    let (set, remove) = APPLIED.with(|a| theme_diff(&a.borrow(), vars.value()));
+    if set.is_empty() && remove.is_empty() {
+        return;
+    }
+    DOCUMENT.with(|document| {
+        let root = document.document_element().expect("expected document element");
+        let style = root.dyn_ref::<HtmlElement>().expect("expected html element").style();
+        APPLIED.with(|a| {
+            let mut applied = a.borrow_mut();
+            for name in remove {
+                style.remove_property(&name).unwrap();
+                applied.remove(&name);
+            }
+            for (name, value) in set {
+                style.set_property(&name, &value).unwrap();
+                applied.insert(name, value);
+            }
+        });
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn theme_changes() {
+        let mut applied = BTreeMap::new();
+        applied.insert("--bg".to_string(), "white".to_string());
+        applied.insert("--fg".to_string(), "black".to_string());
+        let vars = HashMap::from([("bg".to_string(), "black".to_string()), ("--accent".to_string(), "teal".to_string())]);
+        let (set, remove) = theme_diff(&applied, &vars);
+        assert_eq!(set, vec![("--accent".to_string(), "teal".to_string()), ("--bg".to_string(), "black".to_string())]);
+        assert_eq!(remove, vec!["--fg".to_string()]);
+    }
+}
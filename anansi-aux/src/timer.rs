@@ -0,0 +1,190 @@
+use std::rc::Rc;
+use std::cell::Cell;
+
+use crate::on_unmount;
+
+/// Starts and stops the timeouts behind `Debounced` and `Throttled`.
+trait Clock {
+    fn start(&self, ms: i32, f: Box<dyn FnOnce()>) -> i32;
+    fn stop(&self, id: i32);
+}
+
+/// The window's `setTimeout`.
+struct Window;
+
+impl Clock for Window {
+    fn start(&self, ms: i32, f: Box<dyn FnOnce()>) -> i32 {
+        use wasm_bindgen::JsCast;
+        let cb = wasm_bindgen::closure::Closure::once_into_js(f);
+        crate::WINDOW.with(|w| w.set_timeout_with_callback_and_timeout_and_arguments_0(cb.unchecked_ref(), ms).unwrap())
+    }
+    fn stop(&self, id: i32) {
+        crate::WINDOW.with(|w| w.clear_timeout_with_handle(id));
+    }
+}
+
+struct Timer {
+    clock: Rc<dyn Clock>,
+    ms: i32,
+    f: Box<dyn Fn()>,
+    id: Cell<Option<i32>>,
+    pending: Cell<bool>,
+}
+
+impl Timer {
+    fn new(clock: Rc<dyn Clock>, ms: u32, f: impl Fn() + 'static) -> Rc<Self> {
+        let timer = Rc::new(Self {clock, ms: ms as i32, f: Box::new(f), id: Cell::new(None), pending: Cell::new(false)});
+        let t = Rc::downgrade(&timer);
+        on_unmount(move || {
+            if let Some(t) = t.upgrade() {
+                t.cancel();
+            }
+        });
+        timer
+    }
+    fn start(self: &Rc<Self>, fired: fn(&Rc<Self>)) {
+        let t = Rc::downgrade(self);
+        let id = self.clock.start(self.ms, Box::new(move || {
+            if let Some(t) = t.upgrade() {
+                t.id.set(None);
+                fired(&t);
+            }
+        }));
+        self.id.set(Some(id));
+    }
+    fn cancel(&self) {
+        self.pending.set(false);
+        if let Some(id) = self.id.take() {
+            self.clock.stop(id);
+        }
+    }
+}
+
+/// A callback wrapper that runs `f` once calls have stopped for `ms`
+/// milliseconds, e.g. to search only after the user pauses typing. Create it
+/// once, not in the callback body, and `call` it from there.
+#[derive(Clone)]
+pub struct Debounced(Rc<Timer>);
+
+pub fn debounce(ms: u32, f: impl Fn() + 'static) -> Debounced {
+    Debounced(Timer::new(Rc::new(Window), ms, f))
+}
+
+impl Debounced {
+    /// Restarts the wait, dropping the invocation an earlier call scheduled.
+    pub fn call(&self) {
+        self.0.cancel();
+        self.0.start(|t| (t.f)());
+    }
+    /// Drops the pending invocation, as unmounting the component does.
+    pub fn cancel(&self) {
+        self.0.cancel();
+    }
+    pub fn is_pending(&self) -> bool {
+        self.0.id.get().is_some()
+    }
+}
+
+/// A callback wrapper that runs `f` at most once per `ms` milliseconds: at the
+/// first call, then once more at the end of the window if it was called again.
+#[derive(Clone)]
+pub struct Throttled(Rc<Timer>);
+
+pub fn throttle(ms: u32, f: impl Fn() + 'static) -> Throttled {
+    Throttled(Timer::new(Rc::new(Window), ms, f))
+}
+
+fn cooled(t: &Rc<Timer>) {
+    if t.pending.replace(false) {
+        (t.f)();
+        t.start(cooled);
+    }
+}
+
+impl Throttled {
+    pub fn call(&self) {
+        if self.0.id.get().is_some() {
+            self.0.pending.set(true);
+        } else {
+            (self.0.f)();
+            self.0.start(cooled);
+        }
+    }
+    /// Drops the trailing invocation, as unmounting the component does.
+    pub fn cancel(&self) {
+        self.0.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    type Due = (i32, Box<dyn FnOnce()>);
+
+    /// Timers that only fire when the test says so.
+    #[derive(Default)]
+    struct Manual {
+        due: RefCell<Vec<Due>>,
+        next: Cell<i32>,
+    }
+
+    impl Clock for Manual {
+        fn start(&self, _ms: i32, f: Box<dyn FnOnce()>) -> i32 {
+            let id = self.next.replace(self.next.get() + 1);
+            self.due.borrow_mut().push((id, f));
+            id
+        }
+        fn stop(&self, id: i32) {
+            self.due.borrow_mut().retain(|(i, _)| *i != id);
+        }
+    }
+
+    impl Manual {
+        /// Fires every timer started so far, as if their windows had all passed.
+        fn elapse(&self) {
+            let due = self.due.take();
+            for (_, f) in due {
+                f();
+            }
+        }
+    }
+
+    #[test]
+    fn rapid_calls_collapse() {
+        let count = Rc::new(Cell::new(0));
+        let c = count.clone();
+        let clock = Rc::new(Manual::default());
+        let search = Debounced(Timer::new(clock.clone(), 300, move || c.set(c.get() + 1)));
+        for _ in 0..5 {
+            search.call();
+        }
+        assert!(search.is_pending());
+        assert_eq!(count.get(), 0);
+        clock.elapse();
+        assert_eq!(count.get(), 1);
+        search.call();
+        search.cancel();
+        clock.elapse();
+        assert_eq!(count.get(), 1);
+    }
+
+    #[test]
+    fn throttle_leading_and_trailing() {
+        let count = Rc::new(Cell::new(0));
+        let c = count.clone();
+        let clock = Rc::new(Manual::default());
+        let scroll = Throttled(Timer::new(clock.clone(), 100, move || c.set(c.get() + 1)));
+        scroll.call();
+        scroll.call();
+        scroll.call();
+        assert_eq!(count.get(), 1);
+        clock.elapse();
+        assert_eq!(count.get(), 2);
+        clock.elapse();
+        assert_eq!(count.get(), 2);
+        scroll.call();
+        assert_eq!(count.get(), 3);
+    }
+}
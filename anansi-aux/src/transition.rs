@@ -0,0 +1,91 @@
+use std::cell::RefCell;
+
+use wasm_bindgen::JsCast;
+use wasm_bindgen::closure::Closure;
+use web_sys::{Element, HtmlElement};
+
+use crate::WINDOW;
+
+thread_local! {
+    static PENDING: RefCell<Vec<TransitionGroup>> = const { RefCell::new(vec![]) };
+}
+
+/// Animates the children of a keyed list from their old positions to their new
+/// ones (FLIP). Items that are added or removed in the same rerender are left
+/// to the enter class and are not moved.
+pub struct TransitionGroup {
+    rects: Vec<(Element, f64, f64)>,
+    duration: u32,
+}
+
+impl TransitionGroup {
+    /// Records the position of each child of `container`. Call it before
+    /// reordering the list; the group plays once the next rerender has patched it.
+    pub fn capture(container: &Element, duration: u32) {
+        let children = container.children();
+        let mut rects = vec![];
+        for i in 0..children.length() {
+            let el = children.item(i).unwrap();
+            let rect = el.get_bounding_client_rect();
+            rects.push((el, rect.left(), rect.top()));
+        }
+        PENDING.with(|p| p.borrow_mut().push(Self {rects, duration}));
+    }
+    fn play(self) {
+        let mut moved = vec![];
+        for (el, left, top) in self.rects {
+            if !el.is_connected() {
+                continue;
+            }
+            let rect = el.get_bounding_client_rect();
+            if let Some(transform) = invert((left, top), (rect.left(), rect.top())) {
+                let html = el.dyn_into::<HtmlElement>().unwrap();
+                let style = html.style();
+                style.set_property("transition", "none").unwrap();
+                style.set_property("transform", &transform).unwrap();
+                moved.push(html);
+            }
+        }
+        if moved.is_empty() {
+            return;
+        }
+        let transition = format!("transform {}ms", self.duration);
+        let cb = Closure::once_into_js(move || {
+            for html in moved {
+                let style = html.style();
+                style.set_property("transition", &transition).unwrap();
+                style.remove_property("transform").unwrap();
+            }
+        });
+        WINDOW.with(|w| w.request_animation_frame(cb.unchecked_ref()).unwrap());
+    }
+}
+
+/// The transform that puts an element moved from `old` to `new` back at `old`.
+fn invert(old: (f64, f64), new: (f64, f64)) -> Option<String> {
+    let (dx, dy) = (old.0 - new.0, old.1 - new.1);
+    if dx == 0.0 && dy == 0.0 {
+        None
+    } else {
+        Some(format!("translate({}px, {}px)", dx, dy))
+    }
+}
+
+pub(crate) fn play_pending() {
+    let groups = PENDING.with(|p| std::mem::take(&mut *p.borrow_mut()));
+    for group in groups {
+        group.play();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inverted_delta() {
+        assert_eq!(invert((0.0, 40.0), (0.0, 0.0)), Some("translate(0px, 40px)".to_string()));
+        assert_eq!(invert((10.0, 0.0), (25.5, 0.0)), Some("translate(-15.5px, 0px)".to_string()));
+        assert_eq!(invert((3.0, 4.0), (3.0, 4.0)), None);
+    }
+}
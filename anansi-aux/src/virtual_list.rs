@@ -0,0 +1,95 @@
+use std::ops::Range;
+
+use web_sys::Element;
+
+use crate::{Rsx, Elem, Attribute, RefVec, Signal, attributes, element};
+
+/// Renders only the rows of a long list that fall inside the scrolled viewport,
+/// plus `overscan` rows on each side. Row heights start at `estimate` and are
+/// corrected with `measure` once rendered, so rows may vary in height.
+pub struct VirtualList {
+    heights: Vec<f64>,
+    estimate: f64,
+    overscan: usize,
+    viewport: f64,
+}
+
+impl VirtualList {
+    pub fn new(estimate: f64, viewport: f64, overscan: usize) -> Self {
+        Self {heights: vec![], estimate, overscan, viewport}
+    }
+    pub fn set_viewport(&mut self, viewport: f64) {
+        self.viewport = viewport;
+    }
+    fn resize(&mut self, len: usize) {
+        self.heights.resize(len, self.estimate);
+    }
+    pub fn measure(&mut self, n: usize, height: f64) {
+        if n >= self.heights.len() {
+            self.resize(n + 1);
+        }
+        self.heights[n] = height;
+    }
+    pub fn offset(&self, n: usize) -> f64 {
+        self.heights[..n.min(self.heights.len())].iter().sum()
+    }
+    pub fn total_height(&self) -> f64 {
+        self.heights.iter().sum()
+    }
+    /// The indices to render for `len` rows scrolled to `scroll_top`.
+    pub fn range(&mut self, len: usize, scroll_top: f64) -> Range<usize> {
+        self.resize(len);
+        let mut y = 0.0;
+        let mut start = len;
+        let mut end = len;
+        for (n, h) in self.heights.iter().enumerate() {
+            if start == len && y + h > scroll_top {
+                start = n;
+            }
+            if y >= scroll_top + self.viewport {
+                end = n;
+                break;
+            }
+            y += h;
+        }
+        start.saturating_sub(self.overscan)..(end + self.overscan).min(len)
+    }
+    /// Renders the visible rows of `items` between two spacers that keep the
+    /// scroll height of the full list.
+    pub fn render<T>(&mut self, items: &RefVec<T>, scroll_top: &mut Signal<f64>, row: impl Fn(usize, &T) -> Rsx) -> Vec<Rsx> {
+        let range = self.range(items.len(), *scroll_top.value());
+        let spacer = |height: f64| element!("DIV", attributes![("style".to_string(), format!("height: {}px", height))], vec![]);
+        let mut v = vec![spacer(self.offset(range.start))];
+        let mut rows = vec![];
+        for n in range.clone() {
+            rows.push(row(n, &items.inner()[n].borrow()));
+        }
+        v.push(Rsx::new_keyed(rows));
+        v.push(spacer(self.total_height() - self.offset(range.end)));
+        v
+    }
+}
+
+/// Copies the container's scroll offset into `scroll_top`, e.g. from a scroll handler.
+pub fn read_scroll(container: &Element, scroll_top: &mut Signal<f64>) {
+    let top = container.scroll_top() as f64;
+    if *scroll_top.value() != top {
+        *scroll_top.value_mut() = top;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn visible_window() {
+        let mut list = VirtualList::new(20.0, 100.0, 2);
+        assert_eq!(list.range(1000, 0.0), 0..7);
+        assert_eq!(list.range(1000, 400.0), 18..27);
+        list.measure(20, 200.0);
+        assert_eq!(list.range(1000, 400.0), 18..23);
+        assert_eq!(list.offset(21), 20.0 * 20.0 + 200.0);
+        assert_eq!(list.range(10, 150.0), 5..10);
+    }
+}
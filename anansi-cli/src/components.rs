@@ -271,7 +271,7 @@ fn parse_component(split: &str, path: &PathBuf, fn_comp: bool) {
                                         let vs = quote! {#var}.to_string();
                                         let (ty, rn) = local.get(&vs).expect("unexpected variable");
                                         scope_vars.push(quote! {
-                                            let mut #var = _scope[#n].rf.borrow_mut();
+                                            let mut #var = _scope[#n].borrow_mut();
                                             let #var = #var.downcast_mut::<#ty>().expect("problem restoring variable");
                                         });
                                         ref_nums.push(*rn);
@@ -293,21 +293,23 @@ fn parse_component(split: &str, path: &PathBuf, fn_comp: bool) {
                                                 #(#scope_vars)*
                                                 #block
                                             };
-                                            anansi_aux::APP_STATE.with(|state| {
-                                                let state = state.borrow();
-                                                let state = state.as_ref().expect("problem borrowing state");
+                                            anansi_aux::with_state(|state| {
                                                 #res_match(state, Resource::Pending, #res_num);
-                                            });
+                                            }).expect("problem borrowing state");
                                             #comp_set_render();
+                                            let _token = anansi_aux::task::component_token();
                                             wasm_bindgen_futures::spawn_local(async move {
-                                                let text = req.send().await;
+                                                let text = anansi_aux::task::cancellable(&_token, async {
+                                                    match req.send().await {
+                                                        Ok(r) => r.text().await.or_else(|e| Err(Box::new(e) as Box<dyn std::error::Error>)),
+                                                        Err(e) => Err(Box::new(e) as Box<dyn std::error::Error>),
+                                                    }
+                                                }).await;
                                                 let text = match text {
-                                                    Ok(r) => r.text().await.or_else(|e| Err(Box::new(e) as Box<dyn std::error::Error>)),
-                                                    Err(e) => Err(Box::new(e) as Box<dyn std::error::Error>),
+                                                    Some(t) => t,
+                                                    None => return,
                                                 };
-                                                anansi_aux::APP_STATE.with(|state| {
-                                                    let mut state = state.borrow_mut();
-                                                    let state = state.as_mut().expect("problem borrowing state");
+                                                anansi_aux::with_state(|state| {
                                                     let _resource = match text {
                                                         Ok(t) => {
                                                             match serde_json::from_str::<#ty>(&t) {
@@ -318,7 +320,7 @@ fn parse_component(split: &str, path: &PathBuf, fn_comp: bool) {
                                                         Err(e) => Resource::Rejected(e),
                                                     };
                                                     #res_match(state, _resource, #res_num);
-                                                });
+                                                }).expect("problem borrowing state");
                                                 #comp_set_render();
                                             });
                                         }
@@ -385,7 +387,7 @@ fn parse_component(split: &str, path: &PathBuf, fn_comp: bool) {
                     let (s, _) = s.rsplit_once('}').unwrap();
 
                     let lower = component.to_string().to_lowercase();
-                    let mut c_parser = CompParser {start: vec![], events: vec![], callbacks: vec![], rchildren: HashMap::new(), refs: comp_refs.clone(), in_resource: false, lower_comp: lower.clone(), in_block: false, in_element: false, res_types: resource_types.clone(), resource_calls: vec![], res_fn: vec![], selectors: selectors.clone(), comp_rsx_ids: vec![], restart_comp_rsx_ids: vec![], local: local.clone()};
+                    let mut c_parser = CompParser {start: vec![], events: vec![], callbacks: vec![], rchildren: HashMap::new(), refs: comp_refs.clone(), in_resource: false, lower_comp: lower.clone(), in_block: false, in_element: false, res_types: resource_types.clone(), resource_calls: vec![], res_fn: vec![], selectors: selectors.clone(), comp_rsx_ids: vec![], restart_comp_rsx_ids: vec![], local: local.clone(), frames: vec![], dynamic_attrs: vec![], sites: 0};
                     let c_parsed = c_parser.parse_rsx(&s);
                     comp_rsx_ids = c_parser.comp_rsx_ids;
                     restart_comp_rsx_ids = c_parser.restart_comp_rsx_ids;
@@ -416,7 +418,7 @@ fn parse_component(split: &str, path: &PathBuf, fn_comp: bool) {
         let (ty, n) = local.get(&name).expect("problem getting type for rendering function");
         let ident = format_ident!("{}", name);
         lexical_scope.push(quote! {
-            let mut #ident = _scope[#lex_n].rf.borrow_mut();
+            let mut #ident = _scope[#lex_n].borrow_mut();
             let #ident = #ident.downcast_mut::<#ty>().expect("problem restoring variable");
         });
         set_render_idx.push(quote! {#n.to_string()});
@@ -432,20 +434,28 @@ fn parse_component(split: &str, path: &PathBuf, fn_comp: bool) {
                     *ids.borrow_mut() = v;
                 });
                 let _rsx = #comp_render();
-                anansi_aux::rerender(_rsx);
+                anansi_aux::render_source(#cs);
+                anansi_aux::rerender_batched(_rsx);
             }
         }
     } else if !callbacks.is_empty() {
         quote! {
             fn #comp_set_render() {
                 let _rsx = #comp_render();
-                anansi_aux::rerender(_rsx);
+                anansi_aux::render_source(#cs);
+                anansi_aux::rerender_batched(_rsx);
             }
         }
     } else {
         quote! {}
     };
 
+    let set_renderer = if set_ids.is_empty() {
+        quote! {}
+    } else {
+        quote! { anansi_aux::set_renderer(#comp_set_render); }
+    };
+
     let ls = if !lexical_scope.is_empty() {
         quote! { let mut _scope = anansi_aux::lexical_scope(); }
     } else {
@@ -539,48 +549,32 @@ fn parse_component(split: &str, path: &PathBuf, fn_comp: bool) {
             });
         };
     }
+    let load_state = quote! {
+        let node_id = anansi_aux::NODE_ID.with(|n| n.borrow().clone());
+        let _loaded = anansi_aux::mount_state(&node_id);
+    };
     let c_init = if set_scope.is_empty() {
-        quote! {
-            anansi_aux::APP_STATE.with(|a| {
-                let mut app_state = a.borrow_mut();
-                if app_state.is_none() {
-                    let mut contexts = std::collections::HashMap::new();
-                    anansi_aux::DOCUMENT.with(|document| {
-                        *app_state = anansi_aux::get_state(&document, &mut contexts);
-                    });
-                    anansi_aux::CTX.with(|c| *c.borrow_mut() = contexts);
-                }
-            });
-        }
+        load_state
     } else {
         quote! {
-            anansi_aux::APP_STATE.with(|a| {
-                let mut app_state = a.borrow_mut();
-                let store = if let Some(state) = app_state.as_mut() {
-                    state
-                } else {
-                    let mut contexts = std::collections::HashMap::new();
-                    anansi_aux::DOCUMENT.with(|document| {
-                        *app_state = anansi_aux::get_state(&document, &mut contexts);
-                    });
-                    anansi_aux::CTX.with(|c| *c.borrow_mut() = contexts);
-                    app_state.as_mut().unwrap()
-                };
-
-                #(#set_scope)*
-            });
+            #load_state
+            if _loaded {
+                anansi_aux::with_state(|store| {
+                    #(#set_scope)*
+                });
+            }
         }
     };
     let restart_prop = if props != "_props" {
         quote! {
-            anansi_aux::APP_STATE.with(|app_state| {
-                let mut app_state = app_state.borrow_mut();
-                let app_state = app_state.as_mut().expect("problem getting app state");
-                if let anansi_aux::Obj::Rs(p) = &mut app_state.objs_mut()[0] {
-                    let mut p = p.borrow_mut();
-                    *p.downcast_mut::<#properties>().expect("problem restoring prop") = #props;
-                } else {
-                    panic!("expected Rust type");
+            anansi_aux::with_state(|app_state| {
+                match app_state.objs_mut().first_mut() {
+                    Some(anansi_aux::Obj::Rs(p)) => {
+                        let mut p = p.borrow_mut();
+                        *p.downcast_mut::<#properties>().expect("problem restoring prop") = #props;
+                    }
+                    Some(_) => panic!("expected Rust type"),
+                    None => {}
                 }
             });
         }
@@ -617,6 +611,7 @@ fn parse_component(split: &str, path: &PathBuf, fn_comp: bool) {
         pub fn #comp_mount(_node_id: String) {
             #comp_rsx_init
             #c_init
+            #set_renderer
             
             #use_styles
             #ev
@@ -709,6 +704,102 @@ fn collect_str(chrs: &mut Chars) -> Option<String> {
     Some(s)
 }
 
+/// Turns inline component props such as `name="x" count={5} @title` into the
+/// builder calls that assemble the component's `Properties`.
+pub fn prop_list(args: &str) -> String {
+    let mut list = String::new();
+    let mut chars = args.trim().trim_end_matches('/').chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let mut name = String::new();
+        while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != '=') {
+            name.push(c);
+        }
+        if name.is_empty() {
+            break;
+        }
+        if let Some(var) = name.strip_prefix('@') {
+            list.push_str(&format!(".{var}({var})"));
+            continue;
+        }
+        chars.next();
+        let value = match chars.peek() {
+            Some('"') => {
+                let mut lit = String::from(chars.next().unwrap());
+                while let Some(c) = chars.next() {
+                    lit.push(c);
+                    if c == '\\' {
+                        lit.extend(chars.next());
+                    } else if c == '"' {
+                        break;
+                    }
+                }
+                lit
+            }
+            Some('{') => {
+                chars.next();
+                let mut depth = 1;
+                let mut expr = String::new();
+                for c in chars.by_ref() {
+                    match c {
+                        '{' => depth += 1,
+                        '}' => {
+                            depth -= 1;
+                            if depth == 0 {
+                                break;
+                            }
+                        }
+                        _ => {}
+                    }
+                    expr.push(c);
+                }
+                expr
+            }
+            _ => {
+                let mut expr = String::new();
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    expr.push(c);
+                }
+                expr.trim_start_matches('@').to_string()
+            }
+        };
+        list.push_str(&format!(".{}({})", name, value.trim()));
+    }
+    list
+}
+
+fn captured(tokens: TokenStream, is_var: &impl Fn(&str) -> bool, vars: &mut Vec<String>) {
+    for token in tokens {
+        match token {
+            proc_macro2::TokenTree::Ident(id) => {
+                let id = id.to_string();
+                if is_var(&id) && !vars.contains(&id) {
+                    vars.push(id);
+                }
+            }
+            proc_macro2::TokenTree::Group(g) => captured(g.stream(), is_var, vars),
+            _ => {}
+        }
+    }
+}
+
+/// Rewrites an inline handler such as `move || count.set(1)` as the equivalent
+/// `callback!(count, {...})`, capturing every component variable the closure uses.
+pub fn closure_callback(expr: &str, is_var: impl Fn(&str) -> bool) -> Option<String> {
+    let closure: syn::ExprClosure = syn::parse_str(expr).ok()?;
+    let body = &closure.body;
+    let mut vars = vec![];
+    captured(quote! {#body}, &is_var, &mut vars);
+    let block = if let syn::Expr::Block(_) = body.as_ref() {
+        quote! {#body}.to_string()
+    } else {
+        format!("{{{};}}", quote! {#body})
+    };
+    let mut args: String = vars.iter().map(|v| format!("{}, ", v)).collect();
+    args.push_str(&block);
+    Some(format!("callback!({})", args))
+}
+
 fn component_rsx(name: &str) -> String {
     format!("{}_rsx", name.trim().to_lowercase())
 }
@@ -730,6 +821,23 @@ struct CompParser {
     res_types: HashMap<String, TokenStream>,
     selectors: HashSet<String>,
     events: Vec<(TokenStream, TokenStream)>,
+    frames: Vec<Frame>,
+    dynamic_attrs: Vec<usize>,
+    sites: usize,
+}
+
+/// An element being generated, tracking which of its parts are dynamic.
+/// It is `fixed` while its children don't depend on control flow.
+struct Frame {
+    attrs: Vec<usize>,
+    texts: Vec<usize>,
+    children: usize,
+    fixed: bool,
+}
+
+fn index_list(indices: &[usize]) -> String {
+    let list: Vec<String> = indices.iter().map(|n| format!("{}usize", n)).collect();
+    format!("&[{}]", list.join(", "))
 }
 
 pub fn collect_tag(chrs: &mut Chars) -> String {
@@ -750,15 +858,43 @@ pub fn collect_tag(chrs: &mut Chars) -> String {
 }
 
 impl CompParser {
+    /// Counts a child of the element being generated.
+    fn child(&mut self, dynamic_text: bool) {
+        if let Some(frame) = self.frames.last_mut() {
+            if dynamic_text {
+                frame.texts.push(frame.children);
+            }
+            frame.children += 1;
+        }
+    }
+    /// Marks the children of the element being generated as depending on control flow.
+    fn unfix(&mut self) {
+        if let Some(frame) = self.frames.last_mut() {
+            frame.fixed = false;
+        }
+    }
+    /// Closes the `element!` call of `frame`, marking its holes when it's fixed.
+    fn close(&mut self, frame: Option<Frame>) -> String {
+        match frame {
+            Some(frame) if frame.fixed => {
+                let site = format!("{}:{}", self.lower_comp, self.sites);
+                self.sites += 1;
+                format!("_children}}).with_holes(\"{}\", {}, {}));", site, index_list(&frame.attrs), index_list(&frame.texts))
+            }
+            _ => "_children}));".to_string(),
+        }
+    }
     fn parse_rsx(&mut self, content: &str) -> String {
         let mut view = String::new();
         let children = self.process(content);
         view.push_str(&format!("Rsx::Component(Comp {{children: {}}})", children));
         view
     }
+    /// The attribute tuples of `attr_str`, recording which are dynamic in `dynamic_attrs`.
     fn attr_tuple(&mut self, attr_str: &str) -> String {
         let mut s = String::new();
         let mut chrs = attr_str.chars();
+        let mut n = 0;
         loop {
             let mut name = String::new();
             loop {
@@ -787,6 +923,8 @@ impl CompParser {
                                 at.push(d);
                             }
                             if at.starts_with("onclick") {
+                                self.dynamic_attrs.push(n);
+                                n += 1;
                                 let (_, second) = at.split_once('(').unwrap();
                                 let mut schars = second.chars();
                                 let expr = custom_get_expr(&mut schars, 1, 0);
@@ -830,7 +968,7 @@ impl CompParser {
                                         if let Some((vty, m)) = self.local.get(&var.to_string()) {
                                             s.push_str(&format!("{} ", m));
                                             args.push(quote! {
-                                                let mut #var = _scope[#n].rf.borrow_mut();
+                                                let mut #var = _scope[#n].borrow_mut();
                                                 let #var = #var.downcast_mut::<#vty>().expect("problem restoring variable");
                                             });
                                         } else {
@@ -840,7 +978,7 @@ impl CompParser {
                                             let raw_var = format_ident!("_{}", var);
                                             rargs.push(quote! {
                                                 let #raw_var = {
-                                                    let mut var = _scope[#n].rf.borrow_mut();
+                                                    let mut var = _scope[#n].borrow_mut();
                                                     let v = var.downcast_mut::<#vty>().expect("problem restoring refvec");
                                                     v.value().inner()[_scope[#n].index.expect("problem getting reference index")].clone()
                                                 };
@@ -925,8 +1063,11 @@ impl CompParser {
             if nws.starts_with('"') {
                 if let Some(attr) = collect_str(&mut chrs) {
                     s.push_str(&format!("(\"{name}\".to_string(), \"{attr}\".to_string()),"));
+                    n += 1;
                 }
             } else {
+                self.dynamic_attrs.push(n);
+                n += 1;
                 let mut u = String::new();
                 if nws.starts_with("@if") {
                     u.push_str("if ");
@@ -993,7 +1134,7 @@ impl CompParser {
     fn text(&mut self, c: char, tags: &mut Vec<String>, view: &mut String, chars: &mut Chars) {
         let mut txt = String::new();
         txt.push(c);
-        let mut vw = String::new();
+        let mut end = None;
         while let Some(d) = chars.next() {
             match d {
                 '"' => {
@@ -1002,16 +1143,8 @@ impl CompParser {
                 '\\' => {
                     txt.push_str("\\\\");
                 }
-                '@' => {
-                    self.at(&mut vw, chars);
-                    break;
-                }
-                '}' => {
-                    vw.push('}');
-                    break;
-                }
-                '<' => {
-                    self.tag(tags, &mut vw, chars);
+                '@' | '}' | '<' => {
+                    end = Some(d);
                     break;
                 }
                 _ => txt.push(d),
@@ -1020,8 +1153,14 @@ impl CompParser {
         let trimmed = txt.trim();
         if !trimmed.is_empty() {
             view.push_str(&format!("_children.push(Rsx::new_text(\"{}\".to_string()));", trimmed));
+            self.child(false);
+        }
+        match end {
+            Some('@') => self.at(view, chars),
+            Some('}') => view.push('}'),
+            Some('<') => self.tag(tags, view, chars),
+            _ => {}
         }
-        view.push_str(&vw);
     }
     fn tag(&mut self, tags: &mut Vec<String>, view: &mut String, chars: &mut Chars) {
         let mut inner = String::new();
@@ -1032,10 +1171,20 @@ impl CompParser {
                 inner.push(c);
                 inner.push_str(&collect(chars, '>'));
                 inner.pop();
+                let (inner, props) = match inner.split_once(char::is_whitespace) {
+                    Some((name, args)) => (name.to_string(), prop_list(args)),
+                    None => (inner.trim_end_matches('/').to_string(), String::new()),
+                };
+                self.unfix();
                 let comp_rsx = component_rsx(&inner);
                 let comp_rsx = format_ident!("{}", comp_rsx);
                 let comp_num = self.comp_rsx_ids.len();
                 let inner = format_ident!("{}", inner.trim());
+                let props: TokenStream = if props.is_empty() {
+                    quote! {anansi_aux::EmptyProp {}}
+                } else {
+                    format!("<{} as anansi_aux::components::Component>::Properties::new(){}.build()", inner, props).parse().expect("problem parsing props")
+                };
                 if self.in_resource {
                     self.comp_rsx_ids.push(comp_rsx.clone());
                 } else {
@@ -1050,7 +1199,8 @@ impl CompParser {
                             if let Some(c) = #comp_rsx {
                                 match c {Rsx::Component(comp) => _children.append(&mut comp.children.clone()), _ => unimplemented!()};
                             } else {
-                                let _r = #inner::restart(anansi_aux::EmptyProp {});
+                                let _props = #props;
+                                let _r = anansi_aux::preload::take_preloaded(stringify!(#inner), &_props).unwrap_or_else(|| #inner::restart(_props));
                                 match _r {Rsx::Component(ref comp) => _children.append(&mut comp.children.clone()), _ => unimplemented!()};
                                 *#comp_rsx = Some(_r);
                             }
@@ -1068,6 +1218,7 @@ impl CompParser {
         let collected = collect_tag(chars);
         inner.push_str(&collected);
         let mut attrs = String::new();
+        self.dynamic_attrs.clear();
         let name = if let Some((name, av)) = inner.trim().split_once(' ') {
             attrs = self.attr_tuple(av);
             name.to_string()
@@ -1079,7 +1230,9 @@ impl CompParser {
             if tags.is_empty() {
                 self.in_element = false;
             }
-            view.push_str("_children}));");
+            let frame = self.frames.pop();
+            let close = self.close(frame);
+            view.push_str(&close);
             return;
         } else {
             if !inner.ends_with('/') {
@@ -1092,8 +1245,13 @@ impl CompParser {
         }
         let name = name.trim().to_uppercase();
         view.push_str(&format!("_children.push(element!(\"{name}\", attributes![{attrs}], {{let mut _children = vec![];"));
+        self.child(false);
+        let frame = Frame {attrs: std::mem::take(&mut self.dynamic_attrs), texts: vec![], children: 0, fixed: true};
         if inner.ends_with('/') {
-            view.push_str("_children}));");
+            let close = self.close(Some(frame));
+            view.push_str(&close);
+        } else {
+            self.frames.push(frame);
         }
     }
     fn var(&mut self, chars: &mut Chars, s: &mut String) {
@@ -1115,6 +1273,9 @@ impl CompParser {
         let mut s = String::new();
         let mut extra = String::new();
         if let Some(c) = chars.next() {
+            if matches!(c, '(' | '{' | ':') {
+                self.unfix();
+            }
             match c {
                 '(' => {
                     self.var(chars, &mut s);
@@ -1150,6 +1311,11 @@ impl CompParser {
         }
         let keyword = s.clone();
         let mut find_brace = true;
+        if !s.starts_with('{') && !matches!(s.trim(), "if" | "for" | "loop" | "while" | "build" | "unescape" | "href" | "keyed" | "resource" | "onclick") {
+            self.child(true);
+        } else {
+            self.unfix();
+        }
         match s.trim() {
             "if" => {}
             "for" => {}
@@ -1266,6 +1432,7 @@ impl CompParser {
             }
             "onclick" => {
                 let expr = custom_get_expr(chars, 0, 0);
+                let expr = closure_callback(&expr, |v| self.local.get(v).is_some() || self.rchildren.contains_key(v)).unwrap_or(expr);
                 let mut rchildren = vec![];
                 if !expr.contains("callback!") {
                     view.push_str(&format!("(\"on:click\".to_string(), format!(\"{}_{}[", self.lower_comp, expr));
@@ -1577,7 +1744,7 @@ document.addEventListener('click', (e) => {
       if (onclick) {
         let rid = attributes.getNamedItem('rid');
         if (rid) {
-          let called = mod.recall(rid.value);
+          let called = mod.recall_event(rid.value, e);
           if (called) {
             return;
           }
@@ -1593,7 +1760,7 @@ document.addEventListener('click', (e) => {
   }
   if (callback) {
     if (mod) {
-      mod.call(callback, id);
+      mod.call_event(callback, id, e);
     } else {
       import('/static/pkg/".to_string();
     js.push_str(&under_wasm);
@@ -1684,3 +1851,36 @@ crate-type = [\"cdylib\"]", VERSION, comps).into_bytes());
     wasm_path.push("lib.rs");
     fs::write(wasm_path, format!("anansi_aux::start!({});", under_comps)).unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inline_closure() {
+        let is_var = |v: &str| v == "count" || v == "step";
+        let cb = closure_callback("move || count.set(count.get() + step)", is_var).unwrap();
+        let args: CallbackArgs = syn::parse_str(cb.strip_prefix("callback!(").unwrap().strip_suffix(')').unwrap()).unwrap();
+        assert_eq!(args.args.iter().map(|a| a.to_string()).collect::<Vec<_>>(), vec!["count", "step"]);
+        assert!(closure_callback("increment", is_var).is_none());
+    }
+
+    #[test]
+    fn inline_props() {
+        assert_eq!(prop_list(r#"name="x y" count={n + 1} @title /"#), r#".name("x y").count(n + 1).title(title)"#);
+        assert_eq!(prop_list("size=@s"), ".size(s)");
+        assert_eq!(prop_list(""), "");
+    }
+
+    #[test]
+    fn marked_holes() {
+        let mut parser = CompParser {start: vec![], events: vec![], callbacks: vec![], rchildren: HashMap::new(), refs: HashMap::new(), in_resource: false, lower_comp: "row".to_string(), in_block: false, in_element: false, res_types: HashMap::new(), resource_calls: vec![], res_fn: vec![], selectors: HashSet::new(), comp_rsx_ids: vec![], restart_comp_rsx_ids: vec![], local: Local::new(), frames: vec![], dynamic_attrs: vec![], sites: 0};
+        let view = parser.process(r#"<div class="row"><p>Hi</p><p title=@title>@name</p></div>"#);
+        assert!(view.contains(r#"Rsx::new_text("Hi".to_string()));_children}).with_holes("row:0", &[], &[]));"#), "{}", view);
+        assert!(view.contains(r#"html_escape(&format!("{}", name))));_children}).with_holes("row:1", &[0usize], &[0usize]));"#), "{}", view);
+        assert!(view.ends_with(r#"_children}).with_holes("row:2", &[], &[]));_children}"#), "{}", view);
+        let view = parser.process(r#"<ul>@for n in 0..3 {<li>@n</li>}</ul>"#);
+        assert!(view.contains(r#"with_holes("row:3", &[], &[0usize])"#), "{}", view);
+        assert!(view.ends_with("}_children}));_children}"), "{}", view);
+    }
+}
@@ -12,7 +12,7 @@ use which::which;
 
 mod components;
 
-use components::{collect_nws, collect_tag, custom_get_expr, get_expr, check_components, init_components, Local, CallbackArgs};
+use components::{collect_nws, collect_tag, custom_get_expr, get_expr, prop_list, check_components, init_components, Local, CallbackArgs};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -632,27 +632,7 @@ impl Parser {
         } else {
             let args = collect(chars, '>');
             let (args, _) = args.rsplit_once('/').unwrap();
-            let mut chrs = args.chars();
-            let mut list = String::new();
-            loop {
-                let arg = collect(&mut chrs, ' ');
-                if arg.is_empty() {
-                    break;
-                }
-                if arg.starts_with('@') {
-                    let (_, a) = arg.split_once('@').unwrap();
-                    list.push_str(&format!(".{}({0})", a));
-                } else {
-                    let (n, a) = arg.split_once('=').unwrap();
-                    let ar = if a.trim().starts_with('@') {
-                        let (_, a) = a.split_once('@').unwrap();
-                        a.to_string()
-                    } else {
-                        a.to_string()
-                    };
-                    list.push_str(&format!(".{}({})", n, ar));
-                }
-            }
+            let list = prop_list(args);
             let prop = if !list.is_empty() {
                 format!("<{} as anansi_aux::components::Component>::Properties::new(){}.build(), &mut _p", name, list)
             } else {
@@ -1308,6 +1308,7 @@ pub fn start(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
                 }
             }
             anansi_aux::setup(callbacks);
+            anansi_aux::hydrate::hydrate_islands();
         }
     };
     q.into()